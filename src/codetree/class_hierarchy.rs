@@ -0,0 +1,92 @@
+use std::collections::{HashMap, HashSet};
+
+use phpanalyzer::symboldata::class::ClassType;
+use phpanalyzer::symboldata::SymbolData;
+use phpanalyzer::symbols::FullyQualifiedName;
+
+///
+/// The inheritance edges between the classes, interfaces and traits of a `CodeTree`, in both
+/// directions. `SymbolData` only knows the parents of a class, this makes it cheap to find the
+/// children as well.
+///
+pub struct ClassHierarchy {
+    parents: HashMap<FullyQualifiedName, Vec<FullyQualifiedName>>,
+    children: HashMap<FullyQualifiedName, Vec<FullyQualifiedName>>,
+}
+
+impl ClassHierarchy {
+    pub fn new() -> Self {
+        Self {
+            parents: HashMap::new(),
+            children: HashMap::new(),
+        }
+    }
+
+    pub fn from_symbol_data(symbol_data: &SymbolData) -> Self {
+        let mut hierarchy = Self::new();
+
+        for (fq_name, class) in symbol_data.classes.read().unwrap().iter() {
+            let class = class.read().unwrap();
+            let mut parents = vec![];
+            match &*class {
+                ClassType::Class(c) => {
+                    if let Some(base) = &c.base_class_name {
+                        parents.push(base.fq_name.clone());
+                    }
+                    parents.extend(c.interfaces.iter().map(|i| i.fq_name.clone()));
+                    parents.extend(c.traits.iter().map(|t| t.fq_name.clone()));
+                }
+                ClassType::Interface(i) => {
+                    if let Some(bases) = &i.base_interface_names {
+                        parents.extend(bases.iter().map(|b| b.fq_name.clone()));
+                    }
+                }
+                ClassType::Trait(t) => {
+                    parents.extend(t.traits.iter().map(|t| t.fq_name.clone()));
+                }
+                ClassType::None => continue,
+            }
+            for parent in &parents {
+                hierarchy
+                    .children
+                    .entry(parent.clone())
+                    .or_insert_with(Vec::new)
+                    .push(fq_name.clone());
+            }
+            hierarchy.parents.insert(fq_name.clone(), parents);
+        }
+        hierarchy
+    }
+
+    ///
+    /// The base class, interfaces and traits `fq_name` directly inherits from
+    ///
+    pub fn get_parents(&self, fq_name: &FullyQualifiedName) -> Vec<FullyQualifiedName> {
+        self.parents.get(fq_name).cloned().unwrap_or_default()
+    }
+
+    ///
+    /// The classes, interfaces and traits directly inheriting from `fq_name`
+    ///
+    pub fn get_children(&self, fq_name: &FullyQualifiedName) -> Vec<FullyQualifiedName> {
+        self.children.get(fq_name).cloned().unwrap_or_default()
+    }
+
+    ///
+    /// Everything inheriting from `fq_name`, directly or indirectly, not including `fq_name`
+    ///
+    pub fn get_descendants(&self, fq_name: &FullyQualifiedName) -> Vec<FullyQualifiedName> {
+        let mut seen = HashSet::new();
+        seen.insert(fq_name.clone());
+        let mut descendants = vec![];
+        let mut queue = self.get_children(fq_name);
+        while let Some(current) = queue.pop() {
+            if !seen.insert(current.clone()) {
+                continue;
+            }
+            queue.extend(self.get_children(&current));
+            descendants.push(current);
+        }
+        descendants
+    }
+}
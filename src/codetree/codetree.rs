@@ -1,5 +1,14 @@
 use crate::codetree::file_scanner::FileScanner;
+use crate::codetree::references::{ReferenceIndex, ReferenceKey};
+use crate::codetree::cancellation::CancellationToken;
+use crate::codetree::class_hierarchy::ClassHierarchy;
+use crate::codetree::file_symbols::{
+    add_unresolved_keys, copy_symbol_data, expand_to_descendants, FileSymbols,
+};
+use crate::codetree::overlays::Overlays;
+use crate::codetree::symbol_index::SymbolIndex;
 use crate::issues::OutputEmitter;
+use crate::phpls::members::get_class_position;
 use crate::phpparser::phpfile::PHPFile;
 use phpanalyzer::analysis::state::AnalysisState;
 use phpanalyzer::issue::{Issue, IssueEmitter};
@@ -129,12 +138,28 @@ impl IssueEmitter for CaptureEmitter {
         Some(format!("Found {} issues", cnt))
     }
 }
+///
+/// The results of one complete analysis of a `CodeTree`. They are replaced together, so
+/// requests answered while a new analysis is running see a consistent picture of the last one.
+///
+pub struct AnalysisSnapshot {
+    pub symbol_data: Arc<SymbolData>,
+    pub symbol_index: Arc<SymbolIndex>,
+    pub class_hierarchy: Arc<ClassHierarchy>,
+    pub references: Arc<ReferenceIndex>,
+    pub issues: Vec<Issue>,
+}
+
 pub struct CodeTree {
     pub root_folder: PathBuf,
 
     pub files: Arc<RwLock<Vec<Arc<PHPFile>>>>,
-    pub symbol_data: Arc<RwLock<Option<Arc<SymbolData>>>>,
-    pub issues: Arc<RwLock<Vec<Issue>>>,
+    pub snapshot: Arc<RwLock<Option<Arc<AnalysisSnapshot>>>>,
+    pub overlays: Arc<Overlays>,
+}
+
+fn cancelled_error() -> Error {
+    Error::new(ErrorKind::Interrupted, "Analysis was cancelled")
 }
 
 pub struct Worker<T> {
@@ -147,8 +172,8 @@ impl CodeTree {
         CodeTree {
             root_folder: root_folder,
             files: Arc::new(RwLock::new(vec![])),
-            symbol_data: Arc::new(RwLock::new(None)),
-            issues: Arc::new(RwLock::new(vec![])),
+            snapshot: Arc::new(RwLock::new(None)),
+            overlays: Arc::new(Overlays::new()),
         }
     }
 
@@ -160,19 +185,22 @@ impl CodeTree {
         Some(CodeTree {
             root_folder: PathBuf::from(url.path()),
             files: Arc::new(RwLock::new(vec![])),
-            symbol_data: Arc::new(RwLock::new(None)),
-            issues: Arc::new(RwLock::new(vec![])),
+            snapshot: Arc::new(RwLock::new(None)),
+            overlays: Arc::new(Overlays::new()),
         })
     }
 
     pub fn get_issues_for_uri(&self, uri: &Url) -> Vec<Issue> {
         // FIXME this is probably not an ideal search-algorithm
-        let issues = self.issues.read().unwrap();
-        if uri.scheme() != "file" {
-            return vec![];
-        }
-        let s = uri.path();
-        let uri_as_osstring: OsString = s.into();
+        let snapshot = match self.get_snapshot() {
+            Some(snapshot) => snapshot,
+            None => return vec![],
+        };
+        let issues = &snapshot.issues;
+        let uri_as_osstring: OsString = match uri.to_file_path() {
+            Ok(path) => path.into_os_string(),
+            Err(_) => return vec![],
+        };
         eprintln!(
             "Looking for issues matching {:?} out of {} total",
             uri_as_osstring,
@@ -197,7 +225,15 @@ impl CodeTree {
             eprint!("\x1b[1G{:-3}% {}    \x1b[1G", percent, ident);
         })));
 
-        let res = self.internal_traverse(thread_count, symbol_data, emitter.clone(), status);
+        let references = Arc::new(ReferenceIndex::new());
+        let res = self.internal_traverse(
+            thread_count,
+            symbol_data,
+            references,
+            emitter.clone(),
+            status,
+            &CancellationToken::new(),
+        );
         if !output_issues {
             use itertools::Itertools;
             eprintln!("\nSummary of issues:\n");
@@ -222,25 +258,140 @@ impl CodeTree {
         &self,
         thread_count: usize,
         status: Arc<dyn GenericProgress + Send + Sync>,
+        cancel: &CancellationToken,
     ) -> std::io::Result<()> {
         let emitter = Arc::new(CaptureEmitter::new());
         let symbol_data = Arc::new(SymbolData::new());
+        let references = Arc::new(ReferenceIndex::new());
+
+        self.internal_traverse(
+            thread_count,
+            symbol_data.clone(),
+            references.clone(),
+            emitter.clone(),
+            status,
+            cancel,
+        )?;
 
-        self.internal_traverse(thread_count, symbol_data.clone(), emitter.clone(), status)?;
-
-        let mut sd_handle = self.symbol_data.write().unwrap();
-        (*sd_handle) = Some(symbol_data);
-        let mut issues_handle = self.issues.write().unwrap();
-        (*issues_handle) = emitter.get_issues();
+        let snapshot = AnalysisSnapshot {
+            symbol_index: Arc::new(SymbolIndex::from_symbol_data(&symbol_data)),
+            class_hierarchy: Arc::new(ClassHierarchy::from_symbol_data(&symbol_data)),
+            symbol_data,
+            references,
+            issues: emitter.get_issues(),
+        };
+        *(self.snapshot.write().unwrap()) = Some(Arc::new(snapshot));
         Ok(())
     }
 
+    ///
+    /// Analyze a changed file against the existing symbol data, instead of the whole tree. The
+    /// old declarations of the file are retracted before it is run through all three passes,
+    /// and files depending on declarations with a changed signature get their third pass
+    /// redone. Returns every file which got new issues.
+    ///
+    /// The work is done on copies of the symbol data and references, which replace the
+    /// snapshot once complete, so requests keep seeing a consistent state meanwhile.
+    ///
+    pub fn reanalyze_file(&self, file: &PathBuf) -> std::io::Result<Vec<PathBuf>> {
+        let snapshot = self.get_snapshot().ok_or_else(|| {
+            Error::new(ErrorKind::Other, "The tree has not been analyzed yet")
+        })?;
+        let symbol_data = Arc::new(copy_symbol_data(&snapshot.symbol_data));
+        let references = Arc::new(snapshot.references.duplicate());
+        let old_hierarchy = snapshot.class_hierarchy.clone();
+        let mut symbol_index = snapshot.symbol_index.clone();
+        let mut class_hierarchy = old_hierarchy.clone();
+        let file_name = file.as_os_str().to_os_string();
+        let emitter = CaptureEmitter::new();
+
+        let old_symbols = FileSymbols::from_symbol_data(&symbol_data, &file_name);
+        old_symbols.retract(&symbol_data);
+        references.remove_file(&file_name);
+
+        // A deleted file has no new declarations
+        let php_file = self.analyze_file(file).map(Arc::new);
+        if let Some(php_file) = &php_file {
+            php_file.analyze_first_pass(&emitter, symbol_data.clone());
+            php_file.analyze_second_pass(&emitter, symbol_data.clone());
+        }
+        let new_symbols = FileSymbols::from_symbol_data(&symbol_data, &file_name);
+        let changed = old_symbols.changed_keys(&new_symbols);
+
+        let mut analyzed = vec![file.clone()];
+        if !changed.is_empty() {
+            class_hierarchy = Arc::new(ClassHierarchy::from_symbol_data(&symbol_data));
+            symbol_index = Arc::new(SymbolIndex::from_symbol_data(&symbol_data));
+            let mut affected = expand_to_descendants(&changed, &old_hierarchy);
+            affected.extend(expand_to_descendants(&changed, &class_hierarchy));
+            let affected = add_unresolved_keys(&affected);
+
+            let mut dependents = references.get_referencing_files(&affected);
+            // Subclasses are checked against what they inherit
+            for key in &affected {
+                if let ReferenceKey::Class(fq_name) = key {
+                    if let Some(position) = get_class_position(&symbol_data, fq_name) {
+                        dependents.insert(position.uri);
+                    }
+                }
+            }
+            dependents.remove(&file_name);
+            eprintln!(
+                "{} declarations changed in {:?}, rechecking {} files",
+                changed.len(),
+                file,
+                dependents.len()
+            );
+            analyzed.extend(dependents.into_iter().map(PathBuf::from));
+        }
+
+        let mut new_files = vec![];
+        if let Some(php_file) = php_file {
+            php_file.analyze_third_pass(&emitter, symbol_data.clone(), Some(&references));
+            new_files.push(php_file);
+        }
+        for dependent in &analyzed[1..] {
+            if let Some(php_file) = self.analyze_file(dependent) {
+                let php_file = Arc::new(php_file);
+                references.remove_file(&dependent.as_os_str().to_os_string());
+                php_file.analyze_third_pass(&emitter, symbol_data.clone(), Some(&references));
+                new_files.push(php_file);
+            }
+        }
+
+        {
+            let mut files = self.files.write().unwrap();
+            files.retain(|f| !analyzed.contains(&f.fq_file_name));
+            files.extend(new_files);
+        }
+
+        let analyzed_names: Vec<OsString> =
+            analyzed.iter().map(|f| f.as_os_str().to_os_string()).collect();
+        let mut issues: Vec<Issue> = snapshot
+            .issues
+            .iter()
+            .filter(|i| !analyzed_names.contains(&i.issue_file()))
+            .cloned()
+            .collect();
+        issues.extend(emitter.get_issues());
+        *(self.snapshot.write().unwrap()) = Some(Arc::new(AnalysisSnapshot {
+            symbol_data,
+            symbol_index,
+            class_hierarchy,
+            references,
+            issues,
+        }));
+        Ok(analyzed)
+    }
+
     pub fn internal_traverse(
         &self,
         thread_count: usize,
         symbol_data: Arc<SymbolData>,
+        references: Arc<ReferenceIndex>,
         emitter: Arc<dyn IssueEmitter + Send + Sync>,
         status: Arc<dyn GenericProgress + Send + Sync>,
+        cancel: &CancellationToken,
     ) -> std::io::Result<Arc<SymbolData>> {
         if thread_count == 0 {
             eprintln!("Zero thread count");
@@ -251,6 +402,7 @@ impl CodeTree {
             return Err(Error::new(ErrorKind::Other, "Thread count is max 64"));
         }
         let mut new_files = vec![];
+        let overlays = self.overlays.clone();
         let base_lib = "/Users/bear/src/cerum/src/lib";
         let base_libs = "/Users/bear/src/cerum/src/libs";
         let base_sec = "/Users/bear/src/cerum/src/sec";
@@ -262,7 +414,8 @@ impl CodeTree {
                 return;
             }
             if file.is_file() {
-                new_files.push(Arc::new(PHPFile::new(file)));
+                let overlay = overlays.get(&file).map(|o| o.contents);
+                new_files.push(Arc::new(PHPFile::new_with_overlay(file, overlay)));
             }
         })?;
 
@@ -278,6 +431,7 @@ impl CodeTree {
             let status = status.clone();
             self.traverse_list_with_threads(
                 thread_count,
+                cancel,
                 Arc::new(move |file| {
                     file.analyze_first_pass(&*thread_emitter, thread_symbol.clone());
                     status.progress("pass 1/3", thread_emitter.get_status());
@@ -288,7 +442,7 @@ impl CodeTree {
             let status = status.clone();
             let thread_symbol = symbol_data.clone();
 
-            self.traverse_list_in_thread(Box::new(move |file| {
+            self.traverse_list_in_thread(cancel, Box::new(move |file| {
                 file.analyze_first_pass(&*thread_emitter, thread_symbol.clone());
                 status.progress("pass 1/3", thread_emitter.get_status());
             }))?;
@@ -301,6 +455,7 @@ impl CodeTree {
 
             self.traverse_list_with_threads(
                 thread_count,
+                cancel,
                 Arc::new(move |file| {
                     file.analyze_second_pass(&*thread_emitter, thread_symbol.clone());
                     status.progress("pass 2/3", thread_emitter.get_status());
@@ -310,7 +465,7 @@ impl CodeTree {
             let status = status.clone();
             let thread_emitter = emitter.clone();
             let thread_symbol = symbol_data.clone();
-            self.traverse_list_in_thread(Box::new(move |file| {
+            self.traverse_list_in_thread(cancel, Box::new(move |file| {
                 file.analyze_second_pass(&*thread_emitter, thread_symbol.clone());
                 status.progress("pass 2/3", thread_emitter.get_status());
             }))?;
@@ -320,11 +475,17 @@ impl CodeTree {
             let status = status.clone();
             let thread_emitter = emitter.clone();
             let thread_symbol = symbol_data.clone();
+            let thread_references = references.clone();
 
             self.traverse_list_with_threads(
                 thread_count,
+                cancel,
                 Arc::new(move |file| {
-                    file.analyze_third_pass(&*thread_emitter, thread_symbol.clone());
+                    file.analyze_third_pass(
+                        &*thread_emitter,
+                        thread_symbol.clone(),
+                        Some(&thread_references),
+                    );
                     status.progress("pass 3/3", thread_emitter.get_status());
                 }),
             )?;
@@ -332,8 +493,13 @@ impl CodeTree {
             let status = status.clone();
             let thread_emitter = emitter.clone();
             let thread_symbol = symbol_data.clone();
-            self.traverse_list_in_thread(Box::new(move |file| {
-                file.analyze_third_pass(&*thread_emitter, thread_symbol.clone());
+            let thread_references = references.clone();
+            self.traverse_list_in_thread(cancel, Box::new(move |file| {
+                file.analyze_third_pass(
+                    &*thread_emitter,
+                    thread_symbol.clone(),
+                    Some(&thread_references),
+                );
                 status.progress("pass 3/3", thread_emitter.get_status());
             }))?;
         }
@@ -361,6 +527,7 @@ impl CodeTree {
 
     pub fn traverse_list_in_thread(
         &self,
+        cancel: &CancellationToken,
         callback: Box<dyn Fn(Arc<PHPFile>) + Send + Sync>,
     ) -> std::io::Result<()> {
         let reader = match self.files.read() {
@@ -369,6 +536,9 @@ impl CodeTree {
         };
 
         for file in &*reader {
+            if cancel.is_cancelled() {
+                return Err(cancelled_error());
+            }
             callback(file.clone());
         }
         Ok(())
@@ -432,16 +602,20 @@ impl CodeTree {
     pub fn traverse_list_with_threads(
         &self,
         thread_count: usize,
+        cancel: &CancellationToken,
         callback: Arc<dyn Fn(Arc<PHPFile>) + Send + Sync>,
     ) -> std::io::Result<()> {
         let mut workers: Vec<Worker<Arc<PHPFile>>> = vec![];
 
         for _ in 0..thread_count {
             let iter_callback = callback.clone();
+            let thread_cancel = cancel.clone();
             let (sender, receiver) = channel::<Arc<PHPFile>>();
             let handle = thread::spawn(move || {
                 loop {
                     match receiver.recv() {
+                        // Drain what's queued without doing the work
+                        Ok(_) if thread_cancel.is_cancelled() => (),
                         Ok(file) => {
                             // eprintln!("Should analyze file {:?}", file);
                             iter_callback(file);
@@ -463,6 +637,9 @@ impl CodeTree {
         let mut cnt: usize = 0;
 
         for file in &*reader {
+            if cancel.is_cancelled() {
+                break;
+            }
             workers[cnt % workers.len()]
                 .sender
                 .send(file.clone())
@@ -477,6 +654,9 @@ impl CodeTree {
                 Err(err) => return Err(Error::new(ErrorKind::Other, format!("Crap {:?}", err))),
             };
         }
+        if cancel.is_cancelled() {
+            return Err(cancelled_error());
+        }
         eprintln!("Completed.");
         Ok(())
     }
@@ -508,6 +688,10 @@ impl CodeTree {
     }
 
     pub fn analyze_file(&self, file: &PathBuf) -> Option<PHPFile> {
+        if let Some(overlay) = self.overlays.get(file) {
+            // Open in the editor, and maybe not saved yet
+            return Some(PHPFile::new_with_overlay(file.clone(), Some(overlay.contents)));
+        }
         if file.is_file() {
             Some(PHPFile::new(file.clone()))
         } else {
@@ -516,10 +700,7 @@ impl CodeTree {
     }
 
     pub fn analyze_file_uri(&self, file: &Url) -> Option<PHPFile> {
-        if file.scheme() != "file" {
-            return None;
-        }
-        self.analyze_file(&PathBuf::from(file.path()))
+        self.analyze_file(&file.to_file_path().ok()?)
     }
 
     pub fn with_analyzed_file<CB>(file: &PathBuf, cb: CB)
@@ -530,9 +711,34 @@ impl CodeTree {
         cb(php_file);
     }
 
+    ///
+    /// Get the results of the last complete analysis, if there has been one
+    ///
+    pub(crate) fn get_snapshot(&self) -> Option<Arc<AnalysisSnapshot>> {
+        let snapshot = self.snapshot.read().unwrap();
+
+        snapshot.clone()
+    }
+
     pub(crate) fn get_symbol_data(&self) -> Option<Arc<SymbolData>> {
-        let symbol_data = self.symbol_data.read().unwrap();
+        Some(self.get_snapshot()?.symbol_data.clone())
+    }
+
+    pub(crate) fn get_references(&self) -> Arc<ReferenceIndex> {
+        self.get_snapshot()
+            .map(|s| s.references.clone())
+            .unwrap_or_else(|| Arc::new(ReferenceIndex::new()))
+    }
+
+    pub(crate) fn get_symbol_index(&self) -> Arc<SymbolIndex> {
+        self.get_snapshot()
+            .map(|s| s.symbol_index.clone())
+            .unwrap_or_else(|| Arc::new(SymbolIndex::new()))
+    }
 
-        symbol_data.clone()
+    pub(crate) fn get_class_hierarchy(&self) -> Arc<ClassHierarchy> {
+        self.get_snapshot()
+            .map(|s| s.class_hierarchy.clone())
+            .unwrap_or_else(|| Arc::new(ClassHierarchy::new()))
     }
 }
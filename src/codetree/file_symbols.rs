@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+
+use phpanalyzer::symboldata::class::ClassType;
+use phpanalyzer::symboldata::SymbolData;
+
+use crate::codetree::class_hierarchy::ClassHierarchy;
+use crate::codetree::references::ReferenceKey;
+use crate::phpls::members::{get_declared_members, get_direct_parents, get_method, MemberKind};
+use crate::phpls::signatures::{format_function_signature, format_method_signature};
+
+///
+/// The symbols one file declares, with a description of the signature of each. Comparing the
+/// symbols of a file before and after it changed tells which declarations other files might
+/// see differently.
+///
+#[derive(Debug, Default)]
+pub struct FileSymbols {
+    signatures: HashMap<ReferenceKey, String>,
+}
+
+impl FileSymbols {
+    pub fn from_symbol_data(symbol_data: &SymbolData, file: &OsString) -> Self {
+        let mut signatures = HashMap::new();
+
+        for (fq_name, class) in symbol_data.classes.read().unwrap().iter() {
+            let class = class.read().unwrap();
+            let (keyword, position) = match &*class {
+                ClassType::Class(c) => ("class", &c.position),
+                ClassType::Interface(i) => ("interface", &i.position),
+                ClassType::Trait(t) => ("trait", &t.position),
+                ClassType::None => continue,
+            };
+            if &position.uri != file {
+                continue;
+            }
+            let parents: Vec<_> = get_direct_parents(symbol_data, fq_name)
+                .iter()
+                .map(|p| p.fq_name.to_string())
+                .collect();
+            signatures.insert(
+                ReferenceKey::Class(fq_name.clone()),
+                format!("{} {}", keyword, parents.join(", ")),
+            );
+
+            for member in get_declared_members(&class, fq_name) {
+                let (key, signature) = match member.kind {
+                    MemberKind::Method => {
+                        let signature = get_method(symbol_data, fq_name, &member.name)
+                            .map(|m| format_method_signature(&m.read().unwrap()))
+                            .unwrap_or_default();
+                        (ReferenceKey::method(fq_name, &member.name), signature)
+                    }
+                    MemberKind::Property => (
+                        ReferenceKey::property(fq_name, &member.name),
+                        format!("{:?}", member.type_description),
+                    ),
+                    MemberKind::Constant => (
+                        ReferenceKey::class_constant(fq_name, &member.name),
+                        format!("{:?}", member.type_description),
+                    ),
+                };
+                signatures.insert(
+                    key,
+                    format!(
+                        "{:?} {} {:?} {}",
+                        member.visibility, member.is_static, member.is_abstract, signature
+                    ),
+                );
+            }
+        }
+
+        for (fq_name, function) in symbol_data.functions.read().unwrap().iter() {
+            let function = function.read().unwrap();
+            if &function.position.uri == file {
+                signatures.insert(
+                    ReferenceKey::Function(fq_name.clone()),
+                    format_function_signature(&function),
+                );
+            }
+        }
+
+        for (fq_name, constant) in symbol_data.constants.read().unwrap().iter() {
+            if &constant.position.uri == file {
+                signatures.insert(
+                    ReferenceKey::Constant(fq_name.clone()),
+                    format!("{:?}", constant.get_utype().map(|t| t.to_string())),
+                );
+            }
+        }
+
+        Self { signatures }
+    }
+
+    ///
+    /// Remove the classes, functions and constants of the file from `symbol_data`, so that a
+    /// new first pass over the file can register them again
+    ///
+    pub fn retract(&self, symbol_data: &SymbolData) {
+        let mut classes = symbol_data.classes.write().unwrap();
+        let mut functions = symbol_data.functions.write().unwrap();
+        let mut constants = symbol_data.constants.write().unwrap();
+        for key in self.signatures.keys() {
+            match key {
+                ReferenceKey::Class(fq_name) => {
+                    classes.remove(fq_name);
+                }
+                ReferenceKey::Function(fq_name) => {
+                    functions.remove(fq_name);
+                }
+                ReferenceKey::Constant(fq_name) => {
+                    constants.remove(fq_name);
+                }
+                // Members go away with their class
+                _ => (),
+            }
+        }
+    }
+
+    ///
+    /// Get the declarations which were added, removed or got a different signature
+    ///
+    pub fn changed_keys(&self, new: &FileSymbols) -> HashSet<ReferenceKey> {
+        let mut changed = HashSet::new();
+        for (key, signature) in &self.signatures {
+            if new.signatures.get(key) != Some(signature) {
+                changed.insert(key.clone());
+            }
+        }
+        for key in new.signatures.keys() {
+            if !self.signatures.contains_key(key) {
+                changed.insert(key.clone());
+            }
+        }
+        changed
+    }
+}
+
+///
+/// Copy the declarations of `symbol_data`, so that files can be retracted from and analyzed
+/// into the copy while the original is still in use. The declarations themselves are
+/// shared, as a new pass over a file registers new ones rather than changing the old.
+///
+pub fn copy_symbol_data(symbol_data: &SymbolData) -> SymbolData {
+    let copy = SymbolData::new();
+    *copy.classes.write().unwrap() = symbol_data.classes.read().unwrap().clone();
+    *copy.functions.write().unwrap() = symbol_data.functions.read().unwrap().clone();
+    *copy.constants.write().unwrap() = symbol_data.constants.read().unwrap().clone();
+    copy
+}
+
+///
+/// Members used on receivers of unknown type, and functions and constants we don't know of,
+/// are recorded by name only, so files using a changed symbol that way depend on it as well
+///
+pub fn add_unresolved_keys(keys: &HashSet<ReferenceKey>) -> HashSet<ReferenceKey> {
+    let mut expanded = keys.clone();
+    for key in keys {
+        expanded.extend(key.unresolved_key());
+    }
+    expanded
+}
+
+///
+/// Members are also reachable through the classes inheriting them, so a changed member of a
+/// class is a changed member of every descendant as well
+///
+pub fn expand_to_descendants(
+    keys: &HashSet<ReferenceKey>,
+    hierarchy: &ClassHierarchy,
+) -> HashSet<ReferenceKey> {
+    let mut expanded = keys.clone();
+    for key in keys {
+        if let Some(class) = key.class_name() {
+            for descendant in hierarchy.get_descendants(class) {
+                expanded.extend(key.with_class(&descendant));
+            }
+        }
+    }
+    expanded
+}
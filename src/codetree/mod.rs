@@ -1,3 +1,9 @@
 pub mod file_scanner;
 pub mod codetree;
+pub mod overlays;
+pub mod cancellation;
+pub mod class_hierarchy;
+pub mod file_symbols;
+pub mod references;
+pub mod symbol_index;
 pub mod workspace;
\ No newline at end of file
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use rust_lsp::lsp_types::TextDocumentContentChangeEvent;
+
+use crate::phpls::locations::position_to_offset;
+
+///
+/// The contents of a document open in the editor, which might differ from what's on disk
+///
+#[derive(Clone, Debug)]
+pub struct DocumentOverlay {
+    pub version: i32,
+    pub contents: Arc<Vec<u8>>,
+}
+
+///
+/// The unsaved buffers of the open documents. Files with an overlay are read from here
+/// instead of from disk.
+///
+pub struct Overlays {
+    documents: RwLock<HashMap<PathBuf, DocumentOverlay>>,
+}
+
+impl Overlays {
+    pub fn new() -> Self {
+        Self {
+            documents: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn open(&self, path: PathBuf, version: i32, text: String) {
+        let mut documents = self.documents.write().unwrap();
+        documents.insert(
+            path,
+            DocumentOverlay {
+                version,
+                contents: Arc::new(text.into_bytes()),
+            },
+        );
+    }
+
+    ///
+    /// Apply the changes from a `didChange` notification, in order. A change without a range
+    /// replaces the whole document.
+    ///
+    pub fn change(
+        &self,
+        path: &Path,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<(), String> {
+        let mut documents = self.documents.write().unwrap();
+        let document = documents
+            .get_mut(path)
+            .ok_or_else(|| format!("{} is not open", path.display()))?;
+
+        let mut text = String::from_utf8_lossy(&document.contents).to_string();
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = position_to_offset(&text, &range.start);
+                    let end = position_to_offset(&text, &range.end).max(start);
+                    text.replace_range(start..end, &change.text);
+                }
+                None => text = change.text,
+            }
+        }
+        document.version = version;
+        document.contents = Arc::new(text.into_bytes());
+        Ok(())
+    }
+
+    pub fn close(&self, path: &Path) {
+        let mut documents = self.documents.write().unwrap();
+        documents.remove(path);
+    }
+
+    pub fn get(&self, path: &Path) -> Option<DocumentOverlay> {
+        let documents = self.documents.read().unwrap();
+        documents.get(path).cloned()
+    }
+}
@@ -0,0 +1,713 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
+use std::sync::{Arc, RwLock};
+
+use phpanalyzer::analysis::state::{AnalysisState, LookingForNode};
+use phpanalyzer::autonodes::any::AnyNodeRef;
+use phpanalyzer::symboldata::FileLocation;
+use phpanalyzer::symbols::{FullyQualifiedName, Name, Symbol};
+use phpanalyzer::Range;
+
+use crate::phpls::completion::is_identifier_char;
+
+///
+/// Identifies a symbol that can be referenced from code. Method names are lower cased, as PHP
+/// is case insensitive about them.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ReferenceKey {
+    Class(FullyQualifiedName),
+    Method(FullyQualifiedName, Name),
+    Property(FullyQualifiedName, Name),
+    ClassConstant(FullyQualifiedName, Name),
+    Function(FullyQualifiedName),
+    Constant(FullyQualifiedName),
+    /// A member used on a receiver with an unknown type. It could be any member with that name.
+    UnresolvedMember(Name),
+    /// A call to a function we don't know of, by the last part of its name
+    UnresolvedFunction(Name),
+    /// A constant we don't know of, by the last part of its name
+    UnresolvedConstant(Name),
+}
+
+impl ReferenceKey {
+    pub fn method(class: &FullyQualifiedName, name: &str) -> Self {
+        ReferenceKey::Method(class.clone(), Name::from(name.to_lowercase().as_str()))
+    }
+
+    pub fn property(class: &FullyQualifiedName, name: &str) -> Self {
+        ReferenceKey::Property(class.clone(), Name::from(name.trim_start_matches('$')))
+    }
+
+    pub fn class_constant(class: &FullyQualifiedName, name: &str) -> Self {
+        ReferenceKey::ClassConstant(class.clone(), Name::from(name))
+    }
+
+    pub fn unresolved_member(name: &str) -> Self {
+        ReferenceKey::UnresolvedMember(Name::from(name.to_lowercase().as_str()))
+    }
+
+    pub fn unresolved_function(name: &str) -> Self {
+        let name = name.rsplit('\\').next().unwrap_or(name);
+        ReferenceKey::UnresolvedFunction(Name::from(name.to_lowercase().as_str()))
+    }
+
+    pub fn unresolved_constant(name: &str) -> Self {
+        let name = name.rsplit('\\').next().unwrap_or(name);
+        ReferenceKey::UnresolvedConstant(Name::from(name))
+    }
+
+    ///
+    /// The key uses of this symbol are recorded with where we could not resolve them
+    ///
+    pub fn unresolved_key(&self) -> Option<Self> {
+        match self {
+            ReferenceKey::Function(fq_name) => {
+                Some(Self::unresolved_function(&fq_name.to_string()))
+            }
+            ReferenceKey::Constant(fq_name) => {
+                Some(Self::unresolved_constant(&fq_name.to_string()))
+            }
+            _ => self
+                .member_name()
+                .map(|name| Self::unresolved_member(&name)),
+        }
+    }
+
+    pub fn from_symbol(symbol: &Symbol) -> Option<Self> {
+        Some(match symbol {
+            Symbol::Class(c) => ReferenceKey::Class(c.get_fq_name()),
+            Symbol::Method(m) => Self::method(&m.class.get_fq_name(), &m.name.to_string()),
+            Symbol::ClassProperty(p) => Self::property(&p.class.get_fq_name(), &p.name.to_string()),
+            Symbol::ClassConstant(c) => {
+                Self::class_constant(&c.class.get_fq_name(), &c.name.to_string())
+            }
+            Symbol::Function(f) => ReferenceKey::Function(f.fq_name.clone()),
+            Symbol::Constant(c) => ReferenceKey::Constant(c.fq_name.clone()),
+            _ => return None,
+        })
+    }
+
+    ///
+    /// The member name, for keys that refers to a member of a class
+    ///
+    pub fn member_name(&self) -> Option<String> {
+        match self {
+            ReferenceKey::Method(_, name)
+            | ReferenceKey::Property(_, name)
+            | ReferenceKey::ClassConstant(_, name) => Some(name.to_string()),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The class, for class keys and keys that refers to a member of a class
+    ///
+    pub fn class_name(&self) -> Option<&FullyQualifiedName> {
+        match self {
+            ReferenceKey::Class(class)
+            | ReferenceKey::Method(class, _)
+            | ReferenceKey::Property(class, _)
+            | ReferenceKey::ClassConstant(class, _) => Some(class),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The same key, for another class
+    ///
+    pub fn with_class(&self, class: &FullyQualifiedName) -> Option<Self> {
+        Some(match self {
+            ReferenceKey::Class(_) => ReferenceKey::Class(class.clone()),
+            ReferenceKey::Method(_, name) => ReferenceKey::Method(class.clone(), name.clone()),
+            ReferenceKey::Property(_, name) => ReferenceKey::Property(class.clone(), name.clone()),
+            ReferenceKey::ClassConstant(_, name) => {
+                ReferenceKey::ClassConstant(class.clone(), name.clone())
+            }
+            _ => return None,
+        })
+    }
+}
+
+///
+/// A call from inside one method or function to another
+///
+#[derive(Clone, Debug)]
+pub struct CallSite {
+    pub caller: ReferenceKey,
+    pub callee: ReferenceKey,
+    /// The name of the callee at the call
+    pub location: FileLocation,
+}
+
+///
+/// Every place a symbol is used, collected during the third pass
+///
+pub struct ReferenceIndex {
+    references: RwLock<HashMap<ReferenceKey, Vec<FileLocation>>>,
+    calls_by_caller: RwLock<HashMap<ReferenceKey, Vec<CallSite>>>,
+    calls_by_callee: RwLock<HashMap<ReferenceKey, Vec<CallSite>>>,
+}
+
+impl ReferenceIndex {
+    pub fn new() -> Self {
+        Self {
+            references: RwLock::new(HashMap::new()),
+            calls_by_caller: RwLock::new(HashMap::new()),
+            calls_by_callee: RwLock::new(HashMap::new()),
+        }
+    }
+
+    ///
+    /// Copy the index, so that it can be updated while the original is still in use
+    ///
+    pub fn duplicate(&self) -> Self {
+        Self {
+            references: RwLock::new(self.references.read().unwrap().clone()),
+            calls_by_caller: RwLock::new(self.calls_by_caller.read().unwrap().clone()),
+            calls_by_callee: RwLock::new(self.calls_by_callee.read().unwrap().clone()),
+        }
+    }
+
+    pub fn add_call(&self, call: CallSite) {
+        self.calls_by_callee
+            .write()
+            .unwrap()
+            .entry(call.callee.clone())
+            .or_insert_with(Vec::new)
+            .push(call.clone());
+        self.calls_by_caller
+            .write()
+            .unwrap()
+            .entry(call.caller.clone())
+            .or_insert_with(Vec::new)
+            .push(call);
+    }
+
+    ///
+    /// Get the calls made from inside the method or function `caller`
+    ///
+    pub fn get_outgoing_calls(&self, caller: &ReferenceKey) -> Vec<CallSite> {
+        let calls = self.calls_by_caller.read().unwrap();
+        calls.get(caller).cloned().unwrap_or_default()
+    }
+
+    ///
+    /// Get the calls made to the method or function `callee`
+    ///
+    pub fn get_incoming_calls(&self, callee: &ReferenceKey) -> Vec<CallSite> {
+        let calls = self.calls_by_callee.read().unwrap();
+        calls.get(callee).cloned().unwrap_or_default()
+    }
+
+    pub fn add(&self, key: ReferenceKey, location: FileLocation) {
+        let mut references = self.references.write().unwrap();
+        references.entry(key).or_insert_with(Vec::new).push(location);
+    }
+
+    pub fn get(&self, key: &ReferenceKey) -> Vec<FileLocation> {
+        let references = self.references.read().unwrap();
+        references.get(key).cloned().unwrap_or_default()
+    }
+
+    ///
+    /// Get references to `key`. For members this includes uses on receivers we could not find
+    /// the type of, as they might be references as well.
+    ///
+    pub fn get_including_unresolved(&self, key: &ReferenceKey) -> Vec<FileLocation> {
+        let mut locations = self.get(key);
+        if let Some(name) = key.member_name() {
+            locations.extend(self.get(&ReferenceKey::unresolved_member(&name)));
+        }
+        locations
+    }
+
+    ///
+    /// Get the files referencing any of `keys`
+    ///
+    pub fn get_referencing_files(&self, keys: &HashSet<ReferenceKey>) -> HashSet<OsString> {
+        let references = self.references.read().unwrap();
+        keys.iter()
+            .filter_map(|k| references.get(k))
+            .flatten()
+            .map(|l| l.uri.clone())
+            .collect()
+    }
+
+    ///
+    /// Forget all references from one file
+    ///
+    pub fn remove_file(&self, file: &OsString) {
+        let mut references = self.references.write().unwrap();
+        for locations in references.values_mut() {
+            locations.retain(|l| &l.uri != file);
+        }
+        references.retain(|_, locations| !locations.is_empty());
+
+        for calls in [&self.calls_by_caller, &self.calls_by_callee].iter() {
+            let mut calls = calls.write().unwrap();
+            for sites in calls.values_mut() {
+                sites.retain(|c| &c.location.uri != file);
+            }
+            calls.retain(|_, sites| !sites.is_empty());
+        }
+    }
+}
+
+///
+/// Records the references in a file as the analyzer gets to them during the third pass. Each
+/// name is looked at with the state the analyzer has at that node, so that names resolve
+/// against the namespace they are in and `$this` and local variables have their types.
+///
+#[derive(Default)]
+pub struct ReferenceCollector {
+    source: Vec<u8>,
+    filename: OsString,
+    /// Each reference, with the method or function it is made from
+    found: Vec<(ReferenceKey, FileLocation, Option<ReferenceKey>)>,
+}
+
+impl ReferenceCollector {
+    pub fn new(source: Vec<u8>, filename: OsString) -> Self {
+        Self {
+            source,
+            filename,
+            found: vec![],
+        }
+    }
+
+    ///
+    /// Look for each name in `root` that might be a reference during the third pass. It is
+    /// done with `looking_for_node`, which is set up again for the next name each time the
+    /// analyzer gets to one, as the analyzer walks the file in order.
+    ///
+    pub fn look_for_references(
+        collector: Arc<RwLock<ReferenceCollector>>,
+        root: &AnyNodeRef,
+    ) -> Option<LookingForNode> {
+        let mut candidates = vec![];
+        get_reference_candidates(root, &mut candidates);
+        candidates.sort_by_key(|c| c.start_byte);
+        candidates.dedup_by_key(|c| c.start_byte);
+        Self::look_for_next(collector, candidates.into())
+    }
+
+    fn look_for_next(
+        collector: Arc<RwLock<ReferenceCollector>>,
+        mut candidates: VecDeque<Range>,
+    ) -> Option<LookingForNode> {
+        let candidate = candidates.pop_front()?;
+        Some(LookingForNode {
+            pos: candidate.start_point,
+            callback: Arc::new(RwLock::new(Some(Box::new(move |node, state, path| {
+                collector
+                    .write()
+                    .unwrap()
+                    .visit(&node, state, path, &candidate);
+                state.looking_for_node = Self::look_for_next(collector, candidates);
+            })))),
+        })
+    }
+
+    ///
+    /// Look at the name `candidate`, where the analyzer found `node`. `path` is the nodes from
+    /// the root down to, but not including, `node`. We walk upwards to the expression the
+    /// name is part of, if any.
+    ///
+    pub fn visit(
+        &mut self,
+        node: &AnyNodeRef,
+        state: &mut AnalysisState,
+        path: &[AnyNodeRef],
+        candidate: &Range,
+    ) {
+        let mut nodes = path.to_vec();
+        nodes.push(node.clone());
+
+        for idx in (0..nodes.len()).rev() {
+            let current = &nodes[idx];
+            let parent = if idx > 0 { nodes.get(idx - 1) } else { None };
+
+            if let Some((key, range)) = get_reference_for_node(current, parent, state, &self.source)
+            {
+                if range.start_byte != candidate.start_byte {
+                    continue;
+                }
+                let location = FileLocation::new(self.filename.clone(), range);
+                let caller = match &key {
+                    ReferenceKey::Method(..) | ReferenceKey::Function(_) => {
+                        get_caller(&nodes[..idx], state, &self.source)
+                    }
+                    _ => None,
+                };
+                self.found.push((key, location, caller));
+                return;
+            }
+            match current {
+                AnyNodeRef::Arguments(_)
+                | AnyNodeRef::CompoundStatement(_)
+                | AnyNodeRef::ExpressionStatement(_)
+                | AnyNodeRef::ReturnStatement(_)
+                | AnyNodeRef::DeclarationList(_)
+                | AnyNodeRef::Program(_) => return,
+                _ => (),
+            }
+        }
+    }
+
+    ///
+    /// Add what was found to `references`
+    ///
+    pub fn add_to(self, references: &ReferenceIndex) {
+        for (key, location, caller) in self.found {
+            if let Some(caller) = caller {
+                references.add_call(CallSite {
+                    caller,
+                    callee: key.clone(),
+                    location: location.clone(),
+                });
+            }
+            references.add(key, location);
+        }
+    }
+}
+
+///
+/// The names below `node` that might be references: every name, except those of variables,
+/// and qualified names as a whole
+///
+fn get_reference_candidates(node: &AnyNodeRef, candidates: &mut Vec<Range>) {
+    for child in node.children_any() {
+        match &child {
+            AnyNodeRef::Name(_) if !matches!(node, AnyNodeRef::VariableName(_)) => {
+                candidates.push(child.range())
+            }
+            AnyNodeRef::QualifiedName(_) => candidates.push(child.range()),
+            _ => get_reference_candidates(&child, candidates),
+        }
+    }
+}
+
+///
+/// The method or function the innermost declaration in `path` declares. Calls inside closures
+/// are attributed to the function they are in.
+///
+fn get_caller(
+    path: &[AnyNodeRef],
+    state: &mut AnalysisState,
+    source: &[u8],
+) -> Option<ReferenceKey> {
+    for node in path.iter().rev() {
+        match node {
+            AnyNodeRef::MethodDeclaration(_) => {
+                let class = state.in_class.as_ref()?.get_fq_name();
+                let name = get_declared_name(node, source)?;
+                return Some(ReferenceKey::method(&class, &name));
+            }
+            AnyNodeRef::FunctionDefinition(_) => {
+                let name = get_declared_name(node, source)?;
+                return Some(ReferenceKey::Function(
+                    state.get_fq_symbol_name_from_local_name(&Name::from(name.as_str())),
+                ));
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+///
+/// The name of a class, method or function declaration
+///
+fn get_declared_name(node: &AnyNodeRef, source: &[u8]) -> Option<String> {
+    node.children_any()
+        .iter()
+        .find(|c| matches!(c, AnyNodeRef::Name(_)))
+        .map(|n| node_text(&n.range(), source).to_string())
+}
+
+pub fn node_text<'a>(range: &Range, source: &'a [u8]) -> std::borrow::Cow<'a, str> {
+    let end = range.end_byte.min(source.len());
+    let start = range.start_byte.min(end);
+    String::from_utf8_lossy(&source[start..end])
+}
+
+///
+/// Find out if `node` is a reference to a symbol, and which symbol. The range returned is the
+/// range of the name itself, so that it is usable for highlighting and renaming.
+///
+pub fn get_reference_for_node(
+    node: &AnyNodeRef,
+    parent: Option<&AnyNodeRef>,
+    state: &mut AnalysisState,
+    source: &[u8],
+) -> Option<(ReferenceKey, Range)> {
+    match node {
+        AnyNodeRef::MemberCallExpression(mc) => {
+            let range = mc.name.range();
+            let key = mc
+                .get_symbols(state)
+                .and_then(|symbols| symbols.iter().find_map(ReferenceKey::from_symbol))
+                .unwrap_or_else(|| ReferenceKey::unresolved_member(&node_text(&range, source)));
+            Some((key, range))
+        }
+        AnyNodeRef::NullsafeMemberCallExpression(mc) => {
+            let range = mc.name.range();
+            let key = mc
+                .get_symbols(state)
+                .and_then(|symbols| symbols.iter().find_map(ReferenceKey::from_symbol))
+                .unwrap_or_else(|| ReferenceKey::unresolved_member(&node_text(&range, source)));
+            Some((key, range))
+        }
+        AnyNodeRef::MemberAccessExpression(ma) => {
+            let range = ma.name.range();
+            let key = ma
+                .get_symbols(state)
+                .and_then(|symbols| symbols.iter().find_map(ReferenceKey::from_symbol))
+                .unwrap_or_else(|| ReferenceKey::unresolved_member(&node_text(&range, source)));
+            Some((key, range))
+        }
+        AnyNodeRef::NullsafeMemberAccessExpression(ma) => {
+            let range = ma.name.range();
+            let key = ma
+                .get_symbols(state)
+                .and_then(|symbols| symbols.iter().find_map(ReferenceKey::from_symbol))
+                .unwrap_or_else(|| ReferenceKey::unresolved_member(&node_text(&range, source)));
+            Some((key, range))
+        }
+        AnyNodeRef::ScopedCallExpression(sc) => {
+            let symbol = sc.get_method_symbol(state)?;
+            let key = ReferenceKey::from_symbol(&Symbol::Method(symbol))?;
+            Some((key, sc.name.range()))
+        }
+        AnyNodeRef::ScopedPropertyAccessExpression(sp) => {
+            let key = sp
+                .get_symbols(state)?
+                .iter()
+                .find_map(ReferenceKey::from_symbol)?;
+            Some((key, sp.name.range()))
+        }
+        AnyNodeRef::ClassConstantAccessExpression(cc) => {
+            let name = cc.children_any().last()?.range();
+            if node_text(&name, source).eq_ignore_ascii_case("class") {
+                // `Foo::class` is a reference to the class, which the scope name takes care of
+                return None;
+            }
+            let key = cc
+                .get_symbols(state)?
+                .iter()
+                .find_map(ReferenceKey::from_symbol)?;
+            Some((key, name))
+        }
+        AnyNodeRef::FunctionCallExpression(fc) => {
+            let range = fc.function.range();
+            let text = node_text(&range, source);
+            let key = match fc
+                .get_symbols(state)
+                .and_then(|symbols| symbols.iter().find_map(ReferenceKey::from_symbol))
+            {
+                Some(key) => key,
+                // Variables and closures are called by value, not by name
+                None if is_name(&text) => ReferenceKey::unresolved_function(&text),
+                None => return None,
+            };
+            Some((key, range))
+        }
+        AnyNodeRef::Name(_) | AnyNodeRef::QualifiedName(_) => {
+            get_reference_for_name(node, parent?, state, source)
+        }
+        _ => None,
+    }
+}
+
+///
+/// Names in a position where they refer to a class, interface or trait, or a global constant
+///
+fn get_reference_for_name(
+    node: &AnyNodeRef,
+    parent: &AnyNodeRef,
+    state: &mut AnalysisState,
+    source: &[u8],
+) -> Option<(ReferenceKey, Range)> {
+    let range = node.range();
+    let text = node_text(&range, source).to_string();
+    let is_first_child = parent
+        .children_any()
+        .first()
+        .map(|c| c.range() == range)
+        .unwrap_or(false);
+
+    let is_class_reference = match parent {
+        AnyNodeRef::NamedType(_)
+        | AnyNodeRef::OptionalType(_)
+        | AnyNodeRef::UnionType(_)
+        | AnyNodeRef::BaseClause(_)
+        | AnyNodeRef::ClassInterfaceClause(_)
+        | AnyNodeRef::ObjectCreationExpression(_)
+        | AnyNodeRef::TypeList(_)
+        | AnyNodeRef::UseDeclaration(_) => true,
+
+        // instanceof is the only binary operator taking a class name, on its right side
+        AnyNodeRef::BinaryExpression(_) => {
+            let operands = parent.children_any();
+            match (operands.first(), operands.last()) {
+                (Some(left), Some(right)) if right.range() == range => {
+                    let start = left.range().end_byte;
+                    let end = right.range().start_byte;
+                    source
+                        .get(start..end)
+                        .map(|operator| String::from_utf8_lossy(operator).trim().to_lowercase())
+                        .map_or(false, |operator| operator == "instanceof")
+                }
+                _ => false,
+            }
+        }
+
+        // Only the scope is a class name, the member name is handled by the parent
+        AnyNodeRef::ScopedCallExpression(_)
+        | AnyNodeRef::ScopedPropertyAccessExpression(_)
+        | AnyNodeRef::ClassConstantAccessExpression(_) => {
+            if !is_first_child {
+                return None;
+            }
+            true
+        }
+
+        AnyNodeRef::NamespaceUseClause(_) => {
+            // Imports are always fully qualified
+            let fq_name = FullyQualifiedName::from(text.as_str());
+            return Some((ReferenceKey::Class(fq_name), range));
+        }
+
+        // Part of a declaration, a call or a namespace
+        AnyNodeRef::FunctionCallExpression(_)
+        | AnyNodeRef::MemberCallExpression(_)
+        | AnyNodeRef::NullsafeMemberCallExpression(_)
+        | AnyNodeRef::MemberAccessExpression(_)
+        | AnyNodeRef::NullsafeMemberAccessExpression(_)
+        | AnyNodeRef::NamespaceDefinition(_)
+        | AnyNodeRef::NamespaceName(_)
+        | AnyNodeRef::QualifiedName(_)
+        | AnyNodeRef::ClassDeclaration(_)
+        | AnyNodeRef::InterfaceDeclaration(_)
+        | AnyNodeRef::TraitDeclaration(_)
+        | AnyNodeRef::MethodDeclaration(_)
+        | AnyNodeRef::FunctionDefinition(_)
+        | AnyNodeRef::ConstElement(_)
+        | AnyNodeRef::NamedLabelStatement(_)
+        | AnyNodeRef::GotoStatement(_) => return None,
+
+        _ => false,
+    };
+
+    if is_class_reference {
+        if ["self", "static", "parent"]
+            .iter()
+            .any(|kw| text.eq_ignore_ascii_case(kw))
+        {
+            return None;
+        }
+        let fq_name = state.get_fq_symbol_name_from_local_name(&Name::from(text.as_str()));
+        return Some((ReferenceKey::Class(fq_name), range));
+    }
+
+    // Anything else is a constant, if we know about it
+    let fq_name = state.get_fq_symbol_name_from_local_name(&Name::from(text.as_str()));
+    let constants = state.symbol_data.constants.read().unwrap();
+    if constants.contains_key(&fq_name) {
+        return Some((ReferenceKey::Constant(fq_name), range));
+    }
+    // Unqualified constants falls back to the global namespace
+    let global_name = FullyQualifiedName::from(text.as_str());
+    if constants.contains_key(&global_name) {
+        return Some((ReferenceKey::Constant(global_name), range));
+    }
+    // The name of a named argument is the first of two children
+    if matches!(parent, AnyNodeRef::Argument(_))
+        && is_first_child
+        && parent.children_any().len() > 1
+    {
+        return None;
+    }
+    Some((ReferenceKey::unresolved_constant(&text), range))
+}
+
+fn is_name(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| is_identifier_char(c) || c == '\\')
+}
+
+///
+/// Find the symbol at the cursor of a position callback, either a reference to it or its
+/// declaration. We walk upwards from the found node, but not out of the expression it is in.
+///
+pub fn get_reference_at_cursor(
+    node: &AnyNodeRef,
+    path: &Vec<AnyNodeRef>,
+    state: &mut AnalysisState,
+    source: &[u8],
+) -> Option<(ReferenceKey, Range)> {
+    let mut nodes = path.clone();
+    nodes.push(node.clone());
+
+    for idx in (0..nodes.len()).rev() {
+        let current = &nodes[idx];
+        let parent = if idx > 0 { nodes.get(idx - 1) } else { None };
+
+        if let Some(found) = get_reference_for_node(current, parent, state, source) {
+            return Some(found);
+        }
+        if let Some(found) = get_declaration_for_node(current, parent, state, source) {
+            return Some(found);
+        }
+        match current {
+            AnyNodeRef::Arguments(_)
+            | AnyNodeRef::CompoundStatement(_)
+            | AnyNodeRef::ExpressionStatement(_)
+            | AnyNodeRef::ReturnStatement(_)
+            | AnyNodeRef::FormalParameters(_)
+            | AnyNodeRef::DeclarationList(_)
+            | AnyNodeRef::Program(_) => break,
+            _ => (),
+        }
+    }
+    None
+}
+
+///
+/// Check if `node` is the name in the declaration of a symbol
+///
+fn get_declaration_for_node(
+    node: &AnyNodeRef,
+    parent: Option<&AnyNodeRef>,
+    state: &mut AnalysisState,
+    source: &[u8],
+) -> Option<(ReferenceKey, Range)> {
+    let range = node.range();
+    let text = node_text(&range, source).to_string();
+    let in_class = state.in_class.as_ref().map(|c| c.get_fq_name());
+
+    let key = match (node, parent?) {
+        (AnyNodeRef::Name(_), AnyNodeRef::ClassDeclaration(_))
+        | (AnyNodeRef::Name(_), AnyNodeRef::InterfaceDeclaration(_))
+        | (AnyNodeRef::Name(_), AnyNodeRef::TraitDeclaration(_)) => ReferenceKey::Class(
+            state.get_fq_symbol_name_from_local_name(&Name::from(text.as_str())),
+        ),
+        (AnyNodeRef::Name(_), AnyNodeRef::MethodDeclaration(_)) => {
+            ReferenceKey::method(&in_class?, &text)
+        }
+        (AnyNodeRef::VariableName(_), AnyNodeRef::PropertyElement(_)) => {
+            ReferenceKey::property(&in_class?, &text)
+        }
+        (AnyNodeRef::Name(_), AnyNodeRef::ConstElement(_)) => match in_class {
+            Some(class) => ReferenceKey::class_constant(&class, &text),
+            None => ReferenceKey::Constant(
+                state.get_fq_symbol_name_from_local_name(&Name::from(text.as_str())),
+            ),
+        },
+        (AnyNodeRef::Name(_), AnyNodeRef::FunctionDefinition(_)) => ReferenceKey::Function(
+            state.get_fq_symbol_name_from_local_name(&Name::from(text.as_str())),
+        ),
+        _ => return None,
+    };
+    Some((key, range))
+}
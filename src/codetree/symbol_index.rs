@@ -0,0 +1,125 @@
+use phpanalyzer::symboldata::class::ClassType;
+use phpanalyzer::symboldata::{FileLocation, SymbolData};
+use phpanalyzer::symbols::FullyQualifiedName;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexedSymbolKind {
+    Class,
+    Interface,
+    Trait,
+    Function,
+    Constant,
+}
+
+#[derive(Clone, Debug)]
+pub struct IndexedSymbol {
+    /// The name without namespace
+    pub name: String,
+    /// The fully qualified name, without leading backslash
+    pub fq_name: String,
+    pub kind: IndexedSymbolKind,
+    pub location: Option<FileLocation>,
+}
+
+impl IndexedSymbol {
+    ///
+    /// The namespace the symbol is declared in, without leading backslash. The global
+    /// namespace is the empty string.
+    ///
+    pub fn namespace(&self) -> &str {
+        match self.fq_name.rfind('\\') {
+            Some(idx) => &self.fq_name[..idx],
+            None => "",
+        }
+    }
+}
+
+///
+/// Name index over all top level symbols declared in a `CodeTree`, sorted by the lower
+/// cased short name so that prefix lookups are cheap
+///
+pub struct SymbolIndex {
+    symbols: Vec<IndexedSymbol>,
+}
+
+impl SymbolIndex {
+    pub fn new() -> Self {
+        Self { symbols: vec![] }
+    }
+
+    pub fn from_symbol_data(symbol_data: &SymbolData) -> Self {
+        let mut symbols = vec![];
+
+        for (fq_name, class) in symbol_data.classes.read().unwrap().iter() {
+            let class = class.read().unwrap();
+            let (kind, location) = match &*class {
+                ClassType::Class(c) => (IndexedSymbolKind::Class, c.position.clone()),
+                ClassType::Interface(i) => (IndexedSymbolKind::Interface, i.position.clone()),
+                ClassType::Trait(t) => (IndexedSymbolKind::Trait, t.position.clone()),
+                ClassType::None => continue,
+            };
+            symbols.push(Self::indexed_symbol(fq_name, kind, Some(location)));
+        }
+
+        for (fq_name, function) in symbol_data.functions.read().unwrap().iter() {
+            let function = function.read().unwrap();
+            symbols.push(Self::indexed_symbol(
+                fq_name,
+                IndexedSymbolKind::Function,
+                Some(function.position.clone()),
+            ));
+        }
+
+        for (fq_name, constant) in symbol_data.constants.read().unwrap().iter() {
+            symbols.push(Self::indexed_symbol(
+                fq_name,
+                IndexedSymbolKind::Constant,
+                Some(constant.position.clone()),
+            ));
+        }
+
+        symbols.sort_by_cached_key(|s| s.name.to_lowercase());
+        Self { symbols }
+    }
+
+    fn indexed_symbol(
+        fq_name: &FullyQualifiedName,
+        kind: IndexedSymbolKind,
+        location: Option<FileLocation>,
+    ) -> IndexedSymbol {
+        let fq_name = fq_name.to_string().trim_start_matches('\\').to_string();
+        let name = match fq_name.rfind('\\') {
+            Some(idx) => fq_name[idx + 1..].to_string(),
+            None => fq_name.clone(),
+        };
+        IndexedSymbol {
+            name,
+            fq_name,
+            kind,
+            location,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &IndexedSymbol> {
+        self.symbols.iter()
+    }
+
+    ///
+    /// Get all symbols with a short name starting with `prefix`, case insensitive
+    ///
+    pub fn find_by_prefix(&self, prefix: &str) -> &[IndexedSymbol] {
+        let prefix = prefix.to_lowercase();
+        let start = self
+            .symbols
+            .partition_point(|s| s.name.to_lowercase() < prefix);
+        let len = self.symbols[start..]
+            .iter()
+            .take_while(|s| s.name.to_lowercase().starts_with(&prefix))
+            .count();
+        &self.symbols[start..start + len]
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+///
+/// How long to wait after a request for analysis before starting it, so that a burst of saves
+/// only leads to one run
+///
+pub const REANALYSIS_DEBOUNCE: Duration = Duration::from_millis(300);
+
+///
+/// Coalesces requests for analysis, of the workspace or of edited files. Every request gets a generation number, and a run
+/// covers all requests made before it started. Requests arriving while a run is going on are
+/// picked up by one follow-up run, and the callbacks waiting for them are held back until
+/// that run is complete, so they never see results older than the disk state they asked for.
+///
+pub struct AnalysisQueue<T> {
+    debounce: Duration,
+    state: Mutex<QueueState<T>>,
+}
+
+struct QueueState<T> {
+    /// The generation of the latest request
+    requested: u64,
+    /// The generation covered by the latest completed run
+    completed: u64,
+    /// If someone has taken on running the queued requests
+    running: bool,
+    last_request: Option<Instant>,
+    waiting: Vec<(u64, T)>,
+}
+
+impl<T> AnalysisQueue<T> {
+    pub fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            state: Mutex::new(QueueState {
+                requested: 0,
+                completed: 0,
+                running: false,
+                last_request: None,
+                waiting: vec![],
+            }),
+        }
+    }
+
+    ///
+    /// Ask for an analysis reflecting what's on disk now. `callback` is handed back from
+    /// `finish_run` once such an analysis is complete. Returns true when the caller should
+    /// drive the queue with `start_run` and `finish_run`, and false when that is already
+    /// being done.
+    ///
+    pub fn request(&self, callback: Option<T>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.requested += 1;
+        state.last_request = Some(Instant::now());
+        if let Some(callback) = callback {
+            let generation = state.requested;
+            state.waiting.push((generation, callback));
+        }
+        if state.running {
+            false
+        } else {
+            state.running = true;
+            true
+        }
+    }
+
+    ///
+    /// Wait for the results of the analysis in progress, if any. When there is nothing to wait
+    /// for, `callback` is handed right back.
+    ///
+    pub fn when_idle(&self, callback: T) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.running {
+            let generation = state.requested;
+            state.waiting.push((generation, callback));
+            None
+        } else {
+            Some(callback)
+        }
+    }
+
+    ///
+    /// Start the next run, once no request has come in for the debounce period. Returns the
+    /// generation the run covers, or `None` when every request has been taken care of.
+    ///
+    pub fn start_run(&self) -> Option<u64> {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                if state.requested <= state.completed {
+                    state.running = false;
+                    return None;
+                }
+                let since_request = state
+                    .last_request
+                    .map(|t| t.elapsed())
+                    .unwrap_or(self.debounce);
+                if since_request >= self.debounce {
+                    return Some(state.requested);
+                }
+                self.debounce - since_request
+                // End of lock, others can request while we wait
+            };
+            thread::sleep(wait);
+        }
+    }
+
+    ///
+    /// Give up on the run in progress. Whoever is waiting for it keeps waiting, and the next
+    /// request starts a new run.
+    ///
+    pub fn abandon_run(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+    }
+
+    ///
+    /// Mark the run covering `generation` as complete, and get the callbacks it satisfied
+    ///
+    pub fn finish_run(&self, generation: u64) -> Vec<T> {
+        let mut state = self.state.lock().unwrap();
+        state.completed = generation;
+        let (done, waiting) = state
+            .waiting
+            .drain(..)
+            .partition(|(g, _)| *g <= generation);
+        state.waiting = waiting;
+        done.into_iter().map(|(_, callback)| callback).collect()
+    }
+}
@@ -0,0 +1,192 @@
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use rust_lsp::lsp::LspClientRpc;
+use rust_lsp::lsp_types::{
+    NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport,
+};
+
+use url::Url;
+
+use crate::codetree::cancellation::CancellationToken;
+use crate::codetree::codetree::{CallbackProgress, CodeTree};
+
+use super::analysis_queue::AnalysisQueue;
+use super::instance::{publish_diagnostics, PHPLanguageServerInstanceClient};
+
+pub const ANALYSIS_PROGRESS_TOKEN: &str = "oh_by_the_way";
+
+///
+/// Called once an analysis reflecting the disk state at the time of the request is complete.
+/// It runs on the analysis thread, so it only gets a client to talk to the editor with.
+///
+pub type AnalysisCallback =
+    Box<dyn FnOnce(&mut PHPLanguageServerInstanceClient, Arc<CodeTree>) -> () + Send>;
+
+///
+/// Works through the analysis queue on a thread of its own. The code trees keep the snapshot
+/// of their last complete analysis until the new one is done, so requests are answered from
+/// that meanwhile.
+///
+pub struct AnalysisWorker {
+    pub client: PHPLanguageServerInstanceClient,
+    pub codetrees: Vec<Arc<CodeTree>>,
+    pub queue: Arc<AnalysisQueue<AnalysisCallback>>,
+    pub cancellation: Arc<RwLock<CancellationToken>>,
+    pub progress_registered: Arc<AtomicBool>,
+}
+
+impl AnalysisWorker {
+    pub fn spawn(self) {
+        thread::spawn(move || self.run());
+    }
+
+    fn run(mut self) {
+        while let Some(generation) = self.queue.start_run() {
+            if !self.run_analysis() {
+                // The callbacks are kept until the next request leads to a complete run
+                self.queue.abandon_run();
+                break;
+            }
+            for cb in self.queue.finish_run(generation) {
+                if let Some(ct) = self.codetrees.get(0) {
+                    cb(&mut self.client, ct.clone());
+                }
+            }
+        }
+    }
+
+    ///
+    /// Analyze all the code trees. Returns false if the user cancelled it, in which case the
+    /// results of the previous analysis are kept.
+    ///
+    fn run_analysis(&mut self) -> bool {
+        let cancel = CancellationToken::new();
+        *self.cancellation.write().unwrap() = cancel.clone();
+        let mut client_handle = self.client.clone();
+        let progress_token = NumberOrString::String(ANALYSIS_PROGRESS_TOKEN.to_string());
+
+        if !self.progress_registered.swap(true, Ordering::Relaxed) {
+            client_handle
+                .client()
+                .window_work_done_progress_create(WorkDoneProgressCreateParams {
+                    token: progress_token.clone(),
+                });
+        }
+        client_handle.client().progress(ProgressParams {
+            token: progress_token.clone(),
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Analyserer".to_string(),
+                cancellable: Some(true),
+                message: Some("message...".into()),
+                percentage: Some(0),
+            })),
+        });
+
+        let thread_count = 8;
+        let cb_client_handle = client_handle.clone();
+        let cb_token = progress_token.clone();
+        let status = Arc::new(CallbackProgress::new(Box::new(move |percent, ident| {
+            let mut handle = cb_client_handle.clone();
+            handle.client().progress(ProgressParams {
+                token: cb_token.clone(),
+                value: ProgressParamsValue::WorkDone(WorkDoneProgress::Report(
+                    WorkDoneProgressReport {
+                        cancellable: Some(true),
+                        message: Some(ident),
+                        percentage: Some(percent.try_into().ok().unwrap_or(0)),
+                    },
+                )),
+            });
+        })));
+        let mut completed = true;
+        for ct in &self.codetrees {
+            match ct.run_analysis(thread_count, status.clone(), &cancel) {
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    eprintln!("Analysis cancelled");
+                    completed = false;
+                    break;
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+
+        client_handle.client().progress(ProgressParams {
+            token: progress_token,
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(if completed { "Done." } else { "Cancelled." }.into()),
+            })),
+        });
+        completed
+    }
+}
+
+///
+/// Reanalyzes the files being edited on a thread of its own, each on top of the last
+/// analysis. The queue holds the files, and a burst of edits only leads to one run. Files
+/// edited while the workspace is being analyzed are done once that is complete, as their
+/// results would otherwise be replaced by it.
+///
+pub struct FileAnalysisWorker {
+    pub client: PHPLanguageServerInstanceClient,
+    pub codetrees: Vec<Arc<CodeTree>>,
+    pub queue: Arc<AnalysisQueue<Url>>,
+    pub analysis_queue: Arc<AnalysisQueue<AnalysisCallback>>,
+}
+
+impl FileAnalysisWorker {
+    pub fn spawn(self) {
+        thread::spawn(move || self.run());
+    }
+
+    fn run(mut self) {
+        while let Some(generation) = self.queue.start_run() {
+            // Taken before the run, so that edits made during it lead to another one
+            let mut uris = self.queue.finish_run(generation);
+            uris.sort();
+            uris.dedup();
+            for uri in uris {
+                let codetree = match self.codetrees.iter().find(|ct| ct.contains_file(&uri)) {
+                    Some(ct) => ct.clone(),
+                    None => continue,
+                };
+                let work: AnalysisCallback =
+                    Box::new(move |client, _| reanalyze_file(client, &codetree, uri));
+                if let Some(work) = self.analysis_queue.when_idle(work) {
+                    if let Some(ct) = self.codetrees.get(0) {
+                        work(&mut self.client, ct.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+///
+/// Reanalyze `uri`, with its contents in the editor if it is open, and publish the
+/// diagnostics of every file that got new issues
+///
+fn reanalyze_file(client: &mut PHPLanguageServerInstanceClient, codetree: &CodeTree, uri: Url) {
+    let path = match uri.to_file_path() {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    if codetree.get_symbol_data().is_none() {
+        // The file is analyzed along with the rest of the workspace
+        return;
+    }
+    match codetree.reanalyze_file(&path) {
+        Ok(files) => {
+            for file in files {
+                if let Ok(file_uri) = Url::from_file_path(&file) {
+                    publish_diagnostics(client, codetree, file_uri);
+                }
+            }
+        }
+        Err(e) => eprintln!("ERROR: could not reanalyze {}: {}", uri, e),
+    }
+}
@@ -0,0 +1,273 @@
+use phpanalyzer::{
+    symboldata::SymbolData,
+    symbols::{FullyQualifiedName, Name},
+};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{
+        CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
+        CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+        Range, SymbolKind,
+    },
+};
+use serde_json::{json, Value};
+
+use crate::codetree::references::{get_reference_at_cursor, CallSite, ReferenceKey};
+
+use super::{
+    instance::PHPLanguageServerInstance,
+    locations::LocationConverter,
+    members::{get_class_members, get_method, MemberKind},
+    references::{get_declaration_locations, get_related_keys},
+};
+
+pub fn prepare_call_hierarchy(
+    phpls: &PHPLanguageServerInstance,
+    params: CallHierarchyPrepareParams,
+    completable: MethodCompletable<Option<Vec<CallHierarchyItem>>, ()>,
+) {
+    let position = params.text_document_position_params;
+    let uri = position.text_document.uri.clone();
+    let source = phpls
+        .get_file_for_uri(&uri)
+        .and_then(|f| f.get_contents().ok())
+        .unwrap_or_default();
+
+    let key = match phpls.at_position(
+        position,
+        Box::new(move |node, state, path| {
+            get_reference_at_cursor(&node, path, state, &source).map(|(key, _)| key)
+        }),
+    ) {
+        Ok((_, Some(Some(key)))) => key,
+        Ok(_) => {
+            completable.complete(Ok(None));
+            return;
+        }
+        Err(_) if phpls.is_request_cancelled() => {
+            completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+            return;
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let symbol_data = match phpls.get_symbol_data_for_uri(&uri) {
+        Some(symbol_data) => symbol_data,
+        None => {
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let key = get_declaring_key(&symbol_data, &key);
+    let mut converter = LocationConverter::new(phpls);
+    let item = get_call_hierarchy_item(&mut converter, &symbol_data, &key);
+    completable.complete(Ok(item.map(|i| vec![i])))
+}
+
+pub fn incoming_calls(
+    phpls: &PHPLanguageServerInstance,
+    params: CallHierarchyIncomingCallsParams,
+    completable: MethodCompletable<Option<Vec<CallHierarchyIncomingCall>>, ()>,
+) {
+    let uri = params.item.uri.clone();
+    let (key, symbol_data, reference_index, hierarchy) = match (
+        params.item.data.as_ref().and_then(key_from_data),
+        phpls.get_symbol_data_for_uri(&uri),
+        phpls.get_references_for_uri(&uri),
+        phpls.get_class_hierarchy_for_uri(&uri),
+    ) {
+        (Some(key), Some(symbol_data), Some(reference_index), Some(hierarchy)) => {
+            (key, symbol_data, reference_index, hierarchy)
+        }
+        _ => {
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+
+    // Calls made through a parent class or an interface might end up here as well, and so
+    // might calls on subclasses, unless they override the method
+    let descendants = match key.class_name() {
+        Some(class) => hierarchy.get_descendants(class),
+        None => vec![],
+    };
+    let calls = get_related_keys(&symbol_data, &hierarchy, &key)
+        .iter()
+        .filter(|k| match k.class_name() {
+            Some(class) if descendants.contains(class) => get_declaring_key(&symbol_data, k) == key,
+            _ => true,
+        })
+        .flat_map(|k| reference_index.get_incoming_calls(k))
+        .collect();
+    let mut converter = LocationConverter::new(phpls);
+    let incoming = group_calls(&mut converter, calls, |c| &c.caller)
+        .into_iter()
+        .filter_map(|(caller, from_ranges)| {
+            Some(CallHierarchyIncomingCall {
+                from: get_call_hierarchy_item(&mut converter, &symbol_data, &caller)?,
+                from_ranges,
+            })
+        })
+        .collect();
+    completable.complete(Ok(Some(incoming)))
+}
+
+pub fn outgoing_calls(
+    phpls: &PHPLanguageServerInstance,
+    params: CallHierarchyOutgoingCallsParams,
+    completable: MethodCompletable<Option<Vec<CallHierarchyOutgoingCall>>, ()>,
+) {
+    let uri = params.item.uri.clone();
+    let (key, symbol_data, reference_index) = match (
+        params.item.data.as_ref().and_then(key_from_data),
+        phpls.get_symbol_data_for_uri(&uri),
+        phpls.get_references_for_uri(&uri),
+    ) {
+        (Some(key), Some(symbol_data), Some(reference_index)) => {
+            (key, symbol_data, reference_index)
+        }
+        _ => {
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+
+    let mut converter = LocationConverter::new(phpls);
+    let calls = reference_index.get_outgoing_calls(&key);
+    let outgoing = group_calls(&mut converter, calls, |c| &c.callee)
+        .into_iter()
+        .filter_map(|(callee, from_ranges)| {
+            let callee = get_declaring_key(&symbol_data, &callee);
+            Some(CallHierarchyOutgoingCall {
+                to: get_call_hierarchy_item(&mut converter, &symbol_data, &callee)?,
+                from_ranges,
+            })
+        })
+        .collect();
+    completable.complete(Ok(Some(outgoing)))
+}
+
+///
+/// Group calls by caller or callee, keeping the order they were found in
+///
+fn group_calls<F>(
+    converter: &mut LocationConverter,
+    calls: Vec<CallSite>,
+    group_by: F,
+) -> Vec<(ReferenceKey, Vec<Range>)>
+where
+    F: Fn(&CallSite) -> &ReferenceKey,
+{
+    let mut groups: Vec<(ReferenceKey, Vec<Range>)> = vec![];
+    for call in calls {
+        let range = converter.location(call.location.clone()).range;
+        let key = group_by(&call);
+        match groups.iter_mut().find(|(k, _)| k == key) {
+            Some((_, ranges)) => {
+                if !ranges.contains(&range) {
+                    ranges.push(range)
+                }
+            }
+            None => groups.push((key.clone(), vec![range])),
+        }
+    }
+    groups
+}
+
+///
+/// A method used on a subclass is recorded on the class declaring it, as that's where its
+/// outgoing calls are
+///
+fn get_declaring_key(symbol_data: &SymbolData, key: &ReferenceKey) -> ReferenceKey {
+    if let ReferenceKey::Method(class, name) = key {
+        let name = name.to_string();
+        if let Some(method) = get_class_members(symbol_data, class)
+            .into_iter()
+            .find(|m| m.kind == MemberKind::Method && m.name.eq_ignore_ascii_case(&name))
+        {
+            return ReferenceKey::method(&method.declared_in, &name);
+        }
+    }
+    key.clone()
+}
+
+fn get_call_hierarchy_item(
+    converter: &mut LocationConverter,
+    symbol_data: &SymbolData,
+    key: &ReferenceKey,
+) -> Option<CallHierarchyItem> {
+    let (name, kind, detail) = match key {
+        ReferenceKey::Method(class, name) => {
+            // The key has the lower cased name
+            let method = get_method(symbol_data, class, &name.to_string())?;
+            let name = method.read().unwrap().name.to_string();
+            let kind = if name.eq_ignore_ascii_case("__construct") {
+                SymbolKind::Constructor
+            } else {
+                SymbolKind::Method
+            };
+            let class_name = class.to_string().trim_start_matches('\\').to_string();
+            (name, kind, Some(class_name))
+        }
+        ReferenceKey::Function(fq_name) => {
+            let fq_name = fq_name.to_string();
+            let fq_name = fq_name.trim_start_matches('\\');
+            match fq_name.rfind('\\') {
+                Some(idx) => (
+                    fq_name[idx + 1..].to_string(),
+                    SymbolKind::Function,
+                    Some(fq_name[..idx].to_string()),
+                ),
+                None => (fq_name.to_string(), SymbolKind::Function, None),
+            }
+        }
+        _ => return None,
+    };
+    let location = converter.location(
+        get_declaration_locations(symbol_data, key)
+            .into_iter()
+            .next()?,
+    );
+    Some(CallHierarchyItem {
+        name,
+        kind,
+        tags: None,
+        detail,
+        uri: location.uri,
+        range: location.range,
+        selection_range: location.range,
+        data: Some(key_to_data(key)),
+    })
+}
+
+fn key_to_data(key: &ReferenceKey) -> Value {
+    match key {
+        ReferenceKey::Method(class, name) => json!({
+            "kind": "method",
+            "class": class.to_string(),
+            "name": name.to_string(),
+        }),
+        ReferenceKey::Function(fq_name) => json!({
+            "kind": "function",
+            "fq_name": fq_name.to_string(),
+        }),
+        _ => Value::Null,
+    }
+}
+
+fn key_from_data(data: &Value) -> Option<ReferenceKey> {
+    let field = |name: &str| data.get(name).and_then(|v| v.as_str());
+    match field("kind")? {
+        "method" => Some(ReferenceKey::Method(
+            FullyQualifiedName::from(field("class")?),
+            Name::from(field("name")?),
+        )),
+        "function" => Some(ReferenceKey::Function(FullyQualifiedName::from(
+            field("fq_name")?,
+        ))),
+        _ => None,
+    }
+}
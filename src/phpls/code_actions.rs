@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp::LspClientRpc,
+    lsp_types::{
+        ApplyWorkspaceEditParams, CodeAction, CodeActionKind, CodeActionOrCommand,
+        CodeActionParams, CodeActionResponse, Command, ExecuteCommandParams, TextEdit,
+        WorkspaceEdit,
+    },
+};
+use serde_json::Value;
+use url::Url;
+
+use super::{
+    extract::get_extract_actions, implement_methods::get_implement_methods_actions,
+    instance::PHPLanguageServerInstance, quick_fix::get_quick_fixes,
+};
+
+///
+/// Clients without support for code actions get them as plain commands. Those carry the
+/// edit to make, and the client sends it back to us with this command to get it applied.
+///
+pub const APPLY_EDIT_COMMAND: &str = "phpls.applyEdit";
+
+///
+/// Answer with code actions when the client supports them, and with commands otherwise
+///
+pub fn code_action(
+    phpls: &PHPLanguageServerInstance,
+    params: CodeActionParams,
+    completable: MethodCompletable<Option<CodeActionResponse>, ()>,
+) {
+    let actions = get_code_actions(phpls, &params);
+    let response = if phpls.supports_code_action_literals() {
+        actions
+            .into_iter()
+            .map(CodeActionOrCommand::CodeAction)
+            .collect()
+    } else {
+        actions
+            .into_iter()
+            .map(|a| CodeActionOrCommand::Command(action_to_command(a)))
+            .collect()
+    };
+    completable.complete(Ok(Some(response)));
+}
+
+///
+/// The code actions as commands, for the `code_action` of `LanguageServerHandling`. Requests
+/// are answered by `code_action` through the request handler.
+///
+pub fn code_action_commands(
+    phpls: &PHPLanguageServerInstance,
+    params: CodeActionParams,
+    completable: MethodCompletable<Vec<Command>, ()>,
+) {
+    let commands = get_code_actions(phpls, &params)
+        .into_iter()
+        .map(action_to_command)
+        .collect();
+    completable.complete(Ok(commands));
+}
+
+fn get_code_actions(
+    phpls: &PHPLanguageServerInstance,
+    params: &CodeActionParams,
+) -> Vec<CodeAction> {
+    let mut actions = vec![];
+    if is_kind_wanted(params, &CodeActionKind::QUICKFIX) {
+        actions.extend(get_quick_fixes(phpls, params));
+    }
+    if is_kind_wanted(params, &CodeActionKind::REFACTOR_REWRITE) {
+        actions.extend(get_implement_methods_actions(phpls, params));
+    }
+    if is_kind_wanted(params, &CodeActionKind::REFACTOR_EXTRACT) {
+        actions.extend(get_extract_actions(phpls, params));
+    }
+    eprintln!("code_action: {} actions", actions.len());
+    actions
+}
+
+pub fn execute_command(
+    phpls: &PHPLanguageServerInstance,
+    params: ExecuteCommandParams,
+    completable: MethodCompletable<Option<Value>, ()>,
+) {
+    if params.command != APPLY_EDIT_COMMAND {
+        completable.complete(Err(PHPLanguageServerInstance::error_not_available(())));
+        return;
+    }
+    let edit = match params
+        .arguments
+        .into_iter()
+        .next()
+        .map(serde_json::from_value::<WorkspaceEdit>)
+    {
+        Some(Ok(edit)) => edit,
+        _ => {
+            eprintln!("ERROR: {} without an edit", APPLY_EDIT_COMMAND);
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let mut client_handle = phpls.client();
+    let res = client_handle
+        .client()
+        .workspace_apply_edit(ApplyWorkspaceEditParams { label: None, edit });
+    eprintln!("applied edit: {:?}", res);
+    completable.complete(Ok(None));
+}
+
+///
+/// Check if the client asked for actions of `kind`, or didn't limit the kinds at all
+///
+pub fn is_kind_wanted(params: &CodeActionParams, kind: &CodeActionKind) -> bool {
+    match &params.context.only {
+        Some(only) => only.iter().any(|k| kind.as_str().starts_with(k.as_str())),
+        None => true,
+    }
+}
+
+///
+/// A code action of `kind` making `edits` in one document
+///
+pub fn edit_action(
+    title: String,
+    kind: CodeActionKind,
+    uri: &Url,
+    edits: Vec<TextEdit>,
+) -> CodeAction {
+    let mut changes = HashMap::new();
+    changes.insert(uri.clone(), edits);
+    CodeAction {
+        title,
+        kind: Some(kind),
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        }),
+        ..Default::default()
+    }
+}
+
+///
+/// The command getting the edit of `action` applied through `APPLY_EDIT_COMMAND`
+///
+fn action_to_command(action: CodeAction) -> Command {
+    if let Some(command) = action.command {
+        return command;
+    }
+    let edit = action
+        .edit
+        .and_then(|e| serde_json::to_value(e).ok())
+        .unwrap_or(Value::Null);
+    Command {
+        title: action.title,
+        command: APPLY_EDIT_COMMAND.to_string(),
+        arguments: Some(vec![edit]),
+    }
+}
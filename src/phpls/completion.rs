@@ -0,0 +1,763 @@
+use std::sync::Arc;
+
+use phpanalyzer::{
+    analysis::state::AnalysisState, autonodes::any::AnyNodeRef, issue::VoidEmitter,
+    symboldata::class::ClassType,
+    symbols::{FullyQualifiedName, Name},
+    types::union::DiscreteType,
+};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{
+        CompletionItem, CompletionItemKind, CompletionItemTag, CompletionList, Documentation,
+        MarkupContent, MarkupKind, Position, TextDocumentPositionParams,
+    },
+};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::codetree::symbol_index::{IndexedSymbolKind, SymbolIndex};
+
+use super::{
+    goto_type_definition::get_utype_for_node,
+    imports::FileImports,
+    instance::PHPLanguageServerInstance,
+    locations::{offset_to_position, position_to_offset},
+    members::{
+        get_base_class, get_class_members, get_method, is_member_visible, ClassMember, MemberKind,
+    },
+    signatures::{
+        format_class_documentation, format_class_signature, format_constant_documentation,
+        format_constant_signature, format_function_documentation, format_function_signature,
+        format_member_documentation, format_member_signature, format_method_documentation,
+        format_method_signature,
+    },
+    variables::get_scope_variables,
+};
+
+///
+/// What the user is in the middle of typing at the cursor
+///
+#[derive(Debug, PartialEq)]
+pub enum CompletionContext {
+    /// `$obj->pre` or `$obj?->pre`. `receiver` is the position of the last character
+    /// of the expression left of the arrow.
+    Member { receiver: Position, prefix: String },
+    /// `Foo::pre`, `self::pre`, `static::pre` or `parent::pre`. `class_position` is the
+    /// position of the last character of the class name. `dollar` is set for `Foo::$pre`,
+    /// where only static properties apply.
+    Static {
+        class_name: String,
+        class_position: Position,
+        prefix: String,
+        dollar: bool,
+    },
+    /// A bare identifier, which could be a class, function or constant
+    Name { prefix: String },
+    /// `$pre`. `dollar` is the position of the `$`.
+    Variable { dollar: Position, prefix: String },
+}
+
+/// Bare names can match a large part of the workspace, so we cut the list and let the
+/// client ask again as the user types
+const MAX_NAME_ITEMS: usize = 200;
+
+pub fn completion(
+    phpls: &PHPLanguageServerInstance,
+    params: TextDocumentPositionParams,
+    completable: MethodCompletable<CompletionList, ()>,
+) {
+    let list = get_completion_list(phpls, params);
+    if phpls.is_request_cancelled() {
+        completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+        return;
+    }
+    eprintln!("completion: {} items", list.items.len());
+    completable.complete(Ok(list));
+}
+
+fn get_completion_list(
+    phpls: &PHPLanguageServerInstance,
+    params: TextDocumentPositionParams,
+) -> CompletionList {
+    let uri = params.text_document.uri.clone();
+    let file = phpls.get_file_for_uri(&uri);
+    let line = match file
+        .as_ref()
+        .and_then(|f| f.get_line(params.position.line as usize))
+    {
+        Some(line) => line,
+        None => {
+            return CompletionList {
+                is_incomplete: false,
+                items: vec![],
+            }
+        }
+    };
+
+    let mut items = match get_completion_context(&line, params.position) {
+        Some(CompletionContext::Member { receiver, prefix }) => {
+            let mut receiver_params = params;
+            receiver_params.position = receiver;
+            match phpls.at_position(
+                receiver_params,
+                Box::new(move |node, state, path| {
+                    get_member_completion_items(node, state, path, &prefix)
+                }),
+            ) {
+                Ok((_, Some(items))) => items,
+                Ok((_, None)) => vec![],
+                Err(e) => {
+                    eprintln!("ERROR: {}", e);
+                    vec![]
+                }
+            }
+        }
+        Some(CompletionContext::Static {
+            class_name,
+            class_position,
+            prefix,
+            dollar,
+        }) => {
+            let mut class_params = params;
+            class_params.position = class_position;
+            match phpls.at_position(
+                class_params,
+                Box::new(move |_node, state, _path| {
+                    get_static_completion_items(state, &class_name, &prefix, dollar)
+                }),
+            ) {
+                Ok((_, Some(items))) => items,
+                Ok((_, None)) => vec![],
+                Err(e) => {
+                    eprintln!("ERROR: {}", e);
+                    vec![]
+                }
+            }
+        }
+        Some(CompletionContext::Name { prefix }) => {
+            let contents = file
+                .and_then(|f| f.get_contents().ok())
+                .map(|c| String::from_utf8_lossy(&c).to_string())
+                .unwrap_or_default();
+            let offset = position_to_offset(&contents, &params.position);
+            if is_in_string_or_comment(&contents, offset) {
+                return CompletionList {
+                    is_incomplete: false,
+                    items: vec![],
+                };
+            }
+            let imports = FileImports::from_contents(&contents);
+            let index = phpls
+                .get_symbol_index_for_uri(&uri)
+                .unwrap_or_else(|| Arc::new(SymbolIndex::new()));
+            let mut items = get_name_completion_items(&index, &imports, &prefix);
+            let is_incomplete = items.len() >= MAX_NAME_ITEMS;
+            attach_uri_to_data(&mut items, &uri);
+            return CompletionList {
+                is_incomplete,
+                items,
+            };
+        }
+        Some(CompletionContext::Variable { dollar, prefix }) => {
+            let mut dollar_params = params;
+            dollar_params.position = dollar;
+            match phpls.at_position(
+                dollar_params,
+                Box::new(move |_node, state, _path| get_variable_completion_items(state, &prefix)),
+            ) {
+                Ok((_, Some(items))) => items,
+                Ok((_, None)) => vec![],
+                Err(e) => {
+                    eprintln!("ERROR: {}", e);
+                    vec![]
+                }
+            }
+        }
+        None => vec![],
+    };
+    attach_uri_to_data(&mut items, &uri);
+    CompletionList {
+        is_incomplete: false,
+        items,
+    }
+}
+
+///
+/// Remember which document the items were made for, so that `resolve_completion_item`
+/// can find the right code tree
+///
+fn attach_uri_to_data(items: &mut Vec<CompletionItem>, uri: &Url) {
+    for item in items {
+        if let Some(Value::Object(data)) = &mut item.data {
+            data.insert("uri".to_string(), Value::String(uri.to_string()));
+        }
+    }
+}
+
+///
+/// Find out what kind of completion is relevant by looking at the text left of the cursor
+///
+pub fn get_completion_context(line: &str, position: Position) -> Option<CompletionContext> {
+    // Characters in positions are UTF-16 code units, both the cursor and the ones we return
+    let cursor = Position {
+        line: 0,
+        ..position
+    };
+    let end = position_to_offset(line, &cursor);
+    let at = |offset: usize| Position {
+        line: position.line,
+        character: offset_to_position(line, offset).character,
+    };
+    let before = &line[..end];
+    let prefix_start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_identifier_char(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(end);
+    let prefix = before[prefix_start..].to_string();
+    let rest = &before[..prefix_start];
+
+    // `Foo::$pre` is a static property, not a variable
+    let (scoped_rest, dollar) = match rest.strip_suffix('$') {
+        Some(before_dollar) if before_dollar.ends_with("::") => (before_dollar, true),
+        _ => (rest, false),
+    };
+
+    if !dollar && rest.ends_with('$') {
+        return Some(CompletionContext::Variable {
+            dollar: at(rest.len() - 1),
+            prefix,
+        });
+    }
+
+    if scoped_rest.ends_with("::") {
+        let before_colons = &scoped_rest[..scoped_rest.len() - 2];
+        let class_start = before_colons
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_identifier_char(*c) || *c == '\\')
+            .last()
+            .map(|(i, _)| i)?;
+        let class_name = before_colons[class_start..].to_string();
+        return Some(CompletionContext::Static {
+            class_name,
+            class_position: at(before_colons.len() - 1),
+            prefix,
+            dollar,
+        });
+    }
+
+    let arrow = if rest.ends_with("?->") {
+        "?->"
+    } else if rest.ends_with("->") {
+        "->"
+    } else if !prefix.is_empty()
+        && !prefix.starts_with(|c: char| c.is_numeric())
+        && !rest.ends_with(|c| c == '$' || c == '\\')
+    {
+        if follows_declaration_keyword(rest) {
+            // The user is naming something new
+            return None;
+        }
+        return Some(CompletionContext::Name { prefix });
+    } else {
+        return None;
+    };
+    let receiver_end = rest[..rest.len() - arrow.len()].trim_end().len();
+    if receiver_end == 0 {
+        return None;
+    }
+    Some(CompletionContext::Member {
+        receiver: at(receiver_end - 1),
+        prefix,
+    })
+}
+
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || !c.is_ascii()
+}
+
+///
+/// Check if the text left of a name ends with a keyword declaring that name
+///
+fn follows_declaration_keyword(rest: &str) -> bool {
+    let trimmed = rest.trim_end();
+    if trimmed.len() == rest.len() {
+        return false;
+    }
+    let word_start = trimmed
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_identifier_char(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(trimmed.len());
+    let before_word = &trimmed[..word_start];
+    if before_word.ends_with(|c| c == '$' || c == '\\' || c == '>' || c == ':') {
+        // `$class`, `$obj->function` and `Foo::class` are not keywords
+        return false;
+    }
+    let word = trimmed[word_start..].to_lowercase();
+    if (word == "function" || word == "const")
+        && before_word.trim_end().to_lowercase().ends_with("use")
+    {
+        // `use function Foo\bar;` imports a name
+        return false;
+    }
+    matches!(
+        word.as_str(),
+        "class" | "interface" | "trait" | "enum" | "function" | "fn" | "const" | "namespace"
+    )
+}
+
+///
+/// Check if `offset` is in a string, a comment or outside the PHP tags, by scanning the
+/// text before it. Like `FileImports`, this works on files that are being typed in.
+///
+fn is_in_string_or_comment(contents: &str, offset: usize) -> bool {
+    let bytes = &contents.as_bytes()[..offset.min(contents.len())];
+    let mut in_php = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if !in_php {
+            match find_bytes(rest, b"<?") {
+                Some(idx) => {
+                    in_php = true;
+                    i += idx + 2;
+                    continue;
+                }
+                None => return true,
+            }
+        }
+        if rest.starts_with(b"?>") {
+            in_php = false;
+            i += 2;
+        } else if rest.starts_with(b"//") || (rest[0] == b'#' && !rest.starts_with(b"#[")) {
+            match rest.iter().position(|c| *c == b'\n') {
+                Some(idx) => i += idx + 1,
+                None => return true,
+            }
+        } else if rest.starts_with(b"/*") {
+            match find_bytes(&rest[2..], b"*/") {
+                Some(idx) => i += idx + 4,
+                None => return true,
+            }
+        } else if rest.starts_with(b"<<<") {
+            match skip_heredoc(rest) {
+                Some(len) => i += len,
+                None => return true,
+            }
+        } else if rest[0] == b'\'' || rest[0] == b'"' || rest[0] == b'`' {
+            let quote = rest[0];
+            i += 1;
+            loop {
+                match bytes.get(i) {
+                    None => return true,
+                    Some(b'\\') => i += 2,
+                    Some(c) if *c == quote => {
+                        i += 1;
+                        break;
+                    }
+                    Some(_) => i += 1,
+                }
+            }
+        } else {
+            i += 1;
+        }
+    }
+    !in_php
+}
+
+///
+/// Get the length of the heredoc or nowdoc starting `text`, up to and including its closing
+/// identifier. `None` if it isn't closed.
+///
+fn skip_heredoc(text: &[u8]) -> Option<usize> {
+    fn is_label_byte(c: &u8) -> bool {
+        c.is_ascii_alphanumeric() || *c == b'_' || *c >= 0x80
+    }
+
+    let mut start = 3;
+    while text.get(start) == Some(&b' ') || text.get(start) == Some(&b'\t') {
+        start += 1;
+    }
+    if text.get(start) == Some(&b'\'') || text.get(start) == Some(&b'"') {
+        start += 1;
+    }
+    let label_len = text[start..]
+        .iter()
+        .take_while(|c| is_label_byte(c))
+        .count();
+    if label_len == 0 {
+        return None;
+    }
+    let label = &text[start..start + label_len];
+
+    let mut line_start = start + label_len;
+    while let Some(idx) = text[line_start..].iter().position(|c| *c == b'\n') {
+        line_start += idx + 1;
+        let indent = text[line_start..]
+            .iter()
+            .take_while(|c| **c == b' ' || **c == b'\t')
+            .count();
+        let candidate = &text[line_start + indent..];
+        if candidate.starts_with(label) && !candidate.get(label.len()).map_or(false, is_label_byte)
+        {
+            return Some(line_start + indent + label.len());
+        }
+    }
+    None
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+///
+/// Callback for `at_position` with the cursor on the receiver of a member access
+///
+fn get_member_completion_items(
+    node: AnyNodeRef,
+    state: &mut AnalysisState,
+    path: &Vec<AnyNodeRef>,
+    prefix: &str,
+) -> Vec<CompletionItem> {
+    let emitter = VoidEmitter::new();
+    let mut receiver_type = None;
+    let mut candidates = vec![node];
+    candidates.extend(path.iter().rev().cloned());
+    for candidate in &candidates {
+        let is_member_expression = match candidate {
+            // The name of a member in `$a->b->` is not the receiver, the member access is
+            AnyNodeRef::Name(_) => continue,
+            AnyNodeRef::MemberAccessExpression(_)
+            | AnyNodeRef::MemberCallExpression(_)
+            | AnyNodeRef::NullsafeMemberAccessExpression(_)
+            | AnyNodeRef::NullsafeMemberCallExpression(_) => true,
+            _ => false,
+        };
+        if let Some(utype) = get_utype_for_node(candidate, state, &emitter) {
+            receiver_type = Some(utype);
+            break;
+        }
+        // Don't continue upwards into the member access we're completing
+        if is_member_expression {
+            break;
+        }
+    }
+    let receiver_type = if let Some(t) = receiver_type {
+        t
+    } else {
+        eprintln!("Fant ikke type for mottaker av member-access");
+        return vec![];
+    };
+
+    let from_class = state.in_class.as_ref().map(|c| c.get_fq_name());
+    let mut items = vec![];
+    for dtype in receiver_type.types {
+        let fq_name = match dtype {
+            DiscreteType::Named(_, fq_name) => fq_name,
+            _ => continue,
+        };
+        for member in get_class_members(&state.symbol_data, &fq_name) {
+            if member.kind == MemberKind::Constant {
+                continue;
+            }
+            if !is_member_visible(&state.symbol_data, &member, from_class.as_ref()) {
+                continue;
+            }
+            if !matches_prefix(&member.name, prefix) {
+                continue;
+            }
+            if items
+                .iter()
+                .any(|i: &CompletionItem| i.label == member.name)
+            {
+                continue;
+            }
+            items.push(member_to_completion_item(&member, false));
+        }
+    }
+    items
+}
+
+///
+/// Callback for `at_position` with the cursor on the class name left of `::`
+///
+fn get_static_completion_items(
+    state: &mut AnalysisState,
+    class_name: &str,
+    prefix: &str,
+    dollar: bool,
+) -> Vec<CompletionItem> {
+    let from_class = state.in_class.as_ref().map(|c| c.get_fq_name());
+    let is_parent = class_name.eq_ignore_ascii_case("parent");
+    let fq_name = match class_name.to_lowercase().as_str() {
+        "self" | "static" => from_class.clone(),
+        "parent" => from_class
+            .as_ref()
+            .and_then(|c| get_base_class(&state.symbol_data, c)),
+        _ => Some(state.get_fq_symbol_name_from_local_name(&Name::from(class_name))),
+    };
+    let fq_name = if let Some(fq_name) = fq_name {
+        fq_name
+    } else {
+        eprintln!("Fant ikke klassen {} for ::-completion", class_name);
+        return vec![];
+    };
+
+    let mut items = vec![];
+    for member in get_class_members(&state.symbol_data, &fq_name) {
+        // parent::method() is the usual way of calling an overridden instance method
+        let reachable = member.is_static || (is_parent && member.kind == MemberKind::Method);
+        if !reachable || !is_member_visible(&state.symbol_data, &member, from_class.as_ref()) {
+            continue;
+        }
+        if dollar && member.kind != MemberKind::Property {
+            continue;
+        }
+        if !matches_prefix(&member.name, prefix) {
+            continue;
+        }
+        let mut item = member_to_completion_item(&member, true);
+        if dollar {
+            // The `$` is already typed
+            item.filter_text = Some(member.name.clone());
+            item.insert_text = Some(member.name.clone());
+        }
+        items.push(item);
+    }
+    if !dollar && matches_prefix("class", prefix) {
+        items.push(CompletionItem {
+            label: "class".to_string(),
+            kind: Some(CompletionItemKind::Keyword),
+            detail: Some(format!("{}", fq_name)),
+            ..CompletionItem::default()
+        });
+    }
+    items
+}
+
+///
+/// Complete classes, functions and constants from the workspace. Symbols from other
+/// namespaces get an extra edit adding the `use` statement they need.
+///
+fn get_name_completion_items(
+    index: &SymbolIndex,
+    imports: &FileImports,
+    prefix: &str,
+) -> Vec<CompletionItem> {
+    let mut items = vec![];
+    for symbol in index.find_by_prefix(prefix).iter().take(MAX_NAME_ITEMS) {
+        let kind = match symbol.kind {
+            IndexedSymbolKind::Class | IndexedSymbolKind::Trait => CompletionItemKind::Class,
+            IndexedSymbolKind::Interface => CompletionItemKind::Interface,
+            IndexedSymbolKind::Function => CompletionItemKind::Function,
+            IndexedSymbolKind::Constant => CompletionItemKind::Constant,
+        };
+        let (insert_text, additional_text_edits) = if imports.is_reachable(symbol) {
+            (None, None)
+        } else if imports.is_name_taken(symbol, index) {
+            // Importing would collide with another import, so refer to it fully qualified
+            (Some(format!("\\{}", symbol.fq_name)), None)
+        } else {
+            (
+                None,
+                Some(vec![
+                    imports.get_import_edit(symbol.kind.into(), &symbol.fq_name)
+                ]),
+            )
+        };
+        let data_kind = match symbol.kind {
+            IndexedSymbolKind::Function => "function",
+            IndexedSymbolKind::Constant => "constant",
+            _ => "class",
+        };
+        items.push(CompletionItem {
+            label: symbol.name.clone(),
+            kind: Some(kind),
+            detail: Some(symbol.fq_name.clone()),
+            insert_text,
+            additional_text_edits,
+            data: Some(json!({ "kind": data_kind, "name": symbol.fq_name })),
+            ..CompletionItem::default()
+        });
+    }
+    items
+}
+
+///
+/// Callback for `at_position` with the cursor on a `$`. The analysis state has at this
+/// point only seen what is in scope before the cursor.
+///
+fn get_variable_completion_items(state: &mut AnalysisState, prefix: &str) -> Vec<CompletionItem> {
+    let mut items = vec![];
+    if state.in_class.is_some() && matches_prefix("this", prefix) {
+        let this_type = state.in_class.as_ref().map(|c| c.get_fq_name().to_string());
+        items.push(CompletionItem {
+            label: "$this".to_string(),
+            kind: Some(CompletionItemKind::Variable),
+            detail: this_type,
+            filter_text: Some("this".to_string()),
+            insert_text: Some("this".to_string()),
+            ..CompletionItem::default()
+        });
+    }
+
+    for (name, var_type) in get_scope_variables(state) {
+        if name == "this" || !matches_prefix(&name, prefix) {
+            continue;
+        }
+        items.push(CompletionItem {
+            label: format!("${}", name),
+            kind: Some(CompletionItemKind::Variable),
+            detail: var_type,
+            filter_text: Some(name.clone()),
+            // The `$` is already typed
+            insert_text: Some(name),
+            ..CompletionItem::default()
+        });
+    }
+    items
+}
+
+pub fn matches_prefix(name: &str, prefix: &str) -> bool {
+    name.to_lowercase().starts_with(&prefix.to_lowercase())
+}
+
+///
+/// Convert a member to a completion item. `scoped` is true when completing after `::`,
+/// where static properties are written with their `$`.
+///
+pub fn member_to_completion_item(member: &ClassMember, scoped: bool) -> CompletionItem {
+    let (kind, label, data_kind) = match member.kind {
+        MemberKind::Method => (CompletionItemKind::Method, member.name.clone(), "method"),
+        MemberKind::Property if scoped => (
+            CompletionItemKind::Property,
+            format!("${}", member.name),
+            "property",
+        ),
+        MemberKind::Property => (CompletionItemKind::Property, member.name.clone(), "property"),
+        MemberKind::Constant => (CompletionItemKind::Constant, member.name.clone(), "constant"),
+    };
+    CompletionItem {
+        label,
+        kind: Some(kind),
+        detail: member.type_description.clone(),
+        data: Some(json!({
+            "kind": data_kind,
+            "class": member.declared_in.to_string(),
+            "name": member.name,
+        })),
+        ..CompletionItem::default()
+    }
+}
+
+///
+/// Fill in the signature and documentation of an item from `completion`. This is left
+/// out of the initial list, as rendering it for every item is a waste on large workspaces.
+///
+pub fn resolve_completion_item(
+    phpls: &PHPLanguageServerInstance,
+    item: CompletionItem,
+    completable: MethodCompletable<CompletionItem, ()>,
+) {
+    let mut item = item;
+    if let Some((detail, documentation, deprecated)) = get_item_details(phpls, &item) {
+        item.detail = Some(detail);
+        if !documentation.is_empty() {
+            item.documentation = Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: documentation,
+            }));
+        }
+        if deprecated {
+            item.tags = Some(vec![CompletionItemTag::Deprecated]);
+        }
+    }
+    completable.complete(Ok(item));
+}
+
+///
+/// Look up the symbol described by the items `data`, and get the detail, markdown
+/// documentation and whether it is deprecated
+///
+fn get_item_details(
+    phpls: &PHPLanguageServerInstance,
+    item: &CompletionItem,
+) -> Option<(String, String, bool)> {
+    let data = item.data.as_ref()?;
+    let field = |name: &str| data.get(name).and_then(|v| v.as_str());
+    let uri = Url::parse(field("uri")?).ok()?;
+    let symbol_data = phpls.get_symbol_data_for_uri(&uri)?;
+    let name = field("name")?;
+
+    match field("kind")? {
+        "method" => {
+            let class_name = FullyQualifiedName::from(field("class")?);
+            let method = get_method(&symbol_data, &class_name, name)?;
+            let method = method.read().unwrap();
+            Some((
+                format_method_signature(&method),
+                format_method_documentation(&method),
+                method.deprecated.is_some(),
+            ))
+        }
+        "function" => {
+            let functions = symbol_data.functions.read().unwrap();
+            let function = functions.get(&FullyQualifiedName::from(name))?;
+            let function = function.read().unwrap();
+            Some((
+                format_function_signature(&function),
+                format_function_documentation(&function),
+                function.deprecated.is_some(),
+            ))
+        }
+        kind @ ("property" | "constant") if field("class").is_some() => {
+            let class_name = FullyQualifiedName::from(field("class")?);
+            let kind = match kind {
+                "property" => MemberKind::Property,
+                _ => MemberKind::Constant,
+            };
+            let member = get_class_members(&symbol_data, &class_name)
+                .into_iter()
+                .find(|m| m.kind == kind && m.name == name)?;
+            Some((
+                format_member_signature(&member),
+                format_member_documentation(&member),
+                member.deprecated.is_some(),
+            ))
+        }
+        "constant" => {
+            let fq_name = FullyQualifiedName::from(name);
+            let constants = symbol_data.constants.read().unwrap();
+            let constant = constants.get(&fq_name)?;
+            Some((
+                format_constant_signature(constant, &fq_name),
+                format_constant_documentation(constant),
+                constant.deprecated.is_some(),
+            ))
+        }
+        "class" => {
+            let fq_name = FullyQualifiedName::from(name);
+            let class = symbol_data.get_class(&fq_name)?;
+            let class = class.read().unwrap();
+            let deprecated = match &*class {
+                ClassType::Class(c) => c.deprecated.is_some(),
+                ClassType::Interface(i) => i.deprecated.is_some(),
+                ClassType::Trait(t) => t.deprecated.is_some(),
+                ClassType::None => false,
+            };
+            Some((
+                format_class_signature(&class, &fq_name)?,
+                format_class_documentation(&class),
+                deprecated,
+            ))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,217 @@
+use std::ffi::OsString;
+
+use phpanalyzer::{autonodes::any::AnyNodeRef, symboldata::FileLocation};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{DocumentHighlight, DocumentHighlightKind, TextDocumentPositionParams},
+};
+
+use crate::codetree::references::{get_reference_at_cursor, ReferenceKey};
+
+use super::{
+    instance::PHPLanguageServerInstance,
+    locations::{range_to_lsp_range, LocationConverter},
+    references::{get_declaration_locations, get_related_keys},
+    variables::{
+        find_variable_scope, get_local_variable_at_cursor, get_variable_occurrences,
+        VariableOccurrence,
+    },
+};
+
+#[derive(Clone)]
+enum HighlightTarget {
+    Variable(Vec<VariableOccurrence>),
+    Symbol(ReferenceKey),
+}
+
+pub fn document_highlight(
+    phpls: &PHPLanguageServerInstance,
+    params: TextDocumentPositionParams,
+    completable: MethodCompletable<Vec<DocumentHighlight>, ()>,
+) {
+    let uri = params.text_document.uri.clone();
+    let file = match phpls.get_file_for_uri(&uri) {
+        Some(file) => file,
+        None => {
+            completable.complete(Ok(vec![]));
+            return;
+        }
+    };
+    let cb_source = file.get_contents().unwrap_or_default();
+    let source = String::from_utf8_lossy(&cb_source).to_string();
+
+    let target = match phpls.at_position(
+        params,
+        Box::new(move |node, state, path| {
+            if let Some(variable) = get_variable_at_cursor(&node, path, &cb_source) {
+                return Some(variable);
+            }
+            get_reference_at_cursor(&node, path, state, &cb_source)
+                .map(|(key, _)| HighlightTarget::Symbol(key))
+        }),
+    ) {
+        Ok((_, Some(Some(target)))) => target,
+        Ok(_) => {
+            completable.complete(Ok(vec![]));
+            return;
+        }
+        Err(_) if phpls.is_request_cancelled() => {
+            completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+            return;
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            completable.complete(Ok(vec![]));
+            return;
+        }
+    };
+
+    let highlights = match target {
+        HighlightTarget::Variable(occurrences) => occurrences
+            .iter()
+            .map(|o| DocumentHighlight {
+                range: range_to_lsp_range(&source, &o.range),
+                kind: Some(if o.is_write {
+                    DocumentHighlightKind::Write
+                } else {
+                    DocumentHighlightKind::Read
+                }),
+            })
+            .collect(),
+        HighlightTarget::Symbol(key) => {
+            let file_name = file.fq_file_name.as_os_str().to_os_string();
+            let analyzer = file.get_analyzer();
+            let root = analyzer
+                .as_ref()
+                .and_then(|a| a.tree.as_ref())
+                .map(|tree| tree.as_any());
+            get_symbol_highlights(phpls, &uri, &key, &file_name, root.as_ref())
+        }
+    };
+    completable.complete(Ok(highlights));
+}
+
+///
+/// If the cursor is on a local variable, get every use of it in the enclosing function
+///
+fn get_variable_at_cursor(
+    node: &AnyNodeRef,
+    path: &Vec<AnyNodeRef>,
+    source: &[u8],
+) -> Option<HighlightTarget> {
+    let variable = get_local_variable_at_cursor(node, path, source)?;
+    let scope = find_variable_scope(&variable.path)?;
+    Some(HighlightTarget::Variable(get_variable_occurrences(
+        &scope,
+        &variable.name,
+        source,
+    )))
+}
+
+///
+/// Get the uses and the declaration of a symbol in the current file
+///
+fn get_symbol_highlights(
+    phpls: &PHPLanguageServerInstance,
+    uri: &url::Url,
+    key: &ReferenceKey,
+    file_name: &OsString,
+    root: Option<&AnyNodeRef>,
+) -> Vec<DocumentHighlight> {
+    let (symbol_data, reference_index, hierarchy) = match (
+        phpls.get_symbol_data_for_uri(uri),
+        phpls.get_references_for_uri(uri),
+        phpls.get_class_hierarchy_for_uri(uri),
+    ) {
+        (Some(symbol_data), Some(reference_index), Some(hierarchy)) => {
+            (symbol_data, reference_index, hierarchy)
+        }
+        _ => return vec![],
+    };
+
+    let mut converter = LocationConverter::new(phpls);
+    let mut highlights: Vec<DocumentHighlight> = vec![];
+    for key in get_related_keys(&symbol_data, &hierarchy, key) {
+        for location in reference_index.get(&key) {
+            if &location.uri != file_name {
+                continue;
+            }
+            let kind = if root.map_or(false, |root| is_assigned_to(&location, root)) {
+                DocumentHighlightKind::Write
+            } else {
+                DocumentHighlightKind::Read
+            };
+            highlights.push(DocumentHighlight {
+                range: converter.location(location).range,
+                kind: Some(kind),
+            });
+        }
+        for location in get_declaration_locations(&symbol_data, &key) {
+            if &location.uri != file_name {
+                continue;
+            }
+            highlights.push(DocumentHighlight {
+                range: converter.location(location).range,
+                kind: Some(DocumentHighlightKind::Write),
+            });
+        }
+    }
+    highlights
+}
+
+///
+/// Check if the symbol at `location` is assigned to, as in `$this->foo = 42`. That is when
+/// the expression it is named in is the left side of an assignment.
+///
+fn is_assigned_to(location: &FileLocation, root: &AnyNodeRef) -> bool {
+    let mut nodes = vec![root.clone()];
+    loop {
+        let range = nodes[nodes.len() - 1].range();
+        if range.start_point.row == location.start.line as usize
+            && range.start_point.column == location.start.column as usize
+            && range.end_point.row == location.end.line as usize
+            && range.end_point.column == location.end.column as usize
+        {
+            break;
+        }
+        let child = nodes[nodes.len() - 1].children_any().into_iter().find(|c| {
+            let range = c.range();
+            (range.start_point.row, range.start_point.column)
+                <= (location.start.line as usize, location.start.column as usize)
+                && (range.end_point.row, range.end_point.column)
+                    >= (location.end.line as usize, location.end.column as usize)
+        });
+        match child {
+            Some(child) => nodes.push(child),
+            None => return false,
+        }
+    }
+
+    // Up from the name to the expression it is part of
+    let mut idx = nodes.len() - 1;
+    while idx > 0
+        && matches!(
+            nodes[idx],
+            AnyNodeRef::Name(_) | AnyNodeRef::VariableName(_) | AnyNodeRef::QualifiedName(_)
+        )
+    {
+        idx -= 1;
+    }
+    if idx == 0 {
+        return false;
+    }
+    let expression = &nodes[idx];
+    let parent = &nodes[idx - 1];
+    let is_first_child = parent
+        .children_any()
+        .first()
+        .map(|c| c.range() == expression.range())
+        .unwrap_or(false);
+
+    match parent {
+        AnyNodeRef::AssignmentExpression(_)
+        | AnyNodeRef::AugmentedAssignmentExpression(_)
+        | AnyNodeRef::ReferenceAssignmentExpression(_) => is_first_child,
+        _ => false,
+    }
+}
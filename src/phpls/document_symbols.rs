@@ -0,0 +1,305 @@
+use phpanalyzer::autonodes::any::AnyNodeRef;
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{
+        DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Location,
+        SymbolInformation, SymbolKind,
+    },
+};
+use url::Url;
+
+use crate::{codetree::references::node_text, phpparser::phpfile::PHPFile};
+
+use super::{instance::PHPLanguageServerInstance, locations::range_to_lsp_range};
+
+///
+/// Answer with the outline as a tree when the client supports it, and as a flat list where
+/// the nesting is kept through `container_name` otherwise
+///
+pub fn document_symbols(
+    phpls: &PHPLanguageServerInstance,
+    params: DocumentSymbolParams,
+    completable: MethodCompletable<DocumentSymbolResponse, ()>,
+) {
+    let uri = params.text_document.uri;
+    let symbols = match phpls.get_file_for_uri(&uri) {
+        Some(file) => get_document_symbols(&file),
+        None => vec![],
+    };
+    let response = if phpls.supports_hierarchical_document_symbols() {
+        DocumentSymbolResponse::Nested(symbols)
+    } else {
+        DocumentSymbolResponse::Flat(flatten_document_symbols(&uri, symbols, None))
+    };
+    completable.complete(Ok(response));
+}
+
+///
+/// The flat outline, for the server trait, which only lets us answer with
+/// `SymbolInformation`. Requests are answered by `document_symbols` through the request
+/// handler.
+///
+pub fn flat_document_symbols(
+    phpls: &PHPLanguageServerInstance,
+    params: DocumentSymbolParams,
+    completable: MethodCompletable<Vec<SymbolInformation>, ()>,
+) {
+    let uri = params.text_document.uri;
+    let symbols = match phpls.get_file_for_uri(&uri) {
+        Some(file) => get_document_symbols(&file),
+        None => vec![],
+    };
+    completable.complete(Ok(flatten_document_symbols(&uri, symbols, None)));
+}
+
+///
+/// Build the outline of a file from its syntax tree
+///
+pub fn get_document_symbols(file: &PHPFile) -> Vec<DocumentSymbol> {
+    let analyzer = if let Some(analyzer) = file.get_analyzer() {
+        analyzer
+    } else {
+        return vec![];
+    };
+    let tree = if let Some(tree) = &analyzer.tree {
+        tree
+    } else {
+        return vec![];
+    };
+    let source = String::from_utf8_lossy(&file.get_contents().unwrap_or_default()).to_string();
+
+    let mut symbols: Vec<DocumentSymbol> = vec![];
+    // A `namespace Foo;` without braces owns everything up to the next namespace
+    let mut open_namespace: Option<DocumentSymbol> = None;
+
+    for child in tree.as_any().children_any() {
+        if let AnyNodeRef::NamespaceDefinition(_) = child {
+            symbols.extend(open_namespace.take());
+            let namespace = get_namespace_symbol(&child, &source);
+            if has_child(&child, |c| matches!(c, AnyNodeRef::CompoundStatement(_))) {
+                symbols.push(namespace);
+            } else {
+                open_namespace = Some(namespace);
+            }
+            continue;
+        }
+        let found = get_statement_symbols(&child, &source);
+        match &mut open_namespace {
+            Some(namespace) => {
+                if let Some(last) = found.last() {
+                    namespace.range.end = last.range.end;
+                }
+                namespace.children.get_or_insert_with(Vec::new).extend(found);
+            }
+            None => symbols.extend(found),
+        }
+    }
+    symbols.extend(open_namespace.take());
+    symbols
+}
+
+fn get_namespace_symbol(node: &AnyNodeRef, source: &str) -> DocumentSymbol {
+    let name_node = find_child(node, |c| matches!(c, AnyNodeRef::NamespaceName(_)));
+    let children = find_child(node, |c| matches!(c, AnyNodeRef::CompoundStatement(_)))
+        .map(|body| {
+            body.children_any()
+                .iter()
+                .flat_map(|c| get_statement_symbols(c, source))
+                .collect()
+        })
+        .unwrap_or_default();
+    let name = name_node
+        .as_ref()
+        .map(|n| node_text(&n.range(), source.as_bytes()).to_string())
+        .unwrap_or_else(|| "(global)".to_string());
+    new_symbol(
+        name,
+        SymbolKind::Namespace,
+        node,
+        name_node.as_ref(),
+        children,
+        source,
+    )
+}
+
+///
+/// Get the symbols declared by a top level statement
+///
+fn get_statement_symbols(node: &AnyNodeRef, source: &str) -> Vec<DocumentSymbol> {
+    let kind = match node {
+        AnyNodeRef::ClassDeclaration(_) => SymbolKind::Class,
+        AnyNodeRef::InterfaceDeclaration(_) => SymbolKind::Interface,
+        // The protocol has no kind for traits
+        AnyNodeRef::TraitDeclaration(_) => SymbolKind::Class,
+        AnyNodeRef::EnumDeclaration(_) => SymbolKind::Enum,
+        AnyNodeRef::FunctionDefinition(_) => {
+            return named_symbol(node, SymbolKind::Function, source, vec![])
+                .into_iter()
+                .collect()
+        }
+        AnyNodeRef::ConstDeclaration(_) => {
+            return get_const_symbols(node, SymbolKind::Constant, source)
+        }
+        _ => return vec![],
+    };
+    let children = find_child(node, |c| {
+        matches!(
+            c,
+            AnyNodeRef::DeclarationList(_) | AnyNodeRef::EnumDeclarationList(_)
+        )
+    })
+    .map(|body| {
+        body.children_any()
+            .iter()
+            .flat_map(|c| get_member_symbols(c, source))
+            .collect()
+    })
+    .unwrap_or_default();
+    named_symbol(node, kind, source, children)
+        .into_iter()
+        .collect()
+}
+
+///
+/// Get the symbols declared by a statement in the body of a class, interface, trait or enum
+///
+fn get_member_symbols(node: &AnyNodeRef, source: &str) -> Vec<DocumentSymbol> {
+    match node {
+        AnyNodeRef::MethodDeclaration(_) => {
+            let kind = match find_child(node, |c| matches!(c, AnyNodeRef::Name(_))) {
+                Some(name)
+                    if node_text(&name.range(), source.as_bytes())
+                        .eq_ignore_ascii_case("__construct") =>
+                {
+                    SymbolKind::Constructor
+                }
+                _ => SymbolKind::Method,
+            };
+            named_symbol(node, kind, source, vec![]).into_iter().collect()
+        }
+        AnyNodeRef::PropertyDeclaration(_) => node
+            .children_any()
+            .iter()
+            .filter(|c| matches!(c, AnyNodeRef::PropertyElement(_)))
+            .filter_map(|element| {
+                let name_node =
+                    find_child(element, |c| matches!(c, AnyNodeRef::VariableName(_)))?;
+                let name = node_text(&name_node.range(), source.as_bytes()).to_string();
+                Some(new_symbol(
+                    name,
+                    SymbolKind::Property,
+                    element,
+                    Some(&name_node),
+                    vec![],
+                    source,
+                ))
+            })
+            .collect(),
+        AnyNodeRef::ConstDeclaration(_) => get_const_symbols(node, SymbolKind::Constant, source),
+        AnyNodeRef::EnumCase(_) => named_symbol(node, SymbolKind::EnumMember, source, vec![])
+            .into_iter()
+            .collect(),
+        _ => vec![],
+    }
+}
+
+///
+/// `const A = 1, B = 2;` declares one symbol per element
+///
+fn get_const_symbols(node: &AnyNodeRef, kind: SymbolKind, source: &str) -> Vec<DocumentSymbol> {
+    node.children_any()
+        .iter()
+        .filter(|c| matches!(c, AnyNodeRef::ConstElement(_)))
+        .filter_map(|element| named_symbol(element, kind, source, vec![]))
+        .collect()
+}
+
+///
+/// Create a symbol for a node whose name is found in its `Name` child
+///
+fn named_symbol(
+    node: &AnyNodeRef,
+    kind: SymbolKind,
+    source: &str,
+    children: Vec<DocumentSymbol>,
+) -> Option<DocumentSymbol> {
+    let name_node = find_child(node, |c| matches!(c, AnyNodeRef::Name(_)))?;
+    let name = node_text(&name_node.range(), source.as_bytes()).to_string();
+    Some(new_symbol(
+        name,
+        kind,
+        node,
+        Some(&name_node),
+        children,
+        source,
+    ))
+}
+
+#[allow(deprecated)]
+fn new_symbol(
+    name: String,
+    kind: SymbolKind,
+    node: &AnyNodeRef,
+    name_node: Option<&AnyNodeRef>,
+    children: Vec<DocumentSymbol>,
+    source: &str,
+) -> DocumentSymbol {
+    let range = range_to_lsp_range(source, &node.range());
+    let selection_range = name_node
+        .map(|n| range_to_lsp_range(source, &n.range()))
+        .unwrap_or(range);
+    DocumentSymbol {
+        name,
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range,
+        children: if children.is_empty() {
+            None
+        } else {
+            Some(children)
+        },
+    }
+}
+
+fn find_child<F>(node: &AnyNodeRef, predicate: F) -> Option<AnyNodeRef>
+where
+    F: Fn(&AnyNodeRef) -> bool,
+{
+    node.children_any().into_iter().find(|c| predicate(c))
+}
+
+fn has_child<F>(node: &AnyNodeRef, predicate: F) -> bool
+where
+    F: Fn(&AnyNodeRef) -> bool,
+{
+    find_child(node, predicate).is_some()
+}
+
+///
+/// Convert a symbol tree to the flat representation, where each symbol names its parent
+///
+#[allow(deprecated)]
+pub fn flatten_document_symbols(
+    uri: &Url,
+    symbols: Vec<DocumentSymbol>,
+    container_name: Option<String>,
+) -> Vec<SymbolInformation> {
+    let mut result = vec![];
+    for symbol in symbols {
+        result.push(SymbolInformation {
+            name: symbol.name.clone(),
+            kind: symbol.kind,
+            tags: symbol.tags,
+            deprecated: None,
+            location: Location::new(uri.clone(), symbol.range),
+            container_name: container_name.clone(),
+        });
+        if let Some(children) = symbol.children {
+            result.extend(flatten_document_symbols(uri, children, Some(symbol.name)));
+        }
+    }
+    result
+}
@@ -0,0 +1,532 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use phpanalyzer::{analysis::state::AnalysisState, autonodes::any::AnyNodeRef, issue::VoidEmitter};
+use rust_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionParams, Range, TextDocumentPositionParams, TextEdit,
+};
+
+use crate::codetree::{references::node_text, symbol_index::SymbolIndex};
+
+use super::{
+    code_actions::edit_action,
+    goto_type_definition::get_utype_for_node,
+    imports::{FileImports, TypeNames},
+    instance::PHPLanguageServerInstance,
+    locations::{offset_to_position, position_to_offset},
+    variables::{find_variable_scope, get_variable_occurrences},
+};
+
+const INDENT: &str = "    ";
+const EXTRACTED_METHOD_NAME: &str = "extractedMethod";
+const EXTRACTED_VARIABLE_NAME: &str = "extracted";
+
+///
+/// Offer to extract the selected statements into a method, or the selected expression into
+/// a variable
+///
+pub fn get_extract_actions(
+    phpls: &PHPLanguageServerInstance,
+    params: &CodeActionParams,
+) -> Vec<CodeAction> {
+    let uri = params.text_document.uri.clone();
+    let source = match phpls
+        .get_file_for_uri(&uri)
+        .and_then(|f| f.get_contents().ok())
+    {
+        Some(source) => String::from_utf8_lossy(&source).to_string(),
+        None => return vec![],
+    };
+
+    // Whitespace around the selection doesn't count
+    let start = position_to_offset(&source, &params.range.start);
+    let end = position_to_offset(&source, &params.range.end);
+    if start >= end {
+        return vec![];
+    }
+    let selected = &source[start..end];
+    let start = start + (selected.len() - selected.trim_start().len());
+    let end = end - (selected.len() - selected.trim_end().len());
+    if start >= end {
+        return vec![];
+    }
+
+    let index = phpls
+        .get_symbol_index_for_uri(&uri)
+        .unwrap_or_else(|| Arc::new(SymbolIndex::new()));
+
+    let position = TextDocumentPositionParams {
+        text_document: params.text_document.clone(),
+        position: offset_to_position(&source, start),
+    };
+    let actions = match phpls.at_position(
+        position,
+        Box::new(move |node, state, path| {
+            let mut nodes = path.clone();
+            nodes.push(node);
+            let mut actions = vec![];
+            actions.extend(extract_variable(&nodes, state, &index, &source, start, end));
+            actions.extend(extract_method(&nodes, &source, start, end));
+            actions
+        }),
+    ) {
+        Ok((_, Some(actions))) => actions,
+        Ok(_) => vec![],
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            vec![]
+        }
+    };
+    actions
+        .into_iter()
+        .map(|(title, edits)| edit_action(title, CodeActionKind::REFACTOR_EXTRACT, &uri, edits))
+        .collect()
+}
+
+///
+/// Extract the expression spanning `start..end` into a variable assigned just before the
+/// statement it is part of
+///
+fn extract_variable(
+    nodes: &[AnyNodeRef],
+    state: &mut AnalysisState,
+    index: &Arc<SymbolIndex>,
+    source: &str,
+    start: usize,
+    end: usize,
+) -> Option<(String, Vec<TextEdit>)> {
+    let spans_selection = |n: &AnyNodeRef| {
+        let range = n.range();
+        range.start_byte == start && range.end_byte == end
+    };
+    // A lone variable or name is as short as it gets
+    if nodes.iter().any(|n| {
+        spans_selection(n)
+            && matches!(
+                n,
+                AnyNodeRef::VariableName(_)
+                    | AnyNodeRef::DynamicVariableName(_)
+                    | AnyNodeRef::Name(_)
+                    | AnyNodeRef::QualifiedName(_)
+            )
+    }) {
+        return None;
+    }
+    let expr_idx = nodes
+        .iter()
+        .rposition(|n| spans_selection(n) && is_extractable(n))?;
+    let expression = &nodes[expr_idx];
+
+    // Assigning to the extracted value would change nothing
+    if let Some(parent) = nodes[..expr_idx].iter().rev().find(|n| !spans_selection(n)) {
+        let is_first_child = parent
+            .children_any()
+            .first()
+            .map(|c| c.range() == expression.range())
+            .unwrap_or(false);
+        if is_first_child
+            && matches!(
+                parent,
+                AnyNodeRef::AssignmentExpression(_)
+                    | AnyNodeRef::AugmentedAssignmentExpression(_)
+                    | AnyNodeRef::ReferenceAssignmentExpression(_)
+            )
+        {
+            return None;
+        }
+    }
+
+    // The statement to put the assignment in front of, which must be in the same variable scope
+    let statement_idx = (1..=expr_idx)
+        .rev()
+        .find(|&i| is_statement_list(&nodes[i - 1]))?;
+    if statement_idx == expr_idx
+        || nodes[statement_idx..expr_idx]
+            .iter()
+            .any(|n| matches!(n, AnyNodeRef::AnonymousFunctionCreationExpression(_)))
+    {
+        return None;
+    }
+    if is_conditionally_evaluated(&nodes[statement_idx..=expr_idx], source) {
+        return None;
+    }
+    let statement = nodes[statement_idx].range();
+
+    let scope = find_variable_scope(&nodes[..expr_idx].to_vec())?;
+    let scope_text = node_text(&scope.range(), source.as_bytes());
+    let name = unique_name(EXTRACTED_VARIABLE_NAME, |n| {
+        scope_text.contains(&format!("${}", n))
+    });
+
+    let mut type_names = TypeNames::new(FileImports::from_contents(source), index.clone());
+    let indent = line_indent(source, statement.start_byte);
+    let mut new_text = String::new();
+    if let Some(utype) = get_utype_for_node(expression, state, &VoidEmitter::new()) {
+        new_text.push_str(&format!(
+            "{}/** @var {} ${} */\n",
+            indent,
+            type_names.format(&utype),
+            name
+        ));
+    }
+    new_text.push_str(&format!("{}${} = {};\n", indent, name, &source[start..end]));
+
+    let insert_at = offset_to_position(source, line_start(source, statement.start_byte));
+    let mut edits = type_names.get_import_edits();
+    edits.push(TextEdit {
+        range: Range {
+            start: insert_at,
+            end: insert_at,
+        },
+        new_text,
+    });
+    edits.push(TextEdit {
+        range: Range {
+            start: offset_to_position(source, start),
+            end: offset_to_position(source, end),
+        },
+        new_text: format!("${}", name),
+    });
+    Some((format!("Extract to variable ${}", name), edits))
+}
+
+///
+/// Extract the statements spanning `start..end` into a private method on the same class.
+/// Variables used in the selection which are set before it become parameters, and variables
+/// set in the selection which are used after it are returned.
+///
+fn extract_method(
+    nodes: &[AnyNodeRef],
+    source: &str,
+    start: usize,
+    end: usize,
+) -> Option<(String, Vec<TextEdit>)> {
+    let list_idx = nodes.iter().rposition(|n| {
+        let range = n.range();
+        matches!(n, AnyNodeRef::CompoundStatement(_))
+            && range.start_byte < start
+            && end < range.end_byte
+    })?;
+    let scope = find_variable_scope(&nodes[..=list_idx].to_vec())?;
+    if !matches!(scope, AnyNodeRef::MethodDeclaration(_)) {
+        return None;
+    }
+
+    // The selection has to cover whole statements
+    let mut statements = vec![];
+    for child in nodes[list_idx].children_any() {
+        let range = child.range();
+        if range.end_byte <= start || range.start_byte >= end {
+            continue;
+        }
+        if range.start_byte < start || range.end_byte > end {
+            return None;
+        }
+        statements.push(child);
+    }
+    let first = statements.first()?.range();
+    let last = statements.last()?.range();
+    if statements
+        .iter()
+        .any(|s| leaves_selection(s, source, false))
+    {
+        return None;
+    }
+
+    let mut candidates = BTreeSet::new();
+    for statement in &statements {
+        collect_variable_names(statement, source, &mut candidates);
+    }
+    // Inside a loop, what the selection sets can be used by the next round, anywhere in the loop
+    let scope_start = scope.range().start_byte;
+    let enclosing_loop = nodes[..list_idx]
+        .iter()
+        .find(|n| is_loop(n) && n.range().start_byte > scope_start)
+        .map(|n| n.range());
+    let mut parameters = vec![];
+    let mut returns = vec![];
+    for name in candidates {
+        let occurrences = get_variable_occurrences(&scope, &name, source.as_bytes());
+        let inside: Vec<_> = occurrences
+            .iter()
+            .filter(|o| o.range.start_byte >= first.start_byte && o.range.end_byte <= last.end_byte)
+            .collect();
+        if inside.is_empty() {
+            // Only seen in a closure not capturing it
+            continue;
+        }
+        let seen_before = occurrences
+            .iter()
+            .any(|o| o.range.end_byte <= first.start_byte);
+        let used_after = occurrences.iter().any(|o| {
+            o.range.start_byte >= last.end_byte
+                || enclosing_loop.as_ref().map_or(false, |l| {
+                    o.range.start_byte >= l.start_byte && o.range.end_byte <= first.start_byte
+                })
+        });
+        if seen_before {
+            parameters.push(name.clone());
+        }
+        if used_after && inside.iter().any(|o| o.is_write) {
+            returns.push(name);
+        }
+    }
+
+    let method_range = scope.range();
+    let method_text = node_text(&method_range, source.as_bytes());
+    let is_static = method_text
+        .split("function")
+        .next()
+        .map(|modifiers| {
+            modifiers
+                .split_whitespace()
+                .any(|m| m.eq_ignore_ascii_case("static"))
+        })
+        .unwrap_or(false);
+    let lc_source = source.to_lowercase();
+    let name = unique_name(EXTRACTED_METHOD_NAME, |n| {
+        lc_source.contains(&format!("function {}(", n.to_lowercase()))
+    });
+
+    let arguments: Vec<String> = parameters.iter().map(|p| format!("${}", p)).collect();
+    let receiver = if is_static { "self::" } else { "$this->" };
+    let call = format!("{}{}({})", receiver, name, arguments.join(", "));
+    let (call, return_statement, return_type) = match returns.len() {
+        0 => (format!("{};", call), None, ": void"),
+        1 => (
+            format!("${} = {};", returns[0], call),
+            Some(format!("return ${};", returns[0])),
+            "",
+        ),
+        _ => {
+            let list: Vec<String> = returns.iter().map(|r| format!("${}", r)).collect();
+            let list = list.join(", ");
+            (
+                format!("[{}] = {};", list, call),
+                Some(format!("return [{}];", list)),
+                ": array",
+            )
+        }
+    };
+
+    let method_indent = line_indent(source, method_range.start_byte);
+    let body_indent = format!("{}{}", method_indent, INDENT);
+    let mut body = reindent(
+        &source[line_start(source, first.start_byte)..last.end_byte],
+        &body_indent,
+    );
+    if let Some(return_statement) = return_statement {
+        body.push_str(&format!("\n{}{}", body_indent, return_statement));
+    }
+    let new_method = format!(
+        "\n\n{indent}private {static_}function {name}({params}){return_type}\n{indent}{{\n{body}\n{indent}}}",
+        indent = method_indent,
+        static_ = if is_static { "static " } else { "" },
+        name = name,
+        params = arguments.join(", "),
+        return_type = return_type,
+        body = body,
+    );
+
+    let method_end = offset_to_position(source, method_range.end_byte);
+    let edits = vec![
+        TextEdit {
+            range: Range {
+                start: offset_to_position(source, first.start_byte),
+                end: offset_to_position(source, last.end_byte),
+            },
+            new_text: call,
+        },
+        TextEdit {
+            range: Range {
+                start: method_end,
+                end: method_end,
+            },
+            new_text: new_method,
+        },
+    ];
+    Some((format!("Extract to method {}()", name), edits))
+}
+
+///
+/// Check if the last of `path`, which goes from a statement down to an expression, is only
+/// evaluated sometimes, or more than once, when the statement is run. Such an expression
+/// can't be moved in front of the statement.
+///
+fn is_conditionally_evaluated(path: &[AnyNodeRef], source: &str) -> bool {
+    path.windows(2).any(|pair| {
+        let (parent, child) = (&pair[0], &pair[1]);
+        let children = parent.children_any();
+        let is_first_child = children
+            .first()
+            .map_or(false, |c| c.range() == child.range());
+        match parent {
+            // Loop conditions are evaluated for every round
+            AnyNodeRef::WhileStatement(_)
+            | AnyNodeRef::DoStatement(_)
+            | AnyNodeRef::ForStatement(_) => true,
+            // Only the condition, or what is iterated over, is evaluated up front
+            AnyNodeRef::IfStatement(_)
+            | AnyNodeRef::SwitchStatement(_)
+            | AnyNodeRef::ForeachStatement(_)
+            | AnyNodeRef::ConditionalExpression(_) => !is_first_child,
+            AnyNodeRef::BinaryExpression(_) if !is_first_child => {
+                let operator = match (children.first(), children.last()) {
+                    (Some(left), Some(right)) => source
+                        .get(left.range().end_byte..right.range().start_byte)
+                        .unwrap_or("")
+                        .trim()
+                        .to_lowercase(),
+                    _ => String::new(),
+                };
+                matches!(operator.as_str(), "&&" | "||" | "??" | "and" | "or")
+            }
+            _ => false,
+        }
+    })
+}
+
+fn is_loop(node: &AnyNodeRef) -> bool {
+    matches!(
+        node,
+        AnyNodeRef::ForeachStatement(_)
+            | AnyNodeRef::ForStatement(_)
+            | AnyNodeRef::WhileStatement(_)
+            | AnyNodeRef::DoStatement(_)
+    )
+}
+
+fn is_statement_list(node: &AnyNodeRef) -> bool {
+    matches!(
+        node,
+        AnyNodeRef::CompoundStatement(_) | AnyNodeRef::Program(_)
+    )
+}
+
+fn is_extractable(node: &AnyNodeRef) -> bool {
+    !matches!(
+        node,
+        AnyNodeRef::_Statement(_)
+            | AnyNodeRef::ExpressionStatement(_)
+            | AnyNodeRef::CompoundStatement(_)
+            | AnyNodeRef::ReturnStatement(_)
+            | AnyNodeRef::_Type(_)
+            | AnyNodeRef::NamedType(_)
+            | AnyNodeRef::OptionalType(_)
+            | AnyNodeRef::UnionType(_)
+            | AnyNodeRef::Arguments(_)
+            | AnyNodeRef::Argument(_)
+            | AnyNodeRef::ArrayElementInitializer(_)
+            | AnyNodeRef::FormalParameters(_)
+            | AnyNodeRef::SimpleParameter(_)
+            | AnyNodeRef::ListLiteral(_)
+    )
+}
+
+///
+/// Check if `node` contains a `return`, `yield`, `break` or `continue` that would act
+/// differently from inside a method of its own. The latter two are fine inside a loop or
+/// switch that is extracted along with them.
+///
+fn leaves_selection(node: &AnyNodeRef, source: &str, in_loop: bool) -> bool {
+    match node {
+        AnyNodeRef::FunctionDefinition(_)
+        | AnyNodeRef::MethodDeclaration(_)
+        | AnyNodeRef::AnonymousFunctionCreationExpression(_)
+        | AnyNodeRef::ClassDeclaration(_) => return false,
+        AnyNodeRef::ReturnStatement(_) => return true,
+        _ => (),
+    }
+    let text = node_text(&node.range(), source.as_bytes());
+    if starts_with_keyword(&text, "yield") {
+        return true;
+    }
+    if !in_loop && (starts_with_keyword(&text, "break") || starts_with_keyword(&text, "continue")) {
+        return true;
+    }
+    let in_loop = in_loop
+        || ["for", "foreach", "while", "do", "switch"]
+            .iter()
+            .any(|k| starts_with_keyword(&text, k));
+    node.children_any()
+        .iter()
+        .any(|c| leaves_selection(c, source, in_loop))
+}
+
+fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    text.len() >= keyword.len()
+        && text[..keyword.len()].eq_ignore_ascii_case(keyword)
+        && !text[keyword.len()..]
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_alphanumeric() || c == '_')
+}
+
+///
+/// Collect the names of the variables in `node`, except `$this`
+///
+fn collect_variable_names(node: &AnyNodeRef, source: &str, names: &mut BTreeSet<String>) {
+    if let AnyNodeRef::VariableName(_) = node {
+        let name = node_text(&node.range(), source.as_bytes())
+            .trim_start_matches('$')
+            .to_string();
+        if name != "this" {
+            names.insert(name);
+        }
+        return;
+    }
+    for child in node.children_any() {
+        collect_variable_names(&child, source, names);
+    }
+}
+
+fn unique_name<F>(base: &str, is_taken: F) -> String
+where
+    F: Fn(&str) -> bool,
+{
+    let mut name = base.to_string();
+    let mut suffix = 2;
+    while is_taken(&name) {
+        name = format!("{}{}", base, suffix);
+        suffix += 1;
+    }
+    name
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |idx| idx + 1)
+}
+
+fn line_indent(source: &str, offset: usize) -> String {
+    source[line_start(source, offset)..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect()
+}
+
+///
+/// Replace the common indentation of the lines in `text` with `indent`
+///
+fn reindent(text: &str, indent: &str) -> String {
+    let common = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let lines: Vec<String> = text
+        .lines()
+        .map(|l| {
+            if l.trim().is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "{}{}",
+                    indent,
+                    &l[common.min(l.len() - l.trim_start().len())..]
+                )
+            }
+        })
+        .collect();
+    lines.join("\n")
+}
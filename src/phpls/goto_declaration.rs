@@ -12,7 +12,7 @@ use rust_lsp::{
     },
 };
 
-use crate::phpls::locations::file_locations_to_locations;
+use crate::phpls::locations::LocationConverter;
 
 use super::instance::PHPLanguageServerInstance;
 
@@ -25,6 +25,10 @@ pub fn goto_declaration(
 
     let position = params.text_document_position_params;
     let locations = get_locations(phpls, position);
+    if phpls.is_request_cancelled() {
+        completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+        return;
+    }
     eprintln!("Fant locations: {:?}", locations);
     completable.complete(Ok(GotoDeclarationResponse::Array(locations)))
 }
@@ -41,7 +45,7 @@ fn get_locations(
             eprintln!("AT POSITION: {:#?}", pos_copy.position);
             for node in path.iter().rev() {
                 if let Some(file_locations) = get_file_location_for_node(node, state) {
-                    return file_locations_to_locations(file_locations);
+                    return file_locations;
                 } else if abort_upwords_traverse(node) {
                     return vec![];
                 }
@@ -51,9 +55,9 @@ fn get_locations(
             vec![]
         }),
     ) {
-        Ok((_maybe_symbols, Some(locations))) => {
+        Ok((_maybe_symbols, Some(file_locations))) => {
             // void
-            locations
+            LocationConverter::new(phpls).locations(file_locations)
         }
         Ok((_, None)) => vec![],
         Err(e) => {
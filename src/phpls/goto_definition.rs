@@ -4,60 +4,120 @@ use phpanalyzer::{
 };
 use rust_lsp::{
     jsonrpc::MethodCompletable,
-    lsp_types::{Location, TextDocumentPositionParams},
+    lsp_types::{Location, Range, TextDocumentPositionParams},
 };
 
-use super::{instance::PHPLanguageServerInstance, locations::file_locations_to_locations};
+use crate::codetree::references::{get_reference_at_cursor, ReferenceKey};
+
+use super::{
+    instance::PHPLanguageServerInstance,
+    locations::{range_to_lsp_range, LocationConverter},
+    references::get_declaration_locations,
+    variables::{find_variable_scope, get_local_variable_at_cursor, get_variable_occurrences},
+};
+
+#[derive(Clone)]
+enum Definition {
+    /// Members of a receiver with a union type can be declared in several places
+    Symbols(Vec<Symbol>),
+    Reference(ReferenceKey),
+    /// A local variable, defined in the same file
+    Variable(Range),
+}
 
 pub fn goto_definition(
     phpls: &PHPLanguageServerInstance,
     position: TextDocumentPositionParams,
     completable: MethodCompletable<std::vec::Vec<Location>, ()>,
 ) {
+    let uri = position.text_document.uri.clone();
+    let source = phpls
+        .get_file_for_uri(&uri)
+        .and_then(|f| f.get_contents().ok())
+        .unwrap_or_default();
     let result = phpls.at_position(
         position,
-        Box::new(move |node, state, path| get_symbols_at_callback(node, state, path)),
+        Box::new(move |node, state, path| get_definition_at_callback(node, state, path, &source)),
     );
     let mut locations: Vec<_> = vec![];
-    let (symbol_data, symbols) = if let Ok((Some(symbol_data), Some(Some(symbols)))) = result {
-        (symbol_data, symbols)
-    } else {
-        completable.complete(Ok(locations));
-        return;
+    let (symbol_data, definition) = match result {
+        Ok((symbol_data, Some(Some(definition)))) => (symbol_data, definition),
+        Ok(_) => {
+            completable.complete(Ok(locations));
+            return;
+        }
+        Err(_) if phpls.is_request_cancelled() => {
+            completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+            return;
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            completable.complete(Ok(locations));
+            return;
+        }
     };
 
-    for symbol in symbols {
-        for file_locations in symbol_data.get_pos_for_symbol(symbol) {
-            locations.extend(file_locations_to_locations(file_locations))
+    let mut converter = LocationConverter::new(phpls);
+    match (definition, symbol_data) {
+        (Definition::Variable(range), _) => locations.push(Location::new(uri, range)),
+        (Definition::Symbols(symbols), Some(symbol_data)) => {
+            for symbol in symbols {
+                for file_locations in symbol_data.get_pos_for_symbol(symbol) {
+                    locations.extend(converter.locations(file_locations))
+                }
+            }
         }
+        (Definition::Reference(key), Some(symbol_data)) => {
+            locations.extend(converter.locations(get_declaration_locations(&symbol_data, &key)))
+        }
+        (_, None) => (),
     }
 
     completable.complete(Ok(locations));
 }
 
-fn get_symbols_at_callback(
+fn get_definition_at_callback(
     found_node: AnyNodeRef,
     state: &mut AnalysisState,
     path: &Vec<AnyNodeRef>,
-) -> Option<Vec<Symbol>> {
-    // void
-    let mut node = &found_node;
-
-    //         let path = path.clone();
-    let mut iter = path.iter().rev();
-    loop {
-        eprintln!("Ser etter symbol i en {:?}", node.kind());
-        return match node {
-            AnyNodeRef::MemberCallExpression(e) => e.get_symbols(state),
-            AnyNodeRef::MemberAccessExpression(e) => e.get_symbols(state),
-            _ => {
-                if let Some(n) = iter.next() {
-                    node = n;
-                    continue;
-                } else {
-                    None
-                }
+    source: &[u8],
+) -> Option<Definition> {
+    if let Some(variable) = get_local_variable_at_cursor(&found_node, path, source) {
+        let scope = find_variable_scope(&variable.path)?;
+        let occurrences = get_variable_occurrences(&scope, &variable.name, source);
+        // The first assignment, or the parameter
+        let definition = occurrences
+            .iter()
+            .find(|o| o.is_write)
+            .or_else(|| occurrences.first())?;
+        let text = String::from_utf8_lossy(source);
+        let range = range_to_lsp_range(&text, &definition.range);
+        return Some(Definition::Variable(range));
+    }
+
+    let (key, range) = get_reference_at_cursor(&found_node, path, state, source)?;
+    eprintln!("Ser etter definisjon av {:?}", key);
+
+    // Member calls and accesses know all the symbols they might refer to
+    let mut nodes = path.clone();
+    nodes.push(found_node);
+    for node in nodes.iter().rev() {
+        let symbols = match node {
+            AnyNodeRef::MemberCallExpression(e) if e.name.range() == range => e.get_symbols(state),
+            AnyNodeRef::MemberAccessExpression(e) if e.name.range() == range => {
+                e.get_symbols(state)
+            }
+            AnyNodeRef::NullsafeMemberCallExpression(e) if e.name.range() == range => {
+                e.get_symbols(state)
             }
+            AnyNodeRef::NullsafeMemberAccessExpression(e) if e.name.range() == range => {
+                e.get_symbols(state)
+            }
+            _ => continue,
         };
+        if let Some(symbols) = symbols {
+            return Some(Definition::Symbols(symbols));
+        }
     }
+    Some(Definition::Reference(key))
 }
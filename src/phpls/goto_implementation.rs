@@ -0,0 +1,139 @@
+use phpanalyzer::{
+    symboldata::{class::ClassType, FileLocation, SymbolData},
+    symbols::FullyQualifiedName,
+};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::request::{GotoImplementationParams, GotoImplementationResponse},
+};
+
+use std::collections::HashSet;
+
+use crate::{
+    codetree::class_hierarchy::ClassHierarchy,
+    codetree::references::{get_reference_at_cursor, ReferenceKey},
+    phpls::locations::LocationConverter,
+};
+
+use super::{
+    instance::PHPLanguageServerInstance,
+    members::{get_class_members, get_class_position, MemberKind},
+};
+
+pub fn goto_implementation(
+    phpls: &PHPLanguageServerInstance,
+    params: GotoImplementationParams,
+    completable: MethodCompletable<Option<GotoImplementationResponse>, ()>,
+) {
+    let position = params.text_document_position_params;
+    let uri = position.text_document.uri.clone();
+    let source = phpls
+        .get_file_for_uri(&uri)
+        .and_then(|f| f.get_contents().ok())
+        .unwrap_or_default();
+
+    let key = match phpls.at_position(
+        position,
+        Box::new(move |node, state, path| {
+            get_reference_at_cursor(&node, path, state, &source).map(|(key, _)| key)
+        }),
+    ) {
+        Ok((_, Some(Some(key)))) => key,
+        Ok(_) => {
+            completable.complete(Ok(None));
+            return;
+        }
+        Err(_) if phpls.is_request_cancelled() => {
+            completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+            return;
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let (symbol_data, hierarchy) = match (
+        phpls.get_symbol_data_for_uri(&uri),
+        phpls.get_class_hierarchy_for_uri(&uri),
+    ) {
+        (Some(symbol_data), Some(hierarchy)) => (symbol_data, hierarchy),
+        _ => {
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+
+    let file_locations = match &key {
+        ReferenceKey::Class(fq_name) => {
+            get_class_implementations(&symbol_data, &hierarchy, fq_name)
+        }
+        ReferenceKey::Method(class, name) => {
+            get_method_implementations(&symbol_data, &hierarchy, class, &name.to_string())
+        }
+        _ => vec![],
+    };
+    eprintln!("goto_implementation: {} implementations of {:?}", file_locations.len(), key);
+    completable.complete(Ok(Some(GotoImplementationResponse::Array(
+        LocationConverter::new(phpls).locations(file_locations),
+    ))))
+}
+
+///
+/// Get every class extending or implementing `fq_name`, directly or through other classes
+/// and interfaces
+///
+fn get_class_implementations(
+    symbol_data: &SymbolData,
+    hierarchy: &ClassHierarchy,
+    fq_name: &FullyQualifiedName,
+) -> Vec<FileLocation> {
+    hierarchy
+        .get_descendants(fq_name)
+        .iter()
+        .filter(|c| is_class(symbol_data, c))
+        .filter_map(|c| get_class_position(symbol_data, c))
+        .collect()
+}
+
+///
+/// Get every non-abstract declaration of the method `name` in the classes inheriting from
+/// `fq_name`
+///
+fn get_method_implementations(
+    symbol_data: &SymbolData,
+    hierarchy: &ClassHierarchy,
+    fq_name: &FullyQualifiedName,
+    name: &str,
+) -> Vec<FileLocation> {
+    let mut locations = vec![];
+    let mut seen = HashSet::new();
+    for class in hierarchy.get_descendants(fq_name) {
+        if !is_class(symbol_data, &class) {
+            continue;
+        }
+        let implementation = get_class_members(symbol_data, &class).into_iter().find(|m| {
+            m.kind == MemberKind::Method
+                && m.declared_in == class
+                && !m.is_abstract
+                && m.name.eq_ignore_ascii_case(name)
+        });
+        if let Some(position) = implementation.and_then(|m| m.position) {
+            let start = (position.uri.clone(), position.start.line, position.start.column);
+            if seen.insert(start) {
+                locations.push(position);
+            }
+        }
+    }
+    locations
+}
+
+///
+/// Interfaces and traits are not implementations
+///
+fn is_class(symbol_data: &SymbolData, fq_name: &FullyQualifiedName) -> bool {
+    symbol_data
+        .get_class(fq_name)
+        .map(|c| matches!(&*c.read().unwrap(), ClassType::Class(_)))
+        .unwrap_or(false)
+}
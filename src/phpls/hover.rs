@@ -1,4 +1,10 @@
-use phpanalyzer::description::NodeDescription;
+use phpanalyzer::{
+    analysis::state::AnalysisState,
+    autonodes::any::AnyNodeRef,
+    issue::VoidEmitter,
+    symboldata::SymbolData,
+    symbols::FullyQualifiedName,
+};
 use rust_lsp::{
     jsonrpc::MethodCompletable,
     lsp_types::{
@@ -6,7 +12,21 @@ use rust_lsp::{
     },
 };
 
-use super::instance::PHPLanguageServerInstance;
+use crate::codetree::references::{get_reference_at_cursor, ReferenceKey};
+
+use super::{
+    goto_type_definition::get_utype_for_node,
+    instance::PHPLanguageServerInstance,
+    locations::range_to_lsp_range,
+    members::{get_class_members, get_method, MemberKind},
+    signatures::{
+        format_class_documentation, format_class_signature, format_constant_documentation,
+        format_constant_signature, format_function_documentation, format_function_signature,
+        format_member_documentation, format_member_signature, format_method_documentation,
+        format_method_signature,
+    },
+    variables::{get_local_variable_at_cursor, get_scope_variables},
+};
 
 pub fn hover(
     phpls: &PHPLanguageServerInstance,
@@ -15,42 +35,154 @@ pub fn hover(
 ) {
     eprintln!("hover(..): params: {:?}", params);
     let (markdown, range) = match get_hover_text(phpls, params) {
-        Some(result) => result,
+        Some((markdown, range)) => (markdown, Some(range)),
         None => ("".to_string(), None),
     };
+    if phpls.is_request_cancelled() {
+        completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+        return;
+    }
 
     completable.complete(Ok(Hover {
         contents: HoverContents::Markup(MarkupContent {
             kind: MarkupKind::Markdown,
-            value: markdown.clone(),
+            value: markdown,
         }),
         range,
     }));
-    // eprintln!("hover(..): range:{:?} complete markdown: {}", range, markdown);
 }
 
 fn get_hover_text(
     phpls: &PHPLanguageServerInstance,
     position: TextDocumentPositionParams,
-) -> Option<(String, Option<Range>)> {
+) -> Option<(String, Range)> {
+    let source = phpls
+        .get_file_for_uri(&position.text_document.uri)
+        .and_then(|f| f.get_contents().ok())
+        .unwrap_or_default();
     let maybe_desc_result = phpls.at_position(
         position,
-        Box::new(move |node, state, path| {
-            eprintln!("foobar");
-            let mut path = path.clone();
-            path.push(node.clone());
-            node.description(Some(&path[..]), state)
-        }),
+        Box::new(move |node, state, path| get_hover_at_callback(node, state, path, &source)),
     );
 
-    let desc = match maybe_desc_result {
-        Ok((_, Some(Some(desc)))) => Some(desc),
-        Err(e) => Some(format!("ERROR: {}", e)),
-        _ => {
-            // eprintln!("Unknown!?!?");
+    match maybe_desc_result {
+        Ok((_, Some(desc))) => desc,
+        Ok((_, None)) => None,
+        Err(e) => {
+            eprintln!("ERROR: hover failed: {}", e);
             None
         }
-    }?;
+    }
+}
+
+fn get_hover_at_callback(
+    node: AnyNodeRef,
+    state: &mut AnalysisState,
+    path: &Vec<AnyNodeRef>,
+    source: &[u8],
+) -> Option<(String, Range)> {
+    let text = String::from_utf8_lossy(source);
+    if let Some(variable) = get_local_variable_at_cursor(&node, path, source) {
+        let var_type = get_scope_variables(state)
+            .remove(&variable.name)
+            .flatten()
+            .map(|t| format!("{} ", t))
+            .unwrap_or_default();
+        return Some((
+            php_code_block(&format!("{}${}", var_type, variable.name)),
+            range_to_lsp_range(&text, &variable.range),
+        ));
+    }
+
+    if let Some((key, range)) = get_reference_at_cursor(&node, path, state, source) {
+        if let Some(markdown) = describe_symbol(&state.symbol_data, &key) {
+            return Some((markdown, range_to_lsp_range(&text, &range)));
+        }
+    }
+
+    // Anything else, we show the type of
+    let utype = get_utype_for_node(&node, state, &VoidEmitter::new())?;
+    Some((
+        php_code_block(&utype.to_string()),
+        range_to_lsp_range(&text, &node.range()),
+    ))
+}
+
+///
+/// Render the declaration of a symbol, followed by its documentation
+///
+fn describe_symbol(symbol_data: &SymbolData, key: &ReferenceKey) -> Option<String> {
+    let (signature, documentation) = match key {
+        ReferenceKey::Method(class, name) => {
+            let method = get_method(symbol_data, class, &name.to_string())?;
+            let method = method.read().unwrap();
+            (
+                format!("{}\n{}", class_line(class), format_method_signature(&method)),
+                format_method_documentation(&method),
+            )
+        }
+        ReferenceKey::Function(fq_name) => {
+            let functions = symbol_data.functions.read().unwrap();
+            let function = functions.get(fq_name)?.read().unwrap();
+            (
+                format_function_signature(&function),
+                format_function_documentation(&function),
+            )
+        }
+        ReferenceKey::Property(class, name) | ReferenceKey::ClassConstant(class, name) => {
+            let kind = match key {
+                ReferenceKey::Property(..) => MemberKind::Property,
+                _ => MemberKind::Constant,
+            };
+            let name = name.to_string();
+            let member = get_class_members(symbol_data, class)
+                .into_iter()
+                .find(|m| m.kind == kind && m.name == name)?;
+            (
+                format!(
+                    "{}\n{}",
+                    class_line(&member.declared_in),
+                    format_member_signature(&member)
+                ),
+                format_member_documentation(&member),
+            )
+        }
+        ReferenceKey::Class(fq_name) => {
+            let class = symbol_data.get_class(fq_name)?;
+            let class = class.read().unwrap();
+            (
+                format_class_signature(&class, fq_name)?,
+                format_class_documentation(&class),
+            )
+        }
+        ReferenceKey::Constant(fq_name) => {
+            let constants = symbol_data.constants.read().unwrap();
+            let constant = constants.get(fq_name)?;
+            (
+                format_constant_signature(constant, fq_name),
+                format_constant_documentation(constant),
+            )
+        }
+        ReferenceKey::UnresolvedMember(_)
+        | ReferenceKey::UnresolvedFunction(_)
+        | ReferenceKey::UnresolvedConstant(_) => return None,
+    };
+
+    let mut markdown = php_code_block(&signature);
+    if !documentation.is_empty() {
+        markdown.push_str("\n---\n");
+        markdown.push_str(&documentation);
+    }
+    Some(markdown)
+}
+
+///
+/// A comment line telling which class a member belongs to
+///
+fn class_line(class: &FullyQualifiedName) -> String {
+    format!("// {}", class.to_string().trim_start_matches('\\'))
+}
 
-    Some((desc, None))
+fn php_code_block(code: &str) -> String {
+    format!("```php\n{}\n```\n", code)
 }
@@ -0,0 +1,518 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use phpanalyzer::{
+    symboldata::{
+        class::{ClassType, MethodData},
+        SymbolData,
+    },
+    symbols::FullyQualifiedName,
+};
+use rust_lsp::lsp_types::{CodeAction, CodeActionKind, CodeActionParams, Position, Range, TextEdit};
+
+use crate::codetree::symbol_index::SymbolIndex;
+
+use super::{
+    code_actions::edit_action,
+    completion::is_identifier_char,
+    imports::{FileImports, TypeNames},
+    instance::PHPLanguageServerInstance,
+    locations::{offset_to_position, position_to_byte_offset},
+    members::{get_ancestors, get_declared_members, get_method, MemberKind},
+    signatures::format_visibility,
+};
+
+const INDENT: &str = "    ";
+
+///
+/// Offer to add stubs for the abstract and interface methods the class at the cursor has yet
+/// to implement
+///
+pub fn get_implement_methods_actions(
+    phpls: &PHPLanguageServerInstance,
+    params: &CodeActionParams,
+) -> Vec<CodeAction> {
+    let uri = &params.text_document.uri;
+    let file = match uri.to_file_path() {
+        Ok(path) => path.into_os_string(),
+        Err(_) => return vec![],
+    };
+    let source = match phpls
+        .get_file_for_uri(uri)
+        .and_then(|f| f.get_contents().ok())
+    {
+        Some(source) => String::from_utf8_lossy(&source).to_string(),
+        None => return vec![],
+    };
+    let symbol_data = match phpls.get_symbol_data_for_uri(uri) {
+        Some(symbol_data) => symbol_data,
+        None => return vec![],
+    };
+    let (fq_name, class_start) =
+        match get_class_at_line(&symbol_data, &file, params.range.start.line) {
+            Some(class) => class,
+            None => return vec![],
+        };
+    let missing = get_missing_methods(&symbol_data, &fq_name);
+    if missing.is_empty() {
+        return vec![];
+    }
+
+    let (insert_position, class_indent, on_own_line) =
+        match get_insert_position(&source, &class_start) {
+            Some(found) => found,
+            None => return vec![],
+        };
+    let member_indent = format!("{}{}", class_indent, INDENT);
+
+    let index = phpls
+        .get_symbol_index_for_uri(uri)
+        .unwrap_or_else(|| Arc::new(SymbolIndex::new()));
+    let mut type_names = TypeNames::new(FileImports::from_contents(&source), index);
+    let stubs: Vec<String> = missing
+        .iter()
+        .filter_map(|(declared_in, method)| {
+            let method = method.read().unwrap();
+            let declaration = Declaration::read(phpls, &method, declared_in)?;
+            Some(format_stub(
+                &method,
+                &declaration,
+                &member_indent,
+                &mut type_names,
+            ))
+        })
+        .collect();
+    if stubs.is_empty() {
+        return vec![];
+    }
+    let mut new_text = String::new();
+    if !on_own_line {
+        new_text.push('\n');
+    }
+    for stub in &stubs {
+        new_text.push('\n');
+        new_text.push_str(stub);
+    }
+    if !on_own_line {
+        new_text.push_str(&class_indent);
+    }
+
+    let mut edits = type_names.get_import_edits();
+    edits.push(TextEdit {
+        range: Range {
+            start: insert_position,
+            end: insert_position,
+        },
+        new_text,
+    });
+    let title = match stubs.len() {
+        1 => "Implement missing method".to_string(),
+        count => format!("Implement {} missing methods", count),
+    };
+    vec![edit_action(title, CodeActionKind::REFACTOR_REWRITE, uri, edits)]
+}
+
+///
+/// Find the class declared in `file` around `line`, with the start of its declaration
+///
+fn get_class_at_line(
+    symbol_data: &SymbolData,
+    file: &OsString,
+    line: u32,
+) -> Option<(FullyQualifiedName, Position)> {
+    symbol_data
+        .classes
+        .read()
+        .unwrap()
+        .iter()
+        .filter_map(|(fq_name, class)| match &*class.read().unwrap() {
+            ClassType::Class(c) if &c.position.uri == file => {
+                Some((fq_name.clone(), c.position.clone()))
+            }
+            _ => None,
+        })
+        .filter(|(_, position)| {
+            position.start.line as u32 <= line && line <= position.end.line as u32
+        })
+        .min_by_key(|(_, position)| position.end.line - position.start.line)
+        .map(|(fq_name, position)| {
+            let start = Position {
+                line: position.start.line as u32,
+                character: position.start.column as u32,
+            };
+            (fq_name, start)
+        })
+}
+
+///
+/// Get the abstract methods `fq_name` inherits, from parents, interfaces and traits, which
+/// no class in its hierarchy implements. Abstract methods declared in the class itself are
+/// left alone.
+///
+fn get_missing_methods(
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+) -> Vec<(FullyQualifiedName, Arc<RwLock<MethodData>>)> {
+    let mut implemented = HashSet::new();
+    let mut abstract_methods = vec![];
+    for ancestor in get_ancestors(symbol_data, fq_name) {
+        let class = if let Some(class) = symbol_data.get_class(&ancestor) {
+            class
+        } else {
+            continue;
+        };
+        for member in get_declared_members(&class.read().unwrap(), &ancestor) {
+            if member.kind != MemberKind::Method {
+                continue;
+            }
+            let lc_name = member.name.to_lowercase();
+            if !member.is_abstract {
+                implemented.insert(lc_name);
+            } else if &ancestor != fq_name {
+                abstract_methods.push((lc_name, ancestor.clone(), member.name));
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut missing = vec![];
+    for (lc_name, declared_in, name) in abstract_methods {
+        if implemented.contains(&lc_name) || !seen.insert(lc_name) {
+            continue;
+        }
+        if let Some(method) = get_method(symbol_data, &declared_in, &name) {
+            missing.push((declared_in, method));
+        }
+    }
+    missing
+}
+
+///
+/// Find where the stubs go: just before the closing brace of the class. Also returns the
+/// indentation of the class, and whether the closing brace starts its line.
+///
+fn get_insert_position(source: &str, class_start: &Position) -> Option<(Position, String, bool)> {
+    let start = position_to_byte_offset(source, class_start);
+    let open = start + source[start..].find('{')?;
+    let mut depth = 0;
+    let mut close = None;
+    for (idx, c) in source[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(open + idx);
+                    break;
+                }
+            }
+            _ => (),
+        }
+    }
+    let close = close?;
+
+    let class_line_start = source[..start].rfind('\n').map_or(0, |idx| idx + 1);
+    let class_indent: String = source[class_line_start..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect();
+
+    let close_line_start = source[..close].rfind('\n').map_or(0, |idx| idx + 1);
+    if close_line_start > open && source[close_line_start..close].trim().is_empty() {
+        Some((
+            offset_to_position(source, close_line_start),
+            class_indent,
+            true,
+        ))
+    } else {
+        Some((offset_to_position(source, close), class_indent, false))
+    }
+}
+
+///
+/// Format a stub for `method`, with the parameters and return type as they are declared
+///
+fn format_stub(
+    method: &MethodData,
+    declaration: &Declaration,
+    indent: &str,
+    type_names: &mut TypeNames,
+) -> String {
+    let parameters: Vec<String> = declaration
+        .parameters
+        .iter()
+        .map(|p| declaration.format_parameter(p, type_names))
+        .collect();
+    let mut signature = format_visibility(&method.visibility).to_string();
+    if method.is_static {
+        signature.push_str(" static");
+    }
+    signature.push_str(" function ");
+    signature.push_str(&method.name.to_string());
+    signature.push('(');
+    signature.push_str(&parameters.join(", "));
+    signature.push(')');
+    if let Some(return_type) = &declaration.return_type {
+        signature.push_str(": ");
+        signature.push_str(&declaration.rewrite_class_names(return_type, true, type_names));
+    }
+
+    let mut stub = String::new();
+    stub.push_str(&format!("{}/**\n", indent));
+    stub.push_str(&format!("{} * {{@inheritDoc}}\n", indent));
+    stub.push_str(&format!("{} */\n", indent));
+    stub.push_str(&format!("{}{}\n", indent, signature));
+    stub.push_str(&format!("{}{{\n", indent));
+    stub.push_str(&format!(
+        "{}{}throw new \\LogicException('Not implemented');\n",
+        indent, INDENT
+    ));
+    stub.push_str(&format!("{}}}\n", indent));
+    stub
+}
+
+///
+/// The parameters and return type of a method as written in its declaration. The stub has
+/// to match the declaration, which the analyzer's view of it, with types from the PHPDoc and
+/// defaults evaluated, doesn't.
+///
+struct Declaration {
+    /// The imports of the declaring file, which the class names are relative to
+    imports: FileImports,
+    /// The class declaring the method, which `self` refers to
+    declared_in: FullyQualifiedName,
+    parameters: Vec<String>,
+    return_type: Option<String>,
+}
+
+impl Declaration {
+    fn read(
+        phpls: &PHPLanguageServerInstance,
+        method: &MethodData,
+        declared_in: &FullyQualifiedName,
+    ) -> Option<Self> {
+        let source = phpls.get_source(Path::new(&method.position.uri))?;
+
+        let start = position_to_byte_offset(
+            &source,
+            &Position {
+                line: method.position.start.line as u32,
+                character: method.position.start.column as u32,
+            },
+        );
+        let open = find_parameter_list(&source, start, &method.name.to_string())?;
+        let (parameters, close) = split_parameter_list(&source, open)?;
+
+        let mut rest = &source[close + 1..];
+        loop {
+            rest = rest.trim_start();
+            match comment_len(rest) {
+                Some(len) => rest = &rest[len..],
+                None => break,
+            }
+        }
+        let return_type = rest.strip_prefix(':').and_then(|rest| {
+            let end = rest.find(|c| c == '{' || c == ';').unwrap_or(rest.len());
+            let return_type = rest[..end].trim();
+            if return_type.is_empty() {
+                None
+            } else {
+                Some(return_type.to_string())
+            }
+        });
+
+        Some(Self {
+            imports: FileImports::from_contents(&source),
+            declared_in: declared_in.clone(),
+            parameters,
+            return_type,
+        })
+    }
+
+    ///
+    /// Format a parameter as declared, `?Foo &$foo = Foo::BAR`, with class names as the file
+    /// getting the stub refers to them
+    ///
+    fn format_parameter(&self, parameter: &str, type_names: &mut TypeNames) -> String {
+        let dollar = match parameter.find('$') {
+            Some(dollar) => dollar,
+            None => return parameter.to_string(),
+        };
+        // `&` and `...` go with the name
+        let type_end = parameter[..dollar]
+            .trim_end_matches(|c: char| c.is_whitespace() || c == '&' || c == '.')
+            .len();
+        let mut formatted = self.rewrite_class_names(&parameter[..type_end], true, type_names);
+        formatted.push_str(&self.rewrite_class_names(&parameter[type_end..], false, type_names));
+        formatted
+    }
+
+    ///
+    /// Copy `text` from the declaration, with the class names written the way the file getting
+    /// the stub refers to them. In types every name but the built in ones is a class, elsewhere
+    /// only the ones in front of `::`.
+    ///
+    fn rewrite_class_names(&self, text: &str, is_type: bool, type_names: &mut TypeNames) -> String {
+        let is_name_char = |c: char| is_identifier_char(c) || c == '\\';
+        let mut rewritten = String::new();
+        let mut idx = 0;
+        while let Some(c) = text[idx..].chars().next() {
+            let rest = &text[idx..];
+            let len = if c == '\'' || c == '"' {
+                quoted_len(rest)
+            } else if let Some(len) = comment_len(rest) {
+                len
+            } else if is_name_char(c) {
+                rest.find(|c| !is_name_char(c)).unwrap_or(rest.len())
+            } else {
+                c.len_utf8()
+            };
+            let word = &rest[..len];
+            let is_class = is_name_char(c)
+                && !c.is_ascii_digit()
+                && (is_type || rest[len..].trim_start().starts_with("::"));
+            if is_class {
+                rewritten.push_str(&self.class_name(word, type_names));
+            } else {
+                rewritten.push_str(word);
+            }
+            idx += len;
+        }
+        rewritten
+    }
+
+    fn class_name(&self, name: &str, type_names: &mut TypeNames) -> String {
+        const KEYWORDS: &[&str] = &[
+            "array",
+            "bool",
+            "callable",
+            "false",
+            "float",
+            "int",
+            "iterable",
+            "mixed",
+            "never",
+            "null",
+            "object",
+            "parent",
+            "private",
+            "protected",
+            "public",
+            "readonly",
+            "static",
+            "string",
+            "true",
+            "void",
+        ];
+        let lc_name = name.to_lowercase();
+        if lc_name == "self" {
+            type_names.class_name(&self.declared_in.to_string())
+        } else if KEYWORDS.contains(&lc_name.as_str()) {
+            name.to_string()
+        } else {
+            type_names.class_name(&self.imports.resolve_class_name(name))
+        }
+    }
+}
+
+///
+/// Find the `(` starting the parameters of the method `name`, declared at or after `from`.
+/// The declaration may start with its PHPDoc, where the name can show up too.
+///
+fn find_parameter_list(source: &str, from: usize, name: &str) -> Option<usize> {
+    let mut idx = from;
+    while let Some(c) = source[idx..].chars().next() {
+        let rest = &source[idx..];
+        let len = if c == '\'' || c == '"' {
+            quoted_len(rest)
+        } else if let Some(len) = comment_len(rest) {
+            len
+        } else if is_identifier_char(c) {
+            rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+        let after = rest[len..].trim_start();
+        if rest[..len].eq_ignore_ascii_case(name) && after.starts_with('(') {
+            return Some(source.len() - after.len());
+        }
+        idx += len;
+    }
+    None
+}
+
+///
+/// Split the parameter list starting with the `(` at `open` on its top level commas. Returns
+/// the parameters as written, and the offset of the closing `)`.
+///
+fn split_parameter_list(source: &str, open: usize) -> Option<(Vec<String>, usize)> {
+    let mut parameters = vec![];
+    let mut depth = 0;
+    let mut start = open + 1;
+    let mut idx = open;
+    while let Some(c) = source[idx..].chars().next() {
+        let rest = &source[idx..];
+        let mut len = c.len_utf8();
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    // There may be a trailing comma
+                    let last = source[start..idx].trim();
+                    if !last.is_empty() {
+                        parameters.push(last.to_string());
+                    }
+                    return Some((parameters, idx));
+                }
+            }
+            ',' if depth == 1 => {
+                parameters.push(source[start..idx].trim().to_string());
+                start = idx + 1;
+            }
+            '\'' | '"' => len = quoted_len(rest),
+            '/' | '#' => len = comment_len(rest).unwrap_or(len),
+            _ => (),
+        }
+        idx += len;
+    }
+    None
+}
+
+///
+/// The length of the string literal `text` starts with, quotes included
+///
+fn quoted_len(text: &str) -> usize {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, quote)) => quote,
+        None => return 0,
+    };
+    let mut escaped = false;
+    for (idx, c) in chars {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return idx + 1;
+        }
+    }
+    text.len()
+}
+
+///
+/// The length of the comment `text` starts with, if it starts with one. `#[` starts an
+/// attribute rather than a comment.
+///
+fn comment_len(text: &str) -> Option<usize> {
+    if text.starts_with("/*") {
+        Some(text.find("*/").map_or(text.len(), |idx| idx + 2))
+    } else if text.starts_with("//") || (text.starts_with('#') && !text.starts_with("#[")) {
+        Some(text.find('\n').unwrap_or(text.len()))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use phpanalyzer::{
+    symbols::FullyQualifiedName,
+    types::union::{DiscreteType, UnionType},
+};
+use rust_lsp::lsp_types::{Position, Range, TextEdit};
+
+use crate::codetree::symbol_index::{IndexedSymbol, IndexedSymbolKind, SymbolIndex};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportKind {
+    Class,
+    Function,
+    Constant,
+}
+
+impl From<IndexedSymbolKind> for ImportKind {
+    fn from(kind: IndexedSymbolKind) -> Self {
+        match kind {
+            IndexedSymbolKind::Class | IndexedSymbolKind::Interface | IndexedSymbolKind::Trait => {
+                ImportKind::Class
+            }
+            IndexedSymbolKind::Function => ImportKind::Function,
+            IndexedSymbolKind::Constant => ImportKind::Constant,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UseStatement {
+    pub line: u32,
+    pub kind: ImportKind,
+    /// Imported name, without leading backslash
+    pub fq_name: String,
+    /// The name the import is available as in the file
+    pub alias: String,
+}
+
+///
+/// The namespace and `use`-statements at the top of a file, found by looking at the text.
+/// This works on unsaved or broken files, where the syntax tree is of little help.
+///
+#[derive(Debug)]
+pub struct FileImports {
+    pub namespace: Option<String>,
+    pub namespace_line: Option<u32>,
+    pub open_tag_line: Option<u32>,
+    pub uses: Vec<UseStatement>,
+}
+
+impl FileImports {
+    pub fn from_contents(contents: &str) -> Self {
+        let mut imports = FileImports {
+            namespace: None,
+            namespace_line: None,
+            open_tag_line: None,
+            uses: vec![],
+        };
+
+        for (lineno, line) in contents.lines().enumerate() {
+            let lineno = lineno as u32;
+            let line = line.trim();
+            if imports.open_tag_line.is_none() && line.starts_with("<?php") {
+                imports.open_tag_line = Some(lineno);
+            } else if let Some(ns) = line.strip_prefix("namespace ") {
+                let ns = ns.trim_end_matches(|c| c == ';' || c == '{').trim();
+                imports.namespace = Some(ns.trim_start_matches('\\').to_string());
+                imports.namespace_line = Some(lineno);
+            } else if let Some(rest) = line.strip_prefix("use ") {
+                if let Some(statement) = Self::parse_use(lineno, rest) {
+                    imports.uses.push(statement);
+                }
+            } else if Self::is_declaration_start(line) {
+                // `use` below this point is trait usage
+                break;
+            }
+        }
+        imports
+    }
+
+    fn is_declaration_start(line: &str) -> bool {
+        ["class ", "interface ", "trait ", "enum ", "abstract ", "final ", "function "]
+            .iter()
+            .any(|kw| line.starts_with(kw))
+    }
+
+    fn parse_use(lineno: u32, rest: &str) -> Option<UseStatement> {
+        let rest = rest.trim().strip_suffix(';')?.trim();
+        if rest.contains('{') || rest.contains(',') {
+            // Group and multi imports are left alone
+            return None;
+        }
+        let (kind, rest) = if let Some(rest) = rest.strip_prefix("function ") {
+            (ImportKind::Function, rest.trim())
+        } else if let Some(rest) = rest.strip_prefix("const ") {
+            (ImportKind::Constant, rest.trim())
+        } else {
+            (ImportKind::Class, rest)
+        };
+        let mut parts = rest.split_whitespace();
+        let fq_name = parts.next()?.trim_start_matches('\\').to_string();
+        let alias = match (parts.next(), parts.next()) {
+            (Some(as_kw), Some(alias)) if as_kw.eq_ignore_ascii_case("as") => alias.to_string(),
+            _ => fq_name.rsplit('\\').next()?.to_string(),
+        };
+        Some(UseStatement {
+            line: lineno,
+            kind,
+            fq_name,
+            alias,
+        })
+    }
+
+    fn current_namespace(&self) -> &str {
+        self.namespace.as_deref().unwrap_or("")
+    }
+
+    ///
+    /// Resolve a class name as written in the file to a fully qualified name without leading
+    /// backslash, using the imports and the namespace of the file
+    ///
+    pub fn resolve_class_name(&self, name: &str) -> String {
+        if let Some(fq_name) = name.strip_prefix('\\') {
+            return fq_name.to_string();
+        }
+        let (first, rest) = match name.find('\\') {
+            Some(idx) => (&name[..idx], &name[idx..]),
+            None => (name, ""),
+        };
+        if let Some(import) = self
+            .uses
+            .iter()
+            .find(|u| u.kind == ImportKind::Class && u.alias.eq_ignore_ascii_case(first))
+        {
+            return format!("{}{}", import.fq_name, rest);
+        }
+        match self.current_namespace() {
+            "" => name.to_string(),
+            namespace => format!("{}\\{}", namespace, name),
+        }
+    }
+
+    ///
+    /// Check if `symbol` can be referred to by its short name without adding a `use` statement
+    ///
+    pub fn is_reachable(&self, symbol: &IndexedSymbol) -> bool {
+        let kind: ImportKind = symbol.kind.into();
+        if symbol.namespace().eq_ignore_ascii_case(self.current_namespace()) {
+            return true;
+        }
+        // Functions and constants fall back to the global namespace
+        if kind != ImportKind::Class && symbol.namespace().is_empty() {
+            return true;
+        }
+        self.uses
+            .iter()
+            .any(|u| u.kind == kind && u.fq_name.eq_ignore_ascii_case(&symbol.fq_name))
+    }
+
+    ///
+    /// Check if the short name of `symbol` is already taken by another import, or by a
+    /// symbol of the same kind in the current namespace
+    ///
+    pub fn is_name_taken(&self, symbol: &IndexedSymbol, index: &SymbolIndex) -> bool {
+        let kind: ImportKind = symbol.kind.into();
+        let imported = self.uses.iter().any(|u| {
+            u.kind == kind
+                && u.alias.eq_ignore_ascii_case(&symbol.name)
+                && !u.fq_name.eq_ignore_ascii_case(&symbol.fq_name)
+        });
+        imported
+            || index.find_by_prefix(&symbol.name).iter().any(|other| {
+                ImportKind::from(other.kind) == kind
+                    && other.name.eq_ignore_ascii_case(&symbol.name)
+                    && other
+                        .namespace()
+                        .eq_ignore_ascii_case(self.current_namespace())
+                    && !other.fq_name.eq_ignore_ascii_case(&symbol.fq_name)
+            })
+    }
+
+    ///
+    /// Create an edit adding a `use` statement for `fq_name`, placed in sorted order among
+    /// the existing imports
+    ///
+    pub fn get_import_edit(&self, kind: ImportKind, fq_name: &str) -> TextEdit {
+        let statement = match kind {
+            ImportKind::Class => format!("use {};", fq_name),
+            ImportKind::Function => format!("use function {};", fq_name),
+            ImportKind::Constant => format!("use const {};", fq_name),
+        };
+        let sort_key = |kind: ImportKind, name: &str| (kind as u8, name.to_lowercase());
+        let new_key = sort_key(kind, fq_name);
+
+        let (line, new_text) = if let Some(next) = self
+            .uses
+            .iter()
+            .find(|u| sort_key(u.kind, &u.fq_name) > new_key)
+        {
+            (next.line, format!("{}\n", statement))
+        } else if let Some(last) = self.uses.last() {
+            (last.line + 1, format!("{}\n", statement))
+        } else if let Some(ns_line) = self.namespace_line {
+            (ns_line + 1, format!("\n{}\n", statement))
+        } else if let Some(open_line) = self.open_tag_line {
+            (open_line + 1, format!("\n{}\n", statement))
+        } else {
+            (0, format!("{}\n", statement))
+        };
+
+        let position = Position { line, character: 0 };
+        TextEdit {
+            range: Range {
+                start: position,
+                end: position,
+            },
+            new_text,
+        }
+    }
+}
+
+///
+/// Keeps track of how to write class names in the file, and which of them need a new `use`
+///
+pub struct TypeNames {
+    imports: FileImports,
+    index: Arc<SymbolIndex>,
+    /// Imports to add, by the short name they take
+    new_imports: HashMap<String, String>,
+}
+
+impl TypeNames {
+    pub fn new(imports: FileImports, index: Arc<SymbolIndex>) -> Self {
+        Self {
+            imports,
+            index,
+            new_imports: HashMap::new(),
+        }
+    }
+
+    pub fn format(&mut self, utype: &UnionType) -> String {
+        let parts: Vec<String> = utype
+            .types
+            .iter()
+            .map(|t| match t {
+                DiscreteType::Named(_, fq_name) => self.class_name(&fq_name.to_string()),
+                _ => t.to_string(),
+            })
+            .collect();
+        parts.join("|")
+    }
+
+    ///
+    /// Write the class `fq_name` as the file can refer to it, importing it if its short name
+    /// is free, and fully qualified otherwise
+    ///
+    pub fn class_name(&mut self, fq_name: &str) -> String {
+        let fq_name = fq_name.trim_start_matches('\\').to_string();
+        let short_name = fq_name.rsplit('\\').next().unwrap_or("").to_string();
+
+        if self
+            .imports
+            .resolve_class_name(&short_name)
+            .eq_ignore_ascii_case(&fq_name)
+        {
+            return short_name;
+        }
+        let lc_short_name = short_name.to_lowercase();
+        match self.new_imports.get(&lc_short_name) {
+            Some(imported) if imported.eq_ignore_ascii_case(&fq_name) => short_name,
+            None if !self.is_name_taken(&fq_name, &short_name) => {
+                self.new_imports.insert(lc_short_name, fq_name);
+                short_name
+            }
+            _ => format!("\\{}", fq_name),
+        }
+    }
+
+    ///
+    /// Check if importing `fq_name` would clash with another import or a class in the
+    /// namespace of the file. Classes we don't know of are taken to clash, as there's no
+    /// telling what they'd shadow.
+    ///
+    fn is_name_taken(&self, fq_name: &str, short_name: &str) -> bool {
+        let symbol = self.index.find_by_prefix(short_name).iter().find(|s| {
+            ImportKind::from(s.kind) == ImportKind::Class && s.fq_name.eq_ignore_ascii_case(fq_name)
+        });
+        match symbol {
+            Some(symbol) => self.imports.is_name_taken(symbol, &self.index),
+            None => true,
+        }
+    }
+
+    pub fn get_import_edits(&self) -> Vec<TextEdit> {
+        let mut sorted: Vec<&String> = self.new_imports.values().collect();
+        sorted.sort_by_key(|fq_name| fq_name.to_lowercase());
+        sorted
+            .into_iter()
+            .map(|fq_name| self.imports.get_import_edit(ImportKind::Class, fq_name))
+            .collect()
+    }
+}
@@ -1,5 +1,10 @@
-use crate::codetree::codetree::CallbackProgress;
+use crate::codetree::cancellation::CancellationToken;
+use crate::codetree::class_hierarchy::ClassHierarchy;
 use crate::codetree::codetree::CodeTree;
+use crate::codetree::overlays::Overlays;
+use crate::codetree::references::ReferenceIndex;
+use crate::codetree::symbol_index::SymbolIndex;
+use crate::phpparser::phpfile::PHPFile;
 use phpanalyzer::analysis::state::AnalysisState;
 use phpanalyzer::autonodes::any::AnyNodeRef;
 use phpanalyzer::issue::Issue;
@@ -15,26 +20,54 @@ use rust_lsp::lsp::client_rpc_handle;
 use rust_lsp::lsp::LanguageServerHandling;
 use rust_lsp::lsp::LspClientRpc;
 use rust_lsp::lsp::LspClientRpc_;
+use rust_lsp::lsp_types::request::CallHierarchyIncomingCalls;
+use rust_lsp::lsp_types::request::CallHierarchyOutgoingCalls;
+use rust_lsp::lsp_types::request::CallHierarchyPrepare;
 use rust_lsp::lsp_types::request::GotoDeclaration;
 
 use rust_lsp::lsp_types::request::GotoDeclarationParams;
 use rust_lsp::lsp_types::request::GotoTypeDefinition;
 
 use rust_lsp::lsp_types::request::GotoTypeDefinitionParams;
+use rust_lsp::lsp_types::request::GotoImplementation;
+use rust_lsp::lsp_types::request::GotoImplementationParams;
+use rust_lsp::lsp_types::request::PrepareRenameRequest;
 use rust_lsp::lsp_types::*;
 use std::convert::TryInto;
+use std::path::Path;
 use std::sync::Arc;
 // use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering;
 use std::sync::RwLock;
+use std::sync::atomic::AtomicBool;
 
+use rust_lsp::lsp_types::notification::{Cancel, Notification, WorkDoneProgressCancel};
 use rust_lsp::lsp_types::request::Request;
 
+use super::analysis_queue::{AnalysisQueue, REANALYSIS_DEBOUNCE};
+use super::analysis_worker::{
+    AnalysisCallback, AnalysisWorker, FileAnalysisWorker, ANALYSIS_PROGRESS_TOKEN,
+};
+use super::call_hierarchy::{incoming_calls, outgoing_calls, prepare_call_hierarchy};
+use super::code_actions::{code_action_commands, execute_command, APPLY_EDIT_COMMAND};
+use super::completion::{completion, resolve_completion_item};
+use super::document_highlight::document_highlight;
+use super::document_symbols::flat_document_symbols;
 use super::goto_declaration::goto_declaration;
+use super::goto_implementation::goto_implementation;
 use super::goto_type_definition::goto_type_definition;
+use super::locations::{position_to_byte_position, range_to_lsp_range};
 use crate::phpls::goto_definition::goto_definition;
 use crate::phpls::hover::hover;
+use crate::phpls::quick_fix::get_fix_data;
+use crate::phpls::references::references;
+use crate::phpls::rename::{prepare_rename, rename};
+use crate::phpls::signature_help::signature_help;
+use crate::phpls::type_hierarchy::{
+    prepare_type_hierarchy, type_hierarchy_subtypes, type_hierarchy_supertypes,
+    TypeHierarchyParams, TypeHierarchyPrepare, TypeHierarchyPrepareParams,
+    TypeHierarchySubtypes, TypeHierarchySupertypes,
+};
+use crate::phpls::workspace_symbols::workspace_symbols;
 /*
 struct DiagnosticsEmitter {
     issues: RwLock<Vec<Diagnostic>>,
@@ -49,23 +82,12 @@ impl DiagnosticsEmitter {
 }
 */
 trait FromIssue {
-    fn from_issue(issue: &Issue) -> Diagnostic;
+    fn from_issue(issue: &Issue, source: &str) -> Diagnostic;
 }
 
 impl FromIssue for Diagnostic {
-    fn from_issue(issue: &Issue) -> Self {
-        let ts_range = issue.range();
-
-        let range = Range {
-            start: Position {
-                line: ts_range.start_point.row.try_into().unwrap(),
-                character: ts_range.start_point.column.try_into().unwrap(),
-            },
-            end: Position {
-                line: ts_range.end_point.row.try_into().unwrap(),
-                character: ts_range.end_point.column.try_into().unwrap(),
-            },
-        };
+    fn from_issue(issue: &Issue, source: &str) -> Self {
+        let range = range_to_lsp_range(source, &issue.range());
         let severity = Some(match issue.severity() {
             Severity::Hint => DiagnosticSeverity::Hint,
             Severity::Error => DiagnosticSeverity::Error,
@@ -81,7 +103,7 @@ impl FromIssue for Diagnostic {
             message: issue.as_string(),
             related_information: None,
             tags: issue.get_tags(),
-            data: None,
+            data: get_fix_data(issue),
         }
     }
 }
@@ -103,20 +125,6 @@ impl GetTagsFromIssue for Issue {
     }
 }
 
-struct InAnalysisState {
-    request_reanalysis: bool,
-    on_completion: Vec<Box<dyn FnOnce(&mut PHPLanguageServerInstance, Arc<CodeTree>) -> ()>>,
-}
-
-impl InAnalysisState {
-    fn new() -> Self {
-        Self {
-            request_reanalysis: false,
-            on_completion: vec![],
-        }
-    }
-}
-
 #[derive(Clone)]
 pub struct PHPLanguageServerInstanceClient {
     endpoint: Endpoint,
@@ -135,8 +143,16 @@ impl PHPLanguageServerInstanceClient {
 pub struct PHPLanguageServerInstance {
     endpoint: Endpoint,
     codetrees: Vec<Arc<CodeTree>>,
-    in_analyzing: RwLock<Option<InAnalysisState>>,
-    progress_registered: AtomicBool,
+    analysis_queue: Arc<AnalysisQueue<AnalysisCallback>>,
+    /// The edited files waiting to be reanalyzed
+    file_queue: Arc<AnalysisQueue<Url>>,
+    /// Stops the analysis in progress
+    analysis_cancellation: Arc<RwLock<CancellationToken>>,
+    /// Stops the position lookup of the request in progress
+    request_cancellation: RwLock<CancellationToken>,
+    progress_registered: Arc<AtomicBool>,
+    /// What the client told us it supports in `initialize`
+    client_capabilities: ClientCapabilities,
 }
 
 impl PHPLanguageServerInstance {
@@ -144,8 +160,12 @@ impl PHPLanguageServerInstance {
         PHPLanguageServerInstance {
             endpoint,
             codetrees: vec![],
-            in_analyzing: RwLock::new(None),
-            progress_registered: AtomicBool::new(false),
+            analysis_queue: Arc::new(AnalysisQueue::new(REANALYSIS_DEBOUNCE)),
+            file_queue: Arc::new(AnalysisQueue::new(REANALYSIS_DEBOUNCE)),
+            analysis_cancellation: Arc::new(RwLock::new(CancellationToken::new())),
+            request_cancellation: RwLock::new(CancellationToken::new()),
+            progress_registered: Arc::new(AtomicBool::new(false)),
+            client_capabilities: ClientCapabilities::default(),
         }
     }
 
@@ -161,6 +181,69 @@ impl PHPLanguageServerInstance {
         }
     }
 
+    pub fn request_cancelled<DATA>(data: DATA) -> MethodError<DATA> {
+        MethodError::<DATA> {
+            // RequestCancelled in the protocol
+            code: -32800,
+            message: "Request cancelled.".to_string(),
+            data: data,
+        }
+    }
+
+    ///
+    /// Use `cancellation` for the request about to be handled
+    ///
+    pub fn set_request_cancellation(&self, cancellation: CancellationToken) {
+        *self.request_cancellation.write().unwrap() = cancellation;
+    }
+
+    ///
+    /// Check if the client has cancelled the request in progress
+    ///
+    pub fn is_request_cancelled(&self) -> bool {
+        self.request_cancellation.read().unwrap().is_cancelled()
+    }
+
+    ///
+    /// Check if the client can show document symbols as a tree
+    ///
+    pub fn supports_hierarchical_document_symbols(&self) -> bool {
+        self.client_capabilities
+            .text_document
+            .as_ref()
+            .and_then(|t| t.document_symbol.as_ref())
+            .and_then(|d| d.hierarchical_document_symbol_support)
+            .unwrap_or(false)
+    }
+
+    ///
+    /// Check if the client can take code actions, rather than just commands
+    ///
+    pub fn supports_code_action_literals(&self) -> bool {
+        self.client_capabilities
+            .text_document
+            .as_ref()
+            .and_then(|t| t.code_action.as_ref())
+            .map(|c| c.code_action_literal_support.is_some())
+            .unwrap_or(false)
+    }
+
+    ///
+    /// Check if the client can ask the user to confirm annotated edits
+    ///
+    pub fn supports_change_annotations(&self) -> bool {
+        self.client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|w| w.workspace_edit.as_ref())
+            .map(|e| e.document_changes == Some(true) && e.change_annotation_support.is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn get_codetrees(&self) -> &Vec<Arc<CodeTree>> {
+        &self.codetrees
+    }
+
     fn get_codetree_for_uri(&self, uri: &Url) -> Option<Arc<CodeTree>> {
         for ct in &self.codetrees {
             if ct.contains_file(uri) {
@@ -170,169 +253,82 @@ impl PHPLanguageServerInstance {
         None
     }
 
-    pub fn when_completed_analysis(
-        &mut self,
-        uri: Url,
-        callback: Box<dyn FnOnce(&mut PHPLanguageServerInstance, Arc<CodeTree>) -> ()>,
-    ) {
+    pub fn get_file_for_uri(&self, uri: &Url) -> Option<PHPFile> {
+        self.get_codetree_for_uri(uri)?.analyze_file_uri(uri)
+    }
+
+    pub fn get_overlays_for_uri(&self, uri: &Url) -> Option<Arc<Overlays>> {
+        Some(self.get_codetree_for_uri(uri)?.overlays.clone())
+    }
+
+    pub fn get_symbol_data_for_uri(&self, uri: &Url) -> Option<Arc<SymbolData>> {
+        self.get_codetree_for_uri(uri)?.get_symbol_data()
+    }
+
+    pub fn get_references_for_uri(&self, uri: &Url) -> Option<Arc<ReferenceIndex>> {
+        Some(self.get_codetree_for_uri(uri)?.get_references())
+    }
+
+    pub fn get_class_hierarchy_for_uri(&self, uri: &Url) -> Option<Arc<ClassHierarchy>> {
+        Some(self.get_codetree_for_uri(uri)?.get_class_hierarchy())
+    }
+
+    pub fn get_symbol_index_for_uri(&self, uri: &Url) -> Option<Arc<SymbolIndex>> {
+        Some(self.get_codetree_for_uri(uri)?.get_symbol_index())
+    }
+
+    ///
+    /// Get the contents of the file at `path`, from the editor if it has unsaved changes.
+    /// Files outside the code trees, like stubs, are read from disk.
+    ///
+    pub fn get_source(&self, path: &Path) -> Option<String> {
+        let contents = match Url::from_file_path(path)
+            .ok()
+            .and_then(|uri| self.get_file_for_uri(&uri))
         {
-            let mut write = self.in_analyzing.write().unwrap();
-            if let Some(analysing) = &mut *write {
-                // If we're analyzing, pending for after
-                analysing.on_completion.push(callback);
-                return;
-            }
-        }
+            Some(file) => file.get_contents().ok()?,
+            None => std::fs::read(path).ok()?,
+        };
+        Some(String::from_utf8_lossy(&contents).to_string())
+    }
+
+    pub fn when_completed_analysis(&mut self, uri: Url, callback: AnalysisCallback) {
+        // If we're analyzing, pending for after
+        let callback = match self.analysis_queue.when_idle(callback) {
+            Some(callback) => callback,
+            None => return,
+        };
         if let Some(ct) = self.get_codetree_for_uri(&uri) {
-            let read_handle = ct.symbol_data.read().unwrap();
-            if let Some(_) = &*read_handle {
-                callback(self, ct.clone());
+            if ct.get_symbol_data().is_some() {
+                callback(&mut self.client(), ct.clone());
                 return;
             }
         }
-
-        panic!("");
-        // self.pending_analysis.push(callback);
-        // void
-    }
-
-    pub fn republish_diagnostics(&mut self, uri: Url) {
-        let code_tree = match self.get_codetree_for_uri(&uri) {
-            Some(ct) => ct,
-            _ => return,
-        };
-        let issues = code_tree.get_issues_for_uri(&uri);
-        let diagnostics = issues
-            .iter()
-            .map(|i| Diagnostic::from_issue(i))
-            .collect::<Vec<Diagnostic>>();
-        let diag_cnt = diagnostics.len();
-        let mut client_handle = self.client();
-        let res = client_handle
-            .client()
-            .publish_diagnostics(PublishDiagnosticsParams {
-                uri,
-                diagnostics,
-                version: None,
-            });
-
-        eprintln!("published {} diagnostics: {:?}", diag_cnt, res);
-
-        /*
-                for ct in &self.codetrees {
-                    if !ct.contains_file(&uri) {
-                        continue;
-                    }
-                }
-                let diagnostics = if let Some(file) = self.get_phpfile_for_uri(&uri) {
-                    let emitter = DiagnosticsEmitter::new();
-                    let data = if let Some(data) = &self.symbol_data {
-                        data.clone()
-                    } else {
-                        Arc::new(SymbolData::new())
-                    };
-                    file.analyze_with_symbol_data(&emitter, data);
-                    let x = emitter.issues.read().unwrap();
-                    x.clone()
-                } else {
-                    vec![]
-                };
-        */
+        // Never analyzed, so have it done
+        self.reanalyze(Some(uri), Some(callback));
     }
 
+    ///
+    /// Have the workspace analyzed in the background. `then` is called once the results
+    /// reflect what's on disk now.
+    ///
     pub(crate) fn reanalyze(
         &mut self,
         _uri: Option<Url>,
-        mut then: Option<Box<dyn FnOnce(&mut Self, Arc<CodeTree>) -> ()>>,
+        then: Option<AnalysisCallback>,
     ) -> Option<()> {
-        {
-            let mut write = self.in_analyzing.write().unwrap();
-            if let Some(in_analysis) = &mut *write {
-                in_analysis.request_reanalysis = true;
-                if let Some(then) = then.take() {
-                    in_analysis.on_completion.push(then);
-                }
-                return None;
-            }
-            let mut state = InAnalysisState::new();
-            if let Some(then) = then.take() {
-                state.on_completion.push(then);
-            }
-            (*write) = Some(state);
-            // end of write lock
-        }
-        let mut client_handle = self.client();
-        let progress_token = NumberOrString::String("oh_by_the_way".to_string());
-
-        if !self.progress_registered.swap(true, Ordering::Relaxed) {
-            client_handle
-                .client()
-                .window_work_done_progress_create(WorkDoneProgressCreateParams {
-                    token: progress_token.clone(),
-                });
-        }
-        client_handle.client().progress(ProgressParams {
-            token: progress_token.clone(),
-            value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(WorkDoneProgressBegin {
-                title: "Analyserer".to_string(),
-                cancellable: Some(false),
-                message: Some("message...".into()),
-                percentage: Some(0),
-            })),
-        });
-
-        let thread_count = 8;
-        let cb_client_handle = client_handle.clone();
-        let cb_token = progress_token.clone();
-        let status = Arc::new(CallbackProgress::new(Box::new(move |percent, ident| {
-            let mut handle = cb_client_handle.clone();
-            handle.client().progress(ProgressParams {
-                token: cb_token.clone(),
-                value: ProgressParamsValue::WorkDone(WorkDoneProgress::Report(
-                    WorkDoneProgressReport {
-                        cancellable: Some(false),
-                        message: Some(ident),
-                        percentage: Some(percent.try_into().ok().unwrap_or(0)),
-                    },
-                )),
-            });
-        })));
-        for ct in &self.codetrees {
-            match ct.run_analysis(thread_count, status.clone()) {
-                Ok(_) => (),
-                Err(e) => eprintln!("Error: {}", e),
-            }
+        if !self.analysis_queue.request(then) {
+            // The run in progress will do another round for this
+            return None;
         }
-
-        let mut state = {
-            let mut write = self.in_analyzing.write().unwrap();
-            write
-                .take()
-                .expect("This must exist, or we're in a broken state")
-            // End of write lock
-        };
-
-        client_handle.client().progress(ProgressParams {
-            token: progress_token,
-            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
-                message: Some("Done.".into()),
-            })),
-        });
-
-        for cb in state.on_completion.drain(..) {
-            let ct = self.codetrees.get(0).unwrap().clone();
-            cb(self, ct);
-            /*             for ct in &self.codetrees {
-                cb(self, ct);
-                break;
-            }*/
+        AnalysisWorker {
+            client: self.client(),
+            codetrees: self.codetrees.clone(),
+            queue: self.analysis_queue.clone(),
+            cancellation: self.analysis_cancellation.clone(),
+            progress_registered: self.progress_registered.clone(),
         }
-
-        if state.request_reanalysis {
-            eprintln!("Skipping reanalysis");
-            // self.reanalyze(None, None);
-        }
-
+        .spawn();
         None
     }
 
@@ -362,15 +358,26 @@ impl PHPLanguageServerInstance {
         let file = codetree.analyze_file_uri(&uri);
 
         let symbol_data = codetree.get_symbol_data();
-
-        let line: Result<usize, _> = position.position.line.try_into();
-        let charpos: Result<usize, _> = position.position.character.try_into();
+        let cancel = self.request_cancellation.read().unwrap().clone();
+
+        // The analyzer has the column in bytes
+        let source = file
+            .as_ref()
+            .and_then(|f| f.get_contents().ok())
+            .map(|c| String::from_utf8_lossy(&c).to_string());
+        let position = match &source {
+            Some(source) => position_to_byte_position(source, &position.position),
+            None => position.position,
+        };
+        let line: Result<usize, _> = position.line.try_into();
+        let charpos: Result<usize, _> = position.character.try_into();
         match (file, line, charpos) {
             (Some(file), Ok(line), Ok(character)) => {
                 match file.analyze_with_callback_at_position(
                     line,
                     character,
                     symbol_data.clone(),
+                    &cancel,
                     callback,
                 ) {
                     Ok(res) => Ok((symbol_data, res)),
@@ -381,15 +388,33 @@ impl PHPLanguageServerInstance {
             _ => Ok((symbol_data, None)),
         }
     }
-}
 
-impl LanguageServerHandling for PHPLanguageServerInstance {
-    fn initialize(
-        &mut self,
-        params: InitializeParams,
-        completable: MethodCompletable<InitializeResult, InitializeError>,
-    ) {
+    ///
+    /// Have `uri` reanalyzed with its contents in the editor, saved or not, once the edits
+    /// have stopped for a moment. Only the file and the files depending on it are analyzed,
+    /// in the background, and their diagnostics published.
+    ///
+    fn reanalyze_document(&mut self, uri: Url) {
+        if !self.file_queue.request(Some(uri)) {
+            // The worker running picks it up
+            return;
+        }
+        FileAnalysisWorker {
+            client: self.client(),
+            codetrees: self.codetrees.clone(),
+            queue: self.file_queue.clone(),
+            analysis_queue: self.analysis_queue.clone(),
+        }
+        .spawn();
+    }
+
+    ///
+    /// Handles the initialize request and builds the result, the shared part of
+    /// `initialize` and `PHPRequestHandler`
+    ///
+    pub fn initialize_result(&mut self, params: InitializeParams) -> InitializeResult {
         eprintln!("Her er vi i initialize med");
+        self.client_capabilities = params.capabilities.clone();
         if let Some(process_id) = params.process_id {
             eprintln!("  params.process_id: {}", process_id);
         }
@@ -424,7 +449,7 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
         capabilities.text_document_sync = Some(TextDocumentSyncCapability::Options(
             TextDocumentSyncOptions {
                 open_close: Some(true),
-                change: Some(TextDocumentSyncKind::None),
+                change: Some(TextDocumentSyncKind::Incremental),
                 will_save: None,
                 will_save_wait_until: None,
                 save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
@@ -433,10 +458,41 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
             },
         ));
 
+        capabilities.completion_provider = Some(CompletionOptions {
+            resolve_provider: Some(true),
+            trigger_characters: Some(vec![
+                ">".to_string(),
+                ":".to_string(),
+                "$".to_string(),
+            ]),
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+            all_commit_characters: None,
+        });
+
+        capabilities.signature_help_provider = Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+            retrigger_characters: None,
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+        });
+
         // provide hover-text
         capabilities.hover_provider = Some(HoverProviderCapability::Simple(true));
 
         capabilities.references_provider = Some(OneOf::Left(true));
+        capabilities.document_highlight_provider = Some(OneOf::Left(true));
+        capabilities.document_symbol_provider = Some(OneOf::Left(true));
+        capabilities.workspace_symbol_provider = Some(OneOf::Left(true));
+        capabilities.call_hierarchy_provider = Some(CallHierarchyServerCapability::Simple(true));
+        capabilities.rename_provider = Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+        }));
         // provide goto definition
         capabilities.definition_provider = Some(OneOf::Right(DefinitionOptions {
             work_done_progress_options: WorkDoneProgressOptions {
@@ -455,6 +511,12 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
         capabilities.type_definition_provider =
             Some(TypeDefinitionProviderCapability::Simple(true));
 
+        capabilities.code_action_provider = Some(CodeActionProviderCapability::Simple(true));
+        capabilities.execute_command_provider = Some(ExecuteCommandOptions {
+            commands: vec![APPLY_EDIT_COMMAND.to_string()],
+            work_done_progress_options: Default::default(),
+        });
+
         //         capabilities.
         let server_info = ServerInfo {
             name: String::from("phplint"),
@@ -492,12 +554,51 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
             workspace_symbol_provider: None,
         };*/
 
-        completable.complete(Ok(InitializeResult {
+        InitializeResult {
             capabilities: capabilities,
             server_info: Some(server_info),
             // offset_encoding: None,
-        }));
+        }
+    }
+}
 
+///
+/// Send the issues of the last analysis of `uri` to the editor
+///
+pub(crate) fn publish_diagnostics(
+    client_handle: &mut PHPLanguageServerInstanceClient,
+    code_tree: &CodeTree,
+    uri: Url,
+) {
+    let issues = code_tree.get_issues_for_uri(&uri);
+    let source = code_tree
+        .analyze_file_uri(&uri)
+        .and_then(|f| f.get_contents().ok())
+        .map(|c| String::from_utf8_lossy(&c).to_string())
+        .unwrap_or_default();
+    let diagnostics = issues
+        .iter()
+        .map(|i| Diagnostic::from_issue(i, &source))
+        .collect::<Vec<Diagnostic>>();
+    let diag_cnt = diagnostics.len();
+    let res = client_handle
+        .client()
+        .publish_diagnostics(PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        });
+
+    eprintln!("published {} diagnostics: {:?}", diag_cnt, res);
+}
+
+impl LanguageServerHandling for PHPLanguageServerInstance {
+    fn initialize(
+        &mut self,
+        params: InitializeParams,
+        completable: MethodCompletable<InitializeResult, InitializeError>,
+    ) {
+        completable.complete(Ok(self.initialize_result(params)));
         self.reanalyze(None, None);
     }
 
@@ -516,31 +617,50 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
     fn did_open_text_document(&mut self, params: DidOpenTextDocumentParams) {
         eprintln!("did_open_text_document");
         let uri = params.text_document.uri;
+        if let (Some(codetree), Ok(path)) = (self.get_codetree_for_uri(&uri), uri.to_file_path()) {
+            codetree.overlays.open(
+                path,
+                params.text_document.version,
+                params.text_document.text,
+            );
+        }
         self.when_completed_analysis(
             uri.clone(),
-            Box::new(|server, _codetree| {
-                server.republish_diagnostics(uri);
+            Box::new(|client, codetree| {
+                publish_diagnostics(client, &codetree, uri);
             }),
         );
     }
 
-    fn did_change_text_document(&mut self, _params: DidChangeTextDocumentParams) {
-        eprintln!("did_change_text_document")
+    fn did_change_text_document(&mut self, params: DidChangeTextDocumentParams) {
+        eprintln!("did_change_text_document");
+        let uri = params.text_document.uri;
+        if let (Some(codetree), Ok(path)) = (self.get_codetree_for_uri(&uri), uri.to_file_path()) {
+            if let Err(e) = codetree.overlays.change(
+                &path,
+                params.text_document.version,
+                params.content_changes,
+            ) {
+                eprintln!("ERROR: could not apply change to {}: {}", uri, e);
+                return;
+            }
+        }
+        self.reanalyze_document(uri);
     }
 
-    fn did_close_text_document(&mut self, _params: DidCloseTextDocumentParams) {
-        eprintln!("did_close_text_document")
+    fn did_close_text_document(&mut self, params: DidCloseTextDocumentParams) {
+        eprintln!("did_close_text_document");
+        let uri = params.text_document.uri;
+        if let (Some(codetree), Ok(path)) = (self.get_codetree_for_uri(&uri), uri.to_file_path()) {
+            codetree.overlays.close(&path);
+        }
+        // Back to what's on disk
+        self.reanalyze_document(uri);
     }
 
     fn did_save_text_document(&mut self, params: DidSaveTextDocumentParams) {
         eprintln!("did_save_text_document");
-        let uri = params.text_document.uri.clone();
-        self.reanalyze(
-            Some(uri.clone()),
-            Some(Box::new(|server, _codetree| {
-                server.republish_diagnostics(uri)
-            })),
-        );
+        self.reanalyze_document(params.text_document.uri);
     }
 
     fn did_change_watched_files(&mut self, _params: DidChangeWatchedFilesParams) {
@@ -556,20 +676,20 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
 
     fn completion(
         &mut self,
-        _params: TextDocumentPositionParams,
+        params: TextDocumentPositionParams,
         completable: MethodCompletable<CompletionList, ()>,
     ) {
         eprintln!("completion");
-        completable.complete(Err(Self::error_not_available(())));
+        completion(self, params, completable);
     }
 
     fn resolve_completion_item(
         &mut self,
-        _item: CompletionItem,
+        item: CompletionItem,
         completable: MethodCompletable<CompletionItem, ()>,
     ) {
         eprintln!("resolve_completion_item");
-        completable.complete(Err(Self::error_not_available(())));
+        resolve_completion_item(self, item, completable);
     }
 
     fn hover(
@@ -582,11 +702,11 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
 
     fn signature_help(
         &mut self,
-        _params: TextDocumentPositionParams,
+        params: TextDocumentPositionParams,
         completable: MethodCompletable<SignatureHelp, ()>,
     ) {
         eprintln!("signature_help");
-        completable.complete(Err(Self::error_not_available(())));
+        signature_help(self, params, completable);
     }
 
     fn goto_definition(
@@ -601,47 +721,47 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
 
     fn references(
         &mut self,
-        _params: ReferenceParams,
+        params: ReferenceParams,
         completable: MethodCompletable<std::vec::Vec<Location>, ()>,
     ) {
         eprintln!("references");
-        completable.complete(Err(Self::error_not_available(())));
+        references(self, params, completable);
     }
 
     fn document_highlight(
         &mut self,
-        _params: TextDocumentPositionParams,
+        params: TextDocumentPositionParams,
         completable: MethodCompletable<std::vec::Vec<DocumentHighlight>, ()>,
     ) {
         eprintln!("document_highlight");
-        completable.complete(Err(Self::error_not_available(())));
+        document_highlight(self, params, completable);
     }
 
     fn document_symbols(
         &mut self,
-        _params: DocumentSymbolParams,
+        params: DocumentSymbolParams,
         completable: MethodCompletable<std::vec::Vec<SymbolInformation>, ()>,
     ) {
         eprintln!("document_symbols");
-        completable.complete(Err(Self::error_not_available(())));
+        flat_document_symbols(self, params, completable);
     }
 
     fn workspace_symbols(
         &mut self,
-        _params: WorkspaceSymbolParams,
+        params: WorkspaceSymbolParams,
         completable: MethodCompletable<std::vec::Vec<SymbolInformation>, ()>,
     ) {
         eprintln!("workspace_symbols");
-        completable.complete(Err(Self::error_not_available(())));
+        workspace_symbols(self, params, completable);
     }
 
     fn code_action(
         &mut self,
-        _params: CodeActionParams,
+        params: CodeActionParams,
         completable: MethodCompletable<std::vec::Vec<Command>, ()>,
     ) {
         eprintln!("code_action");
-        completable.complete(Err(Self::error_not_available(())));
+        code_action_commands(self, params, completable);
     }
 
     fn code_lens(
@@ -703,18 +823,18 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
         completable.complete(Err(Self::error_not_available(())));
     }
 
-    fn rename(&mut self, _params: RenameParams, completable: MethodCompletable<WorkspaceEdit, ()>) {
+    fn rename(&mut self, params: RenameParams, completable: MethodCompletable<WorkspaceEdit, ()>) {
         eprintln!("rename");
-        completable.complete(Err(Self::error_not_available(())));
+        rename(self, params, completable);
     }
 
     fn execute_command(
         &mut self,
-        _params: ExecuteCommandParams,
+        params: ExecuteCommandParams,
         completable: MethodCompletable<Option<serde_json::value::Value>, ()>,
     ) {
         eprintln!("execute_command");
-        completable.complete(Err(Self::error_not_available(())));
+        execute_command(self, params, completable);
     }
 
     fn handle_other_method(
@@ -738,6 +858,83 @@ impl LanguageServerHandling for PHPLanguageServerInstance {
                 },
             ),
 
+            GotoImplementation::METHOD => completable.handle_request_with(
+                params,
+                |params: GotoImplementationParams, completable| {
+                    goto_implementation(self, params, completable)
+                },
+            ),
+
+            CallHierarchyPrepare::METHOD => completable.handle_request_with(
+                params,
+                |params: CallHierarchyPrepareParams, completable| {
+                    prepare_call_hierarchy(self, params, completable)
+                },
+            ),
+
+            CallHierarchyIncomingCalls::METHOD => completable.handle_request_with(
+                params,
+                |params: CallHierarchyIncomingCallsParams, completable| {
+                    incoming_calls(self, params, completable)
+                },
+            ),
+
+            CallHierarchyOutgoingCalls::METHOD => completable.handle_request_with(
+                params,
+                |params: CallHierarchyOutgoingCallsParams, completable| {
+                    outgoing_calls(self, params, completable)
+                },
+            ),
+
+            TypeHierarchyPrepare::METHOD => completable.handle_request_with(
+                params,
+                |params: TypeHierarchyPrepareParams, completable| {
+                    prepare_type_hierarchy(self, params, completable)
+                },
+            ),
+
+            TypeHierarchySupertypes::METHOD => completable.handle_request_with(
+                params,
+                |params: TypeHierarchyParams, completable| {
+                    type_hierarchy_supertypes(self, params, completable)
+                },
+            ),
+
+            TypeHierarchySubtypes::METHOD => completable.handle_request_with(
+                params,
+                |params: TypeHierarchyParams, completable| {
+                    type_hierarchy_subtypes(self, params, completable)
+                },
+            ),
+
+            PrepareRenameRequest::METHOD => completable.handle_request_with(
+                params,
+                |params: TextDocumentPositionParams, completable| {
+                    prepare_rename(self, params, completable)
+                },
+            ),
+
+            WorkDoneProgressCancel::METHOD => completable.handle_notification_with(
+                params,
+                |params: WorkDoneProgressCancelParams| {
+                    if params.token
+                        == NumberOrString::String(ANALYSIS_PROGRESS_TOKEN.to_string())
+                    {
+                        eprintln!("Avbryter analysen");
+                        self.analysis_cancellation.read().unwrap().cancel();
+                    }
+                },
+            ),
+
+            Cancel::METHOD => completable.handle_notification_with(
+                params,
+                |params: CancelParams| {
+                    // RequestTracker acted on this as soon as it arrived, by now the request
+                    // has been answered
+                    eprintln!("Avbrutt request {:?}", params.id);
+                },
+            ),
+
             // Other
             _ => completable.complete_with_error(
                 rust_lsp::jsonrpc::jsonrpc_common::error_JSON_RPC_MethodNotFound(),
@@ -1,34 +1,174 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::ffi::OsString;
+use std::path::Path;
 
 use phpanalyzer::symboldata::FileLocation;
 use rust_lsp::lsp_types::{Location, Position, Range};
 use url::Url;
 
+use super::instance::PHPLanguageServerInstance;
+
+///
+/// Converts locations from the analyzer, where columns are in bytes, to the protocol, where
+/// characters are counted in UTF-16. Each file is read once, as the editor has it.
+///
+pub struct LocationConverter<'a> {
+    phpls: &'a PHPLanguageServerInstance,
+    sources: HashMap<OsString, Option<String>>,
+}
+
+impl<'a> LocationConverter<'a> {
+    pub fn new(phpls: &'a PHPLanguageServerInstance) -> Self {
+        Self {
+            phpls,
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn location(&mut self, file_location: FileLocation) -> Location {
+        let path = file_location.uri.to_string_lossy().to_string();
+        // Locations from the analyzer are plain paths
+        let uri: Url = Url::from_file_path(&path)
+            .or_else(|_| Url::parse(&path))
+            .unwrap_or_else(|_| Url::parse("file://unknown_or_unparseable").unwrap());
+        let phpls = self.phpls;
+        let source = self
+            .sources
+            .entry(file_location.uri.clone())
+            .or_insert_with(|| phpls.get_source(Path::new(&file_location.uri)));
+        let (start, end) = (&file_location.start, &file_location.end);
+        let range = match source {
+            Some(source) => Range {
+                start: point_to_position(source, start.line, start.column),
+                end: point_to_position(source, end.line, end.column),
+            },
+            // Without the file, the columns are as good as it gets
+            None => Range {
+                start: Position {
+                    line: start.line.try_into().unwrap(),
+                    character: start.column.try_into().unwrap(),
+                },
+                end: Position {
+                    line: end.line.try_into().unwrap(),
+                    character: end.column.try_into().unwrap(),
+                },
+            },
+        };
+        Location::new(uri, range)
+    }
+
+    pub fn locations(&mut self, file_locations: Vec<FileLocation>) -> Vec<Location> {
+        file_locations
+            .into_iter()
+            .map(|l| self.location(l))
+            .collect()
+    }
+}
+
+///
+/// Convert a range from the syntax tree of `source` to a range in the protocol
+///
+pub fn range_to_lsp_range(source: &str, range: &phpanalyzer::Range) -> Range {
+    Range {
+        start: point_to_position(source, range.start_point.row, range.start_point.column),
+        end: point_to_position(source, range.end_point.row, range.end_point.column),
+    }
+}
+
 ///
+/// Convert a position from the analyzer, with the column in bytes, to a protocol position
 ///
-pub fn file_location_to_location(file_location: FileLocation) -> Location {
-    let uri: Url = Url::parse(&file_location.uri.to_string_lossy().to_string())
-        .unwrap_or_else(|_| Url::parse("file://unknown_or_unparseable").unwrap());
-    let range = Range {
-        start: Position {
-            line: file_location.start.line.try_into().unwrap(),
-            character: file_location.start.column.try_into().unwrap(),
-            // void
-        },
-        end: Position {
-            line: file_location.end.line.try_into().unwrap(),
-            character: file_location.end.column.try_into().unwrap(),
-            // void
-        },
+pub fn point_to_position(source: &str, line: usize, column: usize) -> Position {
+    let position = Position {
+        line: line as u32,
+        character: column as u32,
     };
-    Location::new(uri, range)
+    offset_to_position(source, position_to_byte_offset(source, &position))
 }
 
-pub fn file_locations_to_locations(file_locations: Vec<FileLocation>) -> Vec<Location> {
-    let mut file_locations = file_locations;
-    file_locations
-        
-        .drain(..)
-        .map(file_location_to_location)
-        .collect()
+///
+/// Convert a protocol position to one with the character as a byte column, like the analyzer
+/// has it
+///
+pub fn position_to_byte_position(source: &str, position: &Position) -> Position {
+    byte_offset_to_position(source, position_to_offset(source, position))
+}
+
+///
+/// Find the byte offset of a position in `source`, where the character is a byte column like
+/// in the syntax tree. Positions past the end of a line or the source are clamped, and
+/// positions inside a character, as when the source changed since the position was made,
+/// are moved to its start.
+///
+pub fn position_to_byte_offset(source: &str, position: &Position) -> usize {
+    let mut offset = 0;
+    for (lineno, line) in source.split('\n').enumerate() {
+        if lineno as u32 == position.line {
+            let mut column = (position.character as usize).min(line.len());
+            while !line.is_char_boundary(column) {
+                column -= 1;
+            }
+            return offset + column;
+        }
+        offset += line.len() + 1;
+    }
+    source.len()
+}
+
+///
+/// The position of a byte offset in `source`, with the character as a byte column
+///
+pub fn byte_offset_to_position(source: &str, offset: usize) -> Position {
+    let before = &source.as_bytes()[..offset.min(source.len())];
+    let line = before.iter().filter(|&&b| b == b'\n').count();
+    let column = before.len() - before.iter().rposition(|&b| b == b'\n').map_or(0, |idx| idx + 1);
+    Position {
+        line: line as u32,
+        character: column as u32,
+    }
+}
+
+///
+/// Convert a protocol position to a byte offset. Characters are counted in UTF-16 code units,
+/// and positions past the end of a line or the document are clamped.
+///
+pub fn position_to_offset(text: &str, position: &Position) -> usize {
+    let mut offset = 0;
+    for (lineno, line) in text.split_inclusive('\n').enumerate() {
+        if lineno as u32 == position.line {
+            let content = line.trim_end_matches(|c| c == '\n' || c == '\r');
+            let mut units = 0;
+            for (idx, c) in content.char_indices() {
+                if units >= position.character {
+                    return offset + idx;
+                }
+                units += c.len_utf16() as u32;
+            }
+            return offset + content.len();
+        }
+        offset += line.len();
+    }
+    text.len()
+}
+
+///
+/// The protocol position of a byte offset in `text`, with the character counted in UTF-16
+/// code units
+///
+pub fn offset_to_position(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let line_start = text[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+    let line = text[..line_start].matches('\n').count();
+    let character: usize = text[line_start..offset]
+        .chars()
+        .map(|c| c.len_utf16())
+        .sum();
+    Position {
+        line: line as u32,
+        character: character as u32,
+    }
 }
@@ -0,0 +1,330 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use phpanalyzer::{
+    symboldata::{
+        class::{ClassMemberVisibility, ClassName, ClassType, MethodData},
+        FileLocation, SymbolData,
+    },
+    symbols::{FullyQualifiedName, Name},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemberKind {
+    Method,
+    Property,
+    Constant,
+}
+
+///
+/// A method, property or constant reachable from a class, together with
+/// the class it was found in
+///
+#[derive(Clone, Debug)]
+pub struct ClassMember {
+    pub name: String,
+    pub kind: MemberKind,
+    pub is_static: bool,
+    pub is_abstract: bool,
+    pub visibility: ClassMemberVisibility,
+    /// The class that owns the member. For members coming from a trait this is
+    /// the class using the trait, as that is where the member ends up.
+    pub declared_in: FullyQualifiedName,
+    pub type_description: Option<String>,
+    pub position: Option<FileLocation>,
+    /// The summary from the PHPDoc of the member
+    pub description: String,
+    pub deprecated: Option<String>,
+}
+
+///
+/// Get all classes, interfaces and traits `fq_name` inherits from, starting with
+/// `fq_name` itself. Each class is only listed once.
+///
+/// The order follows how PHP looks up members: the class itself, then the traits it uses,
+/// then the parent class with its own traits and so on. Interfaces come after the classes
+/// and traits that implement them.
+///
+pub fn get_ancestors(
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+) -> Vec<FullyQualifiedName> {
+    let mut seen = HashSet::new();
+    let mut ancestors = vec![];
+    collect_ancestors(symbol_data, fq_name, &mut seen, &mut ancestors);
+    ancestors
+}
+
+fn collect_ancestors(
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+    seen: &mut HashSet<FullyQualifiedName>,
+    ancestors: &mut Vec<FullyQualifiedName>,
+) {
+    if !seen.insert(fq_name.clone()) {
+        return;
+    }
+    ancestors.push(fq_name.clone());
+
+    let parents = get_parents_by_kind(symbol_data, fq_name);
+    for parent in parents
+        .traits
+        .iter()
+        .chain(parents.base.iter())
+        .chain(parents.interfaces.iter())
+    {
+        collect_ancestors(symbol_data, &parent.fq_name, seen, ancestors);
+    }
+}
+
+///
+/// The direct parents of a class, interface or trait, by how they are inherited
+///
+#[derive(Default)]
+struct DirectParents {
+    base: Option<ClassName>,
+    interfaces: Vec<ClassName>,
+    traits: Vec<ClassName>,
+}
+
+fn get_parents_by_kind(symbol_data: &SymbolData, fq_name: &FullyQualifiedName) -> DirectParents {
+    let class = if let Some(class) = symbol_data.get_class(fq_name) {
+        class
+    } else {
+        return DirectParents::default();
+    };
+    let class = class.read().unwrap();
+    match &*class {
+        ClassType::Class(c) => DirectParents {
+            base: c.base_class_name.clone(),
+            interfaces: c.interfaces.clone(),
+            traits: c.traits.clone(),
+        },
+        ClassType::Interface(i) => DirectParents {
+            interfaces: i.base_interface_names.clone().unwrap_or_default(),
+            ..DirectParents::default()
+        },
+        ClassType::Trait(t) => DirectParents {
+            traits: t.traits.clone(),
+            ..DirectParents::default()
+        },
+        ClassType::None => DirectParents::default(),
+    }
+}
+
+///
+/// Get the class `fq_name` extends, if any. Interfaces and traits have no base class.
+///
+pub fn get_base_class(
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+) -> Option<FullyQualifiedName> {
+    get_parents_by_kind(symbol_data, fq_name)
+        .base
+        .map(|base| base.fq_name)
+}
+
+///
+/// Get the base class, implemented/extended interfaces and used traits of a class
+///
+pub fn get_direct_parents(symbol_data: &SymbolData, fq_name: &FullyQualifiedName) -> Vec<ClassName> {
+    let parents = get_parents_by_kind(symbol_data, fq_name);
+    parents
+        .base
+        .into_iter()
+        .chain(parents.interfaces)
+        .chain(parents.traits)
+        .collect()
+}
+
+///
+/// Collect every member available on `fq_name`, including the ones inherited from parents,
+/// interfaces and traits. A member declared closer to `fq_name` hides an inherited one with
+/// the same name.
+///
+pub fn get_class_members(symbol_data: &SymbolData, fq_name: &FullyQualifiedName) -> Vec<ClassMember> {
+    let mut seen: HashSet<(MemberKind, String)> = HashSet::new();
+    let mut members = vec![];
+
+    for ancestor in get_ancestors(symbol_data, fq_name) {
+        let class = if let Some(class) = symbol_data.get_class(&ancestor) {
+            class
+        } else {
+            continue;
+        };
+        let is_trait = matches!(&*class.read().unwrap(), ClassType::Trait(_));
+        let owner = if is_trait {
+            // Trait members are copied into the class using the trait
+            get_trait_user(symbol_data, fq_name, &ancestor).unwrap_or_else(|| ancestor.clone())
+        } else {
+            ancestor.clone()
+        };
+
+        for member in get_declared_members(&class.read().unwrap(), &owner) {
+            // PHP method and constant names are case insensitive, properties are not
+            let key = match member.kind {
+                MemberKind::Property => member.name.clone(),
+                _ => member.name.to_lowercase(),
+            };
+            if seen.insert((member.kind, key)) {
+                members.push(member);
+            }
+        }
+    }
+    members
+}
+
+///
+/// Get the position of the declaration of a class, interface or trait
+///
+pub fn get_class_position(
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+) -> Option<FileLocation> {
+    let class = symbol_data.get_class(fq_name)?;
+    let class = class.read().unwrap();
+    match &*class {
+        ClassType::Class(c) => Some(c.position.clone()),
+        ClassType::Interface(i) => Some(i.position.clone()),
+        ClassType::Trait(t) => Some(t.position.clone()),
+        ClassType::None => None,
+    }
+}
+
+///
+/// Find a method by name on `fq_name` or the closest ancestor declaring it
+///
+pub fn get_method(
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+    method_name: &str,
+) -> Option<Arc<RwLock<MethodData>>> {
+    let lc_name = Name::from(method_name.to_lowercase().as_str());
+    for ancestor in get_ancestors(symbol_data, fq_name) {
+        let class = if let Some(class) = symbol_data.get_class(&ancestor) {
+            class
+        } else {
+            continue;
+        };
+        let class = class.read().unwrap();
+        let methods = match &*class {
+            ClassType::Class(c) => &c.methods,
+            ClassType::Interface(i) => &i.methods,
+            ClassType::Trait(t) => &t.methods,
+            ClassType::None => continue,
+        };
+        // Method names are stored lower cased, as PHP is case insensitive about them
+        if let Some(method) = methods.get(&lc_name) {
+            return Some(method.clone());
+        }
+    }
+    None
+}
+
+///
+/// Find the class in the hierarchy of `fq_name` which uses the trait `trait_name`
+///
+fn get_trait_user(
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+    trait_name: &FullyQualifiedName,
+) -> Option<FullyQualifiedName> {
+    get_ancestors(symbol_data, fq_name)
+        .into_iter()
+        .filter(|c| c != trait_name)
+        .find(|c| {
+            get_direct_parents(symbol_data, c)
+                .iter()
+                .any(|p| &p.fq_name == trait_name)
+        })
+}
+
+///
+/// Get the members declared directly in a class, interface or trait
+///
+pub fn get_declared_members(class: &ClassType, owner: &FullyQualifiedName) -> Vec<ClassMember> {
+    let mut members = vec![];
+
+    let (methods, properties, constants) = match class {
+        ClassType::Class(c) => (Some(&c.methods), Some(&c.properties), Some(&c.constants)),
+        ClassType::Interface(i) => (Some(&i.methods), None, Some(&i.constants)),
+        ClassType::Trait(t) => (Some(&t.methods), Some(&t.properties), None),
+        ClassType::None => (None, None, None),
+    };
+    let is_interface = matches!(class, ClassType::Interface(_));
+
+    if let Some(methods) = methods {
+        for method in methods.values() {
+            let method = method.read().unwrap();
+            members.push(ClassMember {
+                name: method.name.to_string(),
+                kind: MemberKind::Method,
+                is_static: method.is_static,
+                is_abstract: method.is_abstract || is_interface,
+                visibility: method.visibility.clone(),
+                declared_in: owner.clone(),
+                type_description: method.get_return_type().map(|t| t.to_string()),
+                position: Some(method.position.clone()),
+                description: method.description.clone(),
+                deprecated: method.deprecated.clone(),
+            });
+        }
+    }
+    if let Some(properties) = properties {
+        for property in properties.values() {
+            let property = property.read().unwrap();
+            members.push(ClassMember {
+                name: property.name.to_string(),
+                kind: MemberKind::Property,
+                is_static: property.is_static,
+                is_abstract: false,
+                visibility: property.visibility.clone(),
+                declared_in: owner.clone(),
+                type_description: property.get_utype().map(|t| t.to_string()),
+                position: Some(property.position.clone()),
+                description: property.description.clone(),
+                deprecated: property.deprecated.clone(),
+            });
+        }
+    }
+    if let Some(constants) = constants {
+        for constant in constants.values() {
+            members.push(ClassMember {
+                name: constant.name.to_string(),
+                kind: MemberKind::Constant,
+                is_static: true,
+                is_abstract: false,
+                visibility: constant.visibility.clone(),
+                declared_in: owner.clone(),
+                type_description: constant.get_utype().map(|t| t.to_string()),
+                position: Some(constant.position.clone()),
+                description: constant.description.clone(),
+                deprecated: constant.deprecated.clone(),
+            });
+        }
+    }
+    members
+}
+
+///
+/// Check if `member` can be accessed from code inside `from_class`, or from outside
+/// of any class when `from_class` is `None`
+///
+pub fn is_member_visible(
+    symbol_data: &SymbolData,
+    member: &ClassMember,
+    from_class: Option<&FullyQualifiedName>,
+) -> bool {
+    match member.visibility {
+        ClassMemberVisibility::Public => true,
+        ClassMemberVisibility::Private => from_class == Some(&member.declared_in),
+        ClassMemberVisibility::Protected => {
+            if let Some(from_class) = from_class {
+                get_ancestors(symbol_data, from_class).contains(&member.declared_in)
+                    || get_ancestors(symbol_data, &member.declared_in).contains(from_class)
+            } else {
+                false
+            }
+        }
+    }
+}
@@ -1,8 +1,31 @@
+pub mod analysis_queue;
+pub mod analysis_worker;
+pub mod call_hierarchy;
+pub mod code_actions;
+pub mod completion;
+pub mod document_highlight;
+pub mod document_symbols;
+pub mod extract;
 pub mod goto_declaration;
 pub mod goto_definition;
+pub mod goto_implementation;
 pub mod goto_type_definition;
 pub mod hover;
+pub mod implement_methods;
+pub mod imports;
 pub mod instance;
 pub mod stdioserver;
 pub mod tcpserver;
+pub mod type_hierarchy;
+pub mod variable_rename;
+pub mod variables;
 pub mod locations;
+pub mod members;
+pub mod quick_fix;
+pub mod references;
+pub mod request_handler;
+pub mod request_tracker;
+pub mod rename;
+pub mod signature_help;
+pub mod signatures;
+pub mod workspace_symbols;
@@ -0,0 +1,296 @@
+use phpanalyzer::{autonodes::any::AnyNodeRef, issue::Issue};
+use rust_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionParams, Diagnostic, Position, Range,
+    TextDocumentIdentifier, TextDocumentPositionParams, TextEdit,
+};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::codetree::{references::node_text, symbol_index::IndexedSymbolKind};
+
+use super::{
+    code_actions::edit_action,
+    imports::{FileImports, ImportKind},
+    instance::PHPLanguageServerInstance,
+    locations::{offset_to_position, position_to_offset},
+};
+
+///
+/// Describe how to fix an issue, for the `data` field of its diagnostic. The client hands it
+/// back to us when asking for code actions. Issues without a mechanical fix get `None`.
+///
+/// The issues only tell where they are, so the name to fix is read from the source at the
+/// range of the diagnostic when the fix is made.
+///
+pub fn get_fix_data(issue: &Issue) -> Option<Value> {
+    match issue {
+        Issue::UnknownClass(..) => Some(json!({ "fix": "import_class" })),
+        Issue::UnreachableCode(_) => Some(json!({ "fix": "remove_unreachable" })),
+        Issue::UnusedVariable(..) => Some(json!({ "fix": "remove_assignment" })),
+        _ => None,
+    }
+}
+
+///
+/// Get the fixes for the diagnostics the client asks about, from the data we gave them
+///
+pub fn get_quick_fixes(
+    phpls: &PHPLanguageServerInstance,
+    params: &CodeActionParams,
+) -> Vec<CodeAction> {
+    let uri = &params.text_document.uri;
+    let source = match phpls
+        .get_file_for_uri(uri)
+        .and_then(|f| f.get_contents().ok())
+    {
+        Some(source) => String::from_utf8_lossy(&source).to_string(),
+        None => return vec![],
+    };
+
+    let mut actions = vec![];
+    for diagnostic in &params.context.diagnostics {
+        let fix = diagnostic
+            .data
+            .as_ref()
+            .and_then(|d| d.get("fix"))
+            .and_then(|f| f.as_str());
+        let start = position_to_offset(&source, &diagnostic.range.start);
+        let end = position_to_offset(&source, &diagnostic.range.end);
+        let text = source.get(start..end).unwrap_or("").trim();
+        let mut fixes = vec![];
+        match fix {
+            Some("import_class") => {
+                let name = text.rsplit('\\').next().unwrap_or("");
+                fixes.extend(import_class_fixes(phpls, uri, &source, name))
+            }
+            Some("remove_unreachable") => fixes.push(quick_fix(
+                "Remove unreachable code".to_string(),
+                uri,
+                vec![remove_lines_edit(&source, &diagnostic.range)],
+            )),
+            Some("remove_assignment") => fixes.extend(remove_assignment_fix(
+                phpls,
+                uri,
+                &source,
+                diagnostic,
+                text.trim_start_matches('$'),
+            )),
+            _ => (),
+        }
+        // When there is only one way to fix it, the client may apply it right away
+        let is_preferred = fixes.len() == 1;
+        for mut fix in fixes {
+            fix.diagnostics = Some(vec![diagnostic.clone()]);
+            fix.is_preferred = Some(is_preferred);
+            actions.push(fix);
+        }
+    }
+    actions
+}
+
+fn quick_fix(title: String, uri: &Url, edits: Vec<TextEdit>) -> CodeAction {
+    edit_action(title, CodeActionKind::QUICKFIX, uri, edits)
+}
+
+///
+/// One fix for each class, interface or trait with the unknown name in another namespace
+///
+fn import_class_fixes(
+    phpls: &PHPLanguageServerInstance,
+    uri: &Url,
+    source: &str,
+    name: &str,
+) -> Vec<CodeAction> {
+    let index = match phpls.get_symbol_index_for_uri(uri) {
+        Some(index) => index,
+        None => return vec![],
+    };
+    let imports = FileImports::from_contents(source);
+    index
+        .find_by_prefix(name)
+        .iter()
+        .filter(|s| s.name.eq_ignore_ascii_case(name))
+        .filter(|s| {
+            matches!(
+                s.kind,
+                IndexedSymbolKind::Class | IndexedSymbolKind::Interface | IndexedSymbolKind::Trait
+            )
+        })
+        .filter(|s| !imports.is_reachable(s) && !imports.is_name_taken(s, &index))
+        .map(|s| {
+            quick_fix(
+                format!("Import {}", s.fq_name),
+                uri,
+                vec![imports.get_import_edit(ImportKind::Class, &s.fq_name)],
+            )
+        })
+        .collect()
+}
+
+///
+/// Remove the assignment to an unused variable. When the assigned value might have side
+/// effects, or the assignment is part of a larger expression, the value is kept.
+///
+fn remove_assignment_fix(
+    phpls: &PHPLanguageServerInstance,
+    uri: &Url,
+    source: &str,
+    diagnostic: &Diagnostic,
+    name: &str,
+) -> Option<CodeAction> {
+    let position = TextDocumentPositionParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        position: diagnostic.range.start,
+    };
+    let variable = format!("${}", name);
+    let tree_source = source.as_bytes().to_vec();
+    let assignment = match phpls.at_position(
+        position,
+        Box::new(move |node, _state, path| {
+            let mut nodes = path.clone();
+            nodes.push(node);
+            find_assignment(&nodes, &variable, &tree_source)
+        }),
+    ) {
+        Ok((_, Some(Some(assignment)))) => assignment,
+        Ok(_) => return None,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            return None;
+        }
+    };
+
+    let edit = if assignment.is_statement && !assignment.has_side_effects {
+        remove_lines_edit(
+            source,
+            &Range {
+                start: offset_to_position(source, assignment.start),
+                end: offset_to_position(source, assignment.end),
+            },
+        )
+    } else {
+        TextEdit {
+            range: Range {
+                start: offset_to_position(source, assignment.start),
+                end: offset_to_position(source, assignment.value_start),
+            },
+            new_text: String::new(),
+        }
+    };
+    Some(quick_fix(
+        format!("Remove assignment to unused ${}", name),
+        uri,
+        vec![edit],
+    ))
+}
+
+///
+/// An assignment to an unused variable, in bytes
+///
+#[derive(Clone)]
+struct UnusedAssignment {
+    /// The start of the assignment, or its statement
+    start: usize,
+    /// The end of the statement, if the assignment is one
+    end: usize,
+    value_start: usize,
+    is_statement: bool,
+    has_side_effects: bool,
+}
+
+///
+/// Find the assignment to `variable` whose left side starts at the last of `nodes`
+///
+fn find_assignment(
+    nodes: &[AnyNodeRef],
+    variable: &str,
+    source: &[u8],
+) -> Option<UnusedAssignment> {
+    let at = nodes.last()?.range().start_byte;
+    let idx = nodes.iter().rposition(|n| {
+        matches!(n, AnyNodeRef::AssignmentExpression(_))
+            && n.children_any().first().map_or(false, |target| {
+                target.range().start_byte == at && node_text(&target.range(), source) == variable
+            })
+    })?;
+    let assignment = &nodes[idx];
+    let value = assignment.children_any().last()?.clone();
+    let statement = match idx.checked_sub(1).map(|i| &nodes[i]) {
+        Some(parent @ AnyNodeRef::ExpressionStatement(_)) => Some(parent.range()),
+        _ => None,
+    };
+    Some(UnusedAssignment {
+        start: assignment.range().start_byte,
+        end: statement.map_or(assignment.range().end_byte, |s| s.end_byte),
+        value_start: value.range().start_byte,
+        is_statement: statement.is_some(),
+        has_side_effects: has_side_effects(&value),
+    })
+}
+
+///
+/// Check if evaluating `node` might do more than produce a value. Only variables, literals,
+/// constants and operators on them are known not to; calls, `new`, increments, assignments
+/// and property reads, which may run `__get`, might.
+///
+fn has_side_effects(node: &AnyNodeRef) -> bool {
+    let children = node.children_any();
+    if children.is_empty() {
+        // Literals and other tokens
+        return false;
+    }
+    let is_pure = matches!(
+        node,
+        AnyNodeRef::_Expression(_)
+            | AnyNodeRef::_PrimaryExpression(_)
+            | AnyNodeRef::_Literal(_)
+            | AnyNodeRef::VariableName(_)
+            | AnyNodeRef::Name(_)
+            | AnyNodeRef::QualifiedName(_)
+            | AnyNodeRef::NamespaceName(_)
+            | AnyNodeRef::ClassConstantAccessExpression(_)
+            | AnyNodeRef::BinaryExpression(_)
+            | AnyNodeRef::UnaryOpExpression(_)
+            | AnyNodeRef::ConditionalExpression(_)
+            | AnyNodeRef::ParenthesizedExpression(_)
+            | AnyNodeRef::CastExpression(_)
+            | AnyNodeRef::ArrayCreationExpression(_)
+            | AnyNodeRef::ArrayElementInitializer(_)
+            | AnyNodeRef::String(_)
+            | AnyNodeRef::EncapsedString(_)
+            | AnyNodeRef::Heredoc(_)
+    );
+    !is_pure || children.iter().any(has_side_effects)
+}
+
+///
+/// Remove `range`. If nothing but whitespace is left on its lines, the lines go as well.
+///
+fn remove_lines_edit(source: &str, range: &Range) -> TextEdit {
+    let start = position_to_offset(source, &range.start);
+    let end = position_to_offset(source, &range.end);
+    let line_start = source[..start].rfind('\n').map_or(0, |idx| idx + 1);
+    let line_end = source[end..]
+        .find('\n')
+        .map_or(source.len(), |idx| end + idx);
+
+    let range =
+        if source[line_start..start].trim().is_empty() && source[end..line_end].trim().is_empty() {
+            Range {
+                start: Position {
+                    line: range.start.line,
+                    character: 0,
+                },
+                end: Position {
+                    line: range.end.line + 1,
+                    character: 0,
+                },
+            }
+        } else {
+            range.clone()
+        };
+    TextEdit {
+        range,
+        new_text: String::new(),
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+use phpanalyzer::{
+    symboldata::{FileLocation, SymbolData},
+    symbols::FullyQualifiedName,
+};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{Location, ReferenceParams},
+};
+
+use crate::codetree::{
+    class_hierarchy::ClassHierarchy,
+    references::{get_reference_at_cursor, ReferenceKey},
+};
+
+use super::{
+    instance::PHPLanguageServerInstance,
+    locations::LocationConverter,
+    members::{get_ancestors, get_class_members, get_class_position, get_method, MemberKind},
+};
+
+pub fn references(
+    phpls: &PHPLanguageServerInstance,
+    params: ReferenceParams,
+    completable: MethodCompletable<Vec<Location>, ()>,
+) {
+    let include_declaration = params.context.include_declaration;
+    let position = params.text_document_position;
+    let uri = position.text_document.uri.clone();
+
+    let source = phpls
+        .get_file_for_uri(&uri)
+        .and_then(|f| f.get_contents().ok())
+        .unwrap_or_default();
+    let key = match phpls.at_position(
+        position,
+        Box::new(move |node, state, path| {
+            get_reference_at_cursor(&node, path, state, &source).map(|(key, _)| key)
+        }),
+    ) {
+        Ok((_, Some(Some(key)))) => key,
+        Ok(_) => {
+            completable.complete(Ok(vec![]));
+            return;
+        }
+        Err(_) if phpls.is_request_cancelled() => {
+            completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+            return;
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            completable.complete(Ok(vec![]));
+            return;
+        }
+    };
+    eprintln!("references: looking for {:?}", key);
+
+    let (symbol_data, reference_index, hierarchy) = match (
+        phpls.get_symbol_data_for_uri(&uri),
+        phpls.get_references_for_uri(&uri),
+        phpls.get_class_hierarchy_for_uri(&uri),
+    ) {
+        (Some(symbol_data), Some(reference_index), Some(hierarchy)) => {
+            (symbol_data, reference_index, hierarchy)
+        }
+        _ => {
+            completable.complete(Ok(vec![]));
+            return;
+        }
+    };
+
+    let mut seen = HashSet::new();
+    let mut unique_locations = vec![];
+    for key in get_related_keys(&symbol_data, &hierarchy, &key) {
+        let mut file_locations = reference_index.get_including_unresolved(&key);
+        if include_declaration {
+            file_locations.extend(get_declaration_locations(&symbol_data, &key));
+        }
+        for location in file_locations {
+            let position = (
+                location.uri.clone(),
+                location.start.line,
+                location.start.column,
+                location.end.line,
+                location.end.column,
+            );
+            if seen.insert(position) {
+                unique_locations.push(location);
+            }
+        }
+    }
+
+    completable.complete(Ok(LocationConverter::new(phpls).locations(unique_locations)))
+}
+
+///
+/// A member used through a parent class or interface is a use of the member in all the
+/// implementing classes as well, and the other way around for a member used through a
+/// subclass. So we include the keys for the member in all ancestors and descendants.
+///
+pub fn get_related_keys(
+    symbol_data: &SymbolData,
+    hierarchy: &ClassHierarchy,
+    key: &ReferenceKey,
+) -> Vec<ReferenceKey> {
+    let class = match key {
+        ReferenceKey::Method(class, _)
+        | ReferenceKey::Property(class, _)
+        | ReferenceKey::ClassConstant(class, _) => class,
+        _ => return vec![key.clone()],
+    };
+    let mut seen = HashSet::new();
+    get_ancestors(symbol_data, class)
+        .into_iter()
+        .chain(hierarchy.get_descendants(class))
+        .filter(|class| seen.insert(class.clone()))
+        .filter_map(|class| key.with_class(&class))
+        .collect()
+}
+
+///
+/// Get where the symbol `key` refers to is declared
+///
+pub fn get_declaration_locations(symbol_data: &SymbolData, key: &ReferenceKey) -> Vec<FileLocation> {
+    let member_position = |class: &FullyQualifiedName, name: &str, kind: MemberKind| {
+        get_class_members(symbol_data, class)
+            .into_iter()
+            .find(|m| m.kind == kind && &m.declared_in == class && m.name == name)
+            .and_then(|m| m.position)
+    };
+    let location = match key {
+        ReferenceKey::Class(fq_name) => get_class_position(symbol_data, fq_name),
+        ReferenceKey::Method(class, name) => get_method(symbol_data, class, &name.to_string())
+            .map(|m| m.read().unwrap().position.clone()),
+        ReferenceKey::Property(class, name) => {
+            member_position(class, &name.to_string(), MemberKind::Property)
+        }
+        ReferenceKey::ClassConstant(class, name) => {
+            member_position(class, &name.to_string(), MemberKind::Constant)
+        }
+        ReferenceKey::Function(fq_name) => {
+            let functions = symbol_data.functions.read().unwrap();
+            functions
+                .get(fq_name)
+                .map(|f| f.read().unwrap().position.clone())
+        }
+        ReferenceKey::Constant(fq_name) => {
+            let constants = symbol_data.constants.read().unwrap();
+            constants.get(fq_name).map(|c| c.position.clone())
+        }
+        ReferenceKey::UnresolvedMember(_)
+        | ReferenceKey::UnresolvedFunction(_)
+        | ReferenceKey::UnresolvedConstant(_) => None,
+    };
+    location.into_iter().collect()
+}
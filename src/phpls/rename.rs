@@ -0,0 +1,829 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::Path;
+
+use phpanalyzer::symboldata::{FileLocation, SymbolData};
+use phpanalyzer::symbols::FullyQualifiedName;
+use regex::Regex;
+use rust_lsp::{
+    jsonrpc::{method_types::MethodError, MethodCompletable},
+    lsp_types::{
+        AnnotatedTextEdit, ChangeAnnotation, DocumentChanges, OneOf,
+        OptionalVersionedTextDocumentIdentifier, Position, PrepareRenameResponse, Range,
+        RenameParams, TextDocumentEdit, TextDocumentPositionParams, TextEdit, WorkspaceEdit,
+    },
+};
+use url::Url;
+
+use crate::codetree::class_hierarchy::ClassHierarchy;
+use crate::codetree::overlays::Overlays;
+use crate::codetree::references::{get_reference_at_cursor, node_text, ReferenceIndex, ReferenceKey};
+
+use super::{
+    completion::is_identifier_char,
+    imports::FileImports,
+    instance::PHPLanguageServerInstance,
+    locations::{offset_to_position, position_to_byte_offset},
+    members::{get_ancestors, get_class_members, MemberKind},
+    references::get_declaration_locations,
+    variable_rename::{get_variable_rename, VariableRename},
+};
+
+/// The characters PHP allows in names. PHP allows the bytes 0x80-0xff, which in a UTF-8 file
+/// is any character outside of ASCII. In a Rust regex `\x80-\xff` would be U+0080 to U+00FF.
+const NAME_CHARS: &str = r"A-Za-z0-9_\x{80}-\x{10FFFF}";
+const NAME_START_CHARS: &str = r"A-Za-z_\x{80}-\x{10FFFF}";
+
+/// The change annotation of edits to uses on objects of unknown type
+const UNRESOLVED_ANNOTATION: &str = "unresolved";
+
+#[derive(Clone)]
+enum RenameTarget {
+    /// A symbol, with the name and its range at the cursor
+    Symbol(ReferenceKey, String, Range),
+    Variable(VariableRename),
+}
+
+pub fn prepare_rename(
+    phpls: &PHPLanguageServerInstance,
+    params: TextDocumentPositionParams,
+    completable: MethodCompletable<Option<PrepareRenameResponse>, ()>,
+) {
+    let uri = params.text_document.uri.clone();
+    let target = get_rename_target(phpls, params, None);
+    if phpls.is_request_cancelled() {
+        completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+        return;
+    }
+    let (key, name, range) = match target {
+        Some(RenameTarget::Symbol(key, name, range)) => (key, name, range),
+        Some(RenameTarget::Variable(variable)) => {
+            completable.complete(Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+                range: variable.range,
+                placeholder: variable.name,
+            })));
+            return;
+        }
+        None => {
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let (symbol_data, reference_index, hierarchy) = match (
+        phpls.get_symbol_data_for_uri(&uri),
+        phpls.get_references_for_uri(&uri),
+        phpls.get_class_hierarchy_for_uri(&uri),
+    ) {
+        (Some(symbol_data), Some(reference_index), Some(hierarchy)) => {
+            (symbol_data, reference_index, hierarchy)
+        }
+        _ => {
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let can_confirm = phpls.supports_change_annotations();
+    let checked = check_renameable(&symbol_data, &hierarchy, &key)
+        .and_then(|_| check_unresolved_uses(&reference_index, &key, can_confirm));
+    if let Err(e) = checked {
+        completable.complete(Err(rename_error(e)));
+        return;
+    }
+    completable.complete(Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+        range,
+        placeholder: name,
+    })));
+}
+
+pub fn rename(
+    phpls: &PHPLanguageServerInstance,
+    params: RenameParams,
+    completable: MethodCompletable<WorkspaceEdit, ()>,
+) {
+    let uri = params.text_document_position.text_document.uri.clone();
+    let new_name = params.new_name.trim().to_string();
+
+    let target = get_rename_target(
+        phpls,
+        params.text_document_position,
+        Some(new_name.trim_start_matches('$').to_string()),
+    );
+    if phpls.is_request_cancelled() {
+        completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+        return;
+    }
+    let (key, name) = match target {
+        Some(RenameTarget::Symbol(key, name, _)) => (key, name),
+        Some(RenameTarget::Variable(variable)) => {
+            completable.complete(rename_variable(&uri, variable, &new_name));
+            return;
+        }
+        None => {
+            completable.complete(Err(rename_error("No symbol to rename here".to_string())));
+            return;
+        }
+    };
+    let (symbol_data, reference_index, hierarchy, overlays) = match (
+        phpls.get_symbol_data_for_uri(&uri),
+        phpls.get_references_for_uri(&uri),
+        phpls.get_class_hierarchy_for_uri(&uri),
+        phpls.get_overlays_for_uri(&uri),
+    ) {
+        (Some(symbol_data), Some(reference_index), Some(hierarchy), Some(overlays)) => {
+            (symbol_data, reference_index, hierarchy, overlays)
+        }
+        _ => {
+            completable.complete(Err(rename_error(
+                "The workspace has not been analyzed yet".to_string(),
+            )));
+            return;
+        }
+    };
+
+    let new_name = match &key {
+        ReferenceKey::Property(..) => new_name.trim_start_matches('$').to_string(),
+        _ => new_name,
+    };
+    if new_name == name {
+        completable.complete(Ok(WorkspaceEdit::default()));
+        return;
+    }
+    let can_confirm = phpls.supports_change_annotations();
+    let result = check_renameable(&symbol_data, &hierarchy, &key)
+        .and_then(|_| check_unresolved_uses(&reference_index, &key, can_confirm))
+        .and_then(|_| check_new_name(&symbol_data, &hierarchy, &key, &new_name))
+        .map(|_| {
+            let changes = get_rename_edits(
+                &symbol_data,
+                &hierarchy,
+                &reference_index,
+                &overlays,
+                &key,
+                &name,
+                &new_name,
+            );
+            let unresolved = if can_confirm {
+                get_unresolved_edits(
+                    &reference_index,
+                    &overlays,
+                    &key,
+                    &name,
+                    &new_name,
+                    &changes,
+                )
+            } else {
+                HashMap::new()
+            };
+            to_workspace_edit(changes, unresolved, &key)
+        });
+    match result {
+        Ok(edit) => completable.complete(Ok(edit)),
+        Err(e) => completable.complete(Err(rename_error(e))),
+    }
+}
+
+///
+/// Put the edits together. Edits of uses on objects of unknown type are annotated, so that
+/// the client asks before making them.
+///
+fn to_workspace_edit(
+    changes: HashMap<Url, Vec<TextEdit>>,
+    unresolved: HashMap<Url, Vec<TextEdit>>,
+    key: &ReferenceKey,
+) -> WorkspaceEdit {
+    if unresolved.is_empty() {
+        return WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        };
+    }
+
+    let mut edits: HashMap<Url, Vec<OneOf<TextEdit, AnnotatedTextEdit>>> = HashMap::new();
+    for (uri, file_edits) in changes {
+        edits
+            .entry(uri)
+            .or_insert_with(Vec::new)
+            .extend(file_edits.into_iter().map(OneOf::Left));
+    }
+    for (uri, file_edits) in unresolved {
+        edits
+            .entry(uri)
+            .or_insert_with(Vec::new)
+            .extend(file_edits.into_iter().map(|text_edit| {
+                OneOf::Right(AnnotatedTextEdit {
+                    text_edit,
+                    annotation_id: UNRESOLVED_ANNOTATION.to_string(),
+                })
+            }));
+    }
+    let document_changes = edits
+        .into_iter()
+        .map(|(uri, edits)| TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+            edits,
+        })
+        .collect();
+
+    let mut annotations = HashMap::new();
+    annotations.insert(
+        UNRESOLVED_ANNOTATION.to_string(),
+        ChangeAnnotation {
+            label: "Uses on objects of unknown type".to_string(),
+            needs_confirmation: Some(true),
+            description: Some(format!(
+                "These might not be uses of {}, as the type of the object is unknown",
+                describe_key(key)
+            )),
+        },
+    );
+    WorkspaceEdit {
+        changes: None,
+        document_changes: Some(DocumentChanges::Edits(document_changes)),
+        change_annotations: Some(annotations),
+    }
+}
+
+///
+/// Rename a local variable. The edits are all in the file we're in.
+///
+fn rename_variable(
+    uri: &Url,
+    variable: VariableRename,
+    new_name: &str,
+) -> Result<WorkspaceEdit, MethodError<()>> {
+    let new_name = new_name.trim_start_matches('$');
+    if !is_valid_identifier(new_name) {
+        return Err(rename_error(format!("${} is not a valid variable name", new_name)));
+    }
+    if variable.conflicts {
+        return Err(rename_error(format!(
+            "${} is already used in this function",
+            new_name
+        )));
+    }
+    let edits = variable
+        .ranges
+        .into_iter()
+        .map(|range| TextEdit {
+            range,
+            new_text: new_name.to_string(),
+        })
+        .collect();
+    let mut changes = HashMap::new();
+    changes.insert(uri.clone(), edits);
+    Ok(WorkspaceEdit {
+        changes: Some(changes),
+        document_changes: None,
+        change_annotations: None,
+    })
+}
+
+fn is_valid_identifier(name: &str) -> bool {
+    let identifier = Regex::new(&format!("^[{}][{}]*$", NAME_START_CHARS, NAME_CHARS)).unwrap();
+    identifier.is_match(name)
+}
+
+fn rename_error(message: String) -> MethodError<()> {
+    MethodError {
+        code: 1,
+        message,
+        data: (),
+    }
+}
+
+///
+/// Find what to rename at the cursor. Local variables are looked at first, as they are not
+/// symbols. `new_name` is used to check if a renamed variable would collide with another one.
+///
+fn get_rename_target(
+    phpls: &PHPLanguageServerInstance,
+    params: TextDocumentPositionParams,
+    new_name: Option<String>,
+) -> Option<RenameTarget> {
+    let source = phpls
+        .get_file_for_uri(&params.text_document.uri)
+        .and_then(|f| f.get_contents().ok())
+        .unwrap_or_default();
+    let found = phpls.at_position(
+        params,
+        Box::new(move |node, state, path| {
+            if let Some(variable) = get_variable_rename(&node, path, &source, new_name.as_deref()) {
+                return Some(RenameTarget::Variable(variable));
+            }
+            let (key, range) = get_reference_at_cursor(&node, path, state, &source)?;
+            let text = node_text(&range, &source).to_string();
+            // Only the last part of a qualified name is renamed, and never the `$`
+            let name = text
+                .rsplit('\\')
+                .next()
+                .unwrap_or("")
+                .trim_start_matches('$')
+                .to_string();
+            if name.is_empty() {
+                return None;
+            }
+            let text = String::from_utf8_lossy(&source);
+            let range = Range {
+                start: offset_to_position(&text, range.end_byte - name.len()),
+                end: offset_to_position(&text, range.end_byte),
+            };
+            Some(RenameTarget::Symbol(key, name, range))
+        }),
+    );
+    match found {
+        Ok((_, Some(target))) => target,
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            None
+        }
+    }
+}
+
+///
+/// Get the keys of the symbol and everything that has to be renamed along with it. For a
+/// member that is every declaration it overrides or is overridden by.
+///
+fn get_hierarchy_keys(
+    symbol_data: &SymbolData,
+    hierarchy: &ClassHierarchy,
+    key: &ReferenceKey,
+) -> Vec<ReferenceKey> {
+    let (class, name, kind) = match key {
+        ReferenceKey::Method(class, name) => (class, name, MemberKind::Method),
+        ReferenceKey::Property(class, name) => (class, name, MemberKind::Property),
+        ReferenceKey::ClassConstant(class, name) => (class, name, MemberKind::Constant),
+        _ => return vec![key.clone()],
+    };
+    let name = name.to_string();
+    let declares = |c: &FullyQualifiedName| {
+        get_class_members(symbol_data, c)
+            .iter()
+            .any(|m| m.kind == kind && &m.declared_in == c && same_member_name(kind, &m.name, &name))
+    };
+
+    let mut seen = HashSet::new();
+    let mut classes: Vec<FullyQualifiedName> = vec![];
+    for ancestor in get_ancestors(symbol_data, class) {
+        if !declares(&ancestor) && &ancestor != class {
+            continue;
+        }
+        for c in std::iter::once(ancestor.clone()).chain(hierarchy.get_descendants(&ancestor)) {
+            if seen.insert(c.clone()) {
+                classes.push(c);
+            }
+        }
+    }
+    classes
+        .into_iter()
+        .map(|c| match kind {
+            MemberKind::Method => ReferenceKey::method(&c, &name),
+            MemberKind::Property => ReferenceKey::property(&c, &name),
+            MemberKind::Constant => ReferenceKey::class_constant(&c, &name),
+        })
+        .collect()
+}
+
+fn same_member_name(kind: MemberKind, a: &str, b: &str) -> bool {
+    match kind {
+        // PHP method names are case insensitive
+        MemberKind::Method => a.eq_ignore_ascii_case(b),
+        _ => a == b,
+    }
+}
+
+///
+/// Refuse to rename symbols we don't have the source of, like the ones registered by
+/// `phpanalyzer::native`, or members that implement or override them
+///
+fn check_renameable(
+    symbol_data: &SymbolData,
+    hierarchy: &ClassHierarchy,
+    key: &ReferenceKey,
+) -> Result<(), String> {
+    if let ReferenceKey::UnresolvedMember(name) = key {
+        return Err(format!(
+            "Cannot rename {}, the type of the object it is used on is unknown",
+            name
+        ));
+    }
+    let mut found_declaration = false;
+    for key in get_hierarchy_keys(symbol_data, hierarchy, key) {
+        for location in get_declaration_locations(symbol_data, &key) {
+            if !Path::new(&location.uri).is_file() {
+                return Err(format!("Cannot rename {}, it is a builtin", describe_key(&key)));
+            }
+            found_declaration = true;
+        }
+    }
+    if !found_declaration {
+        return Err(format!(
+            "Cannot rename {}, its declaration is not in the workspace",
+            describe_key(key)
+        ));
+    }
+    Ok(())
+}
+
+///
+/// Refuse to rename a member used on objects we don't know the type of, unless the client
+/// can confirm the edits of those uses. They might be objects of the class, and renaming the
+/// member would then break them.
+///
+fn check_unresolved_uses(
+    reference_index: &ReferenceIndex,
+    key: &ReferenceKey,
+    can_confirm: bool,
+) -> Result<(), String> {
+    let name = match key.member_name() {
+        Some(name) if !can_confirm => name,
+        _ => return Ok(()),
+    };
+    let unresolved = reference_index.get(&ReferenceKey::unresolved_member(&name));
+    if let Some(first) = unresolved.first() {
+        return Err(format!(
+            "Cannot rename {}, {} is used on objects of unknown type {} times, first in {}:{}",
+            describe_key(key),
+            name,
+            unresolved.len(),
+            Path::new(&first.uri).display(),
+            first.start.line + 1
+        ));
+    }
+    Ok(())
+}
+
+///
+/// Check that `new_name` is a valid identifier, and that it doesn't collide with an existing
+/// symbol, or an existing member anywhere in the class hierarchy
+///
+fn check_new_name(
+    symbol_data: &SymbolData,
+    hierarchy: &ClassHierarchy,
+    key: &ReferenceKey,
+    new_name: &str,
+) -> Result<(), String> {
+    if !is_valid_identifier(new_name) {
+        return Err(format!("{} is not a valid name", new_name));
+    }
+
+    let sibling_name = |fq_name: &FullyQualifiedName| {
+        let fq_name = fq_name.to_string();
+        let namespace = match fq_name.rfind('\\') {
+            Some(idx) => &fq_name[..idx],
+            None => "",
+        };
+        FullyQualifiedName::from(format!("{}\\{}", namespace, new_name).as_str())
+    };
+
+    let kind = match key {
+        ReferenceKey::Class(fq_name) => {
+            let new_fq_name = sibling_name(fq_name);
+            if symbol_data.get_class(&new_fq_name).is_some() {
+                return Err(format!("{} already exists", new_fq_name));
+            }
+            return Ok(());
+        }
+        ReferenceKey::Function(fq_name) => {
+            let new_fq_name = sibling_name(fq_name);
+            if symbol_data.functions.read().unwrap().contains_key(&new_fq_name) {
+                return Err(format!("A function {} already exists", new_fq_name));
+            }
+            return Ok(());
+        }
+        ReferenceKey::Constant(fq_name) => {
+            let new_fq_name = sibling_name(fq_name);
+            if symbol_data.constants.read().unwrap().contains_key(&new_fq_name) {
+                return Err(format!("A constant {} already exists", new_fq_name));
+            }
+            return Ok(());
+        }
+        ReferenceKey::Method(..) => MemberKind::Method,
+        ReferenceKey::Property(..) => MemberKind::Property,
+        ReferenceKey::ClassConstant(..) => MemberKind::Constant,
+        ReferenceKey::UnresolvedMember(_)
+        | ReferenceKey::UnresolvedFunction(_)
+        | ReferenceKey::UnresolvedConstant(_) => return Ok(()),
+    };
+
+    for class_key in get_hierarchy_keys(symbol_data, hierarchy, key) {
+        let class = match &class_key {
+            ReferenceKey::Method(class, _)
+            | ReferenceKey::Property(class, _)
+            | ReferenceKey::ClassConstant(class, _) => class,
+            _ => continue,
+        };
+        if let Some(existing) = get_class_members(symbol_data, class)
+            .into_iter()
+            .find(|m| m.kind == kind && same_member_name(kind, &m.name, new_name))
+        {
+            return Err(format!(
+                "{} already has a member named {}, declared in {}",
+                class, existing.name, existing.declared_in
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn describe_key(key: &ReferenceKey) -> String {
+    match key {
+        ReferenceKey::Class(fq_name)
+        | ReferenceKey::Function(fq_name)
+        | ReferenceKey::Constant(fq_name) => fq_name.to_string(),
+        ReferenceKey::Method(class, name) => format!("{}::{}()", class, name),
+        ReferenceKey::Property(class, name) => format!("{}::${}", class, name),
+        ReferenceKey::ClassConstant(class, name) => format!("{}::{}", class, name),
+        ReferenceKey::UnresolvedMember(name)
+        | ReferenceKey::UnresolvedFunction(name)
+        | ReferenceKey::UnresolvedConstant(name) => name.to_string(),
+    }
+}
+
+///
+/// Build the edits for every declaration and reference of the symbol. For classes we also
+/// rename mentions in PHPDoc and class names in strings, in the files that refer to the class.
+///
+fn get_rename_edits(
+    symbol_data: &SymbolData,
+    hierarchy: &ClassHierarchy,
+    reference_index: &ReferenceIndex,
+    overlays: &Overlays,
+    key: &ReferenceKey,
+    old_name: &str,
+    new_name: &str,
+) -> HashMap<Url, Vec<TextEdit>> {
+    let case_insensitive = matches!(
+        key,
+        ReferenceKey::Class(_) | ReferenceKey::Method(..) | ReferenceKey::Function(_)
+    );
+    let mut files: HashMap<OsString, RenameFile> = HashMap::new();
+
+    for key in get_hierarchy_keys(symbol_data, hierarchy, key) {
+        // Uses on objects of unknown type are left to `get_unresolved_edits`
+        let locations = reference_index
+            .get(&key)
+            .into_iter()
+            .chain(get_declaration_locations(symbol_data, &key));
+        for location in locations {
+            let file = files
+                .entry(location.uri.clone())
+                .or_insert_with(|| RenameFile::new(&location.uri, overlays));
+            if let Some(range) = file.find_name(&location, old_name, case_insensitive) {
+                file.add_edit(range, new_name);
+            }
+        }
+    }
+
+    if let ReferenceKey::Class(fq_name) = key {
+        let fq_name = fq_name.to_string().trim_start_matches('\\').to_string();
+        for file in files.values_mut() {
+            file.rename_in_phpdoc(&fq_name, old_name, new_name);
+            file.rename_in_strings(&fq_name, old_name, new_name);
+        }
+    }
+
+    files
+        .into_iter()
+        .filter_map(|(path, file)| {
+            let uri = Url::from_file_path(Path::new(&path)).ok()?;
+            Some((uri, file.edits))
+        })
+        .filter(|(_, edits)| !edits.is_empty())
+        .collect()
+}
+
+///
+/// Build the edits for uses of the member on objects we don't know the type of, other than
+/// the ones already in `changes`
+///
+fn get_unresolved_edits(
+    reference_index: &ReferenceIndex,
+    overlays: &Overlays,
+    key: &ReferenceKey,
+    old_name: &str,
+    new_name: &str,
+    changes: &HashMap<Url, Vec<TextEdit>>,
+) -> HashMap<Url, Vec<TextEdit>> {
+    let name = match key.member_name() {
+        Some(name) => name,
+        None => return HashMap::new(),
+    };
+    let case_insensitive = matches!(key, ReferenceKey::Method(..));
+    let mut files: HashMap<OsString, RenameFile> = HashMap::new();
+
+    for location in reference_index.get(&ReferenceKey::unresolved_member(&name)) {
+        let file = files
+            .entry(location.uri.clone())
+            .or_insert_with(|| RenameFile::new(&location.uri, overlays));
+        if let Some(range) = file.find_name(&location, old_name, case_insensitive) {
+            file.add_edit(range, new_name);
+        }
+    }
+
+    files
+        .into_iter()
+        .filter_map(|(path, file)| {
+            let uri = Url::from_file_path(Path::new(&path)).ok()?;
+            let confirmed = changes.get(&uri).cloned().unwrap_or_default();
+            let edits: Vec<TextEdit> = file
+                .edits
+                .into_iter()
+                .filter(|e| !confirmed.iter().any(|c| c.range == e.range))
+                .collect();
+            Some((uri, edits))
+        })
+        .filter(|(_, edits)| !edits.is_empty())
+        .collect()
+}
+
+///
+/// The contents of a file being renamed in, and the edits collected for it
+///
+struct RenameFile {
+    contents: String,
+    edits: Vec<TextEdit>,
+}
+
+impl RenameFile {
+    fn new(path: &OsString, overlays: &Overlays) -> Self {
+        // Edits have to match what's in the editor, saved or not
+        let contents = match overlays.get(Path::new(path)) {
+            Some(overlay) => String::from_utf8_lossy(&overlay.contents).to_string(),
+            None => std::fs::read(path)
+                .map(|c| String::from_utf8_lossy(&c).to_string())
+                .unwrap_or_default(),
+        };
+        Self {
+            contents,
+            edits: vec![],
+        }
+    }
+
+    fn add_edit(&mut self, range: Range, new_text: &str) {
+        if self.edits.iter().any(|e| e.range == range) {
+            return;
+        }
+        self.edits.push(TextEdit {
+            range,
+            new_text: new_text.to_string(),
+        });
+    }
+
+    ///
+    /// Find `name` inside `location`. References point at the name itself, possibly qualified,
+    /// while declarations might cover the whole declaration, so we look for the first whole word
+    /// that matches.
+    ///
+    fn find_name(&self, location: &FileLocation, name: &str, case_insensitive: bool) -> Option<Range> {
+        let start = self.offset_of(location.start.line as usize, location.start.column as usize)?;
+        let end = self
+            .offset_of(location.end.line as usize, location.end.column as usize)
+            .unwrap_or(self.contents.len());
+        let span = self.contents.get(start..end)?;
+
+        // A qualified name only has its last part renamed
+        if let Some(idx) = span.rfind('\\') {
+            let last = &span[idx + 1..];
+            if names_equal(last, name, case_insensitive) {
+                return Some(self.range_of(start + idx + 1, end));
+            }
+        }
+        let mut search_from = 0;
+        while let Some(found) = find_word(&span[search_from..], name, case_insensitive) {
+            let word_start = search_from + found;
+            let word_end = word_start + name.len();
+            let before = span[..word_start].chars().last();
+            let after = span[word_end..].chars().next();
+            if !before.map_or(false, is_identifier_char) && !after.map_or(false, is_identifier_char) {
+                return Some(self.range_of(start + word_start, start + word_end));
+            }
+            search_from = word_end;
+        }
+        None
+    }
+
+    ///
+    /// Rename class names in the type positions of PHPDoc comments
+    ///
+    fn rename_in_phpdoc(&mut self, fq_name: &str, old_name: &str, new_name: &str) {
+        let imports = FileImports::from_contents(&self.contents);
+        let docblock = Regex::new(r"(?s)/\*\*.*?\*/").unwrap();
+        let tagged_type = Regex::new(
+            &format!(
+                r"@[A-Za-z-]+\s+([{}\\|?<>,\[\]()&\s]*?)(?:\s\$|\s*\*/|\n|$)",
+                NAME_CHARS
+            ),
+        )
+        .unwrap();
+        let class_name = Regex::new(&format!(
+            r"\\?[{start}][{chars}]*(?:\\[{start}][{chars}]*)*",
+            start = NAME_START_CHARS,
+            chars = NAME_CHARS
+        ))
+        .unwrap();
+
+        let mut ranges = vec![];
+        for doc in docblock.find_iter(&self.contents) {
+            for tag in tagged_type.captures_iter(doc.as_str()) {
+                let types = if let Some(types) = tag.get(1) {
+                    types
+                } else {
+                    continue;
+                };
+                for name in class_name.find_iter(types.as_str()) {
+                    let text = name.as_str();
+                    let last = text.rsplit('\\').next().unwrap_or("");
+                    if !last.eq_ignore_ascii_case(old_name)
+                        || !imports.resolve_class_name(text).eq_ignore_ascii_case(fq_name)
+                    {
+                        continue;
+                    }
+                    let end = doc.start() + types.start() + name.end();
+                    ranges.push(self.range_of(end - last.len(), end));
+                }
+            }
+        }
+        for range in ranges {
+            self.add_edit(range, new_name);
+        }
+    }
+
+    ///
+    /// Rename string literals holding the fully qualified class name, which is what `::class`
+    /// gives. Classes in the global namespace are skipped, as any string with the class name
+    /// would match.
+    ///
+    fn rename_in_strings(&mut self, fq_name: &str, old_name: &str, new_name: &str) {
+        if !fq_name.contains('\\') {
+            return;
+        }
+        let string = Regex::new(&format!(
+            r#"'(\\?[{chars}\\]+)'|"(\\{{0,2}}[{chars}\\]+)""#,
+            chars = NAME_CHARS
+        ))
+        .unwrap();
+        let mut ranges = vec![];
+        for captures in string.captures_iter(&self.contents) {
+            let (text, double_quoted) = match (captures.get(1), captures.get(2)) {
+                (Some(text), _) => (text, false),
+                (_, Some(text)) => (text, true),
+                _ => continue,
+            };
+            let name = if double_quoted {
+                text.as_str().replace("\\\\", "\\")
+            } else {
+                text.as_str().to_string()
+            };
+            if !name.trim_start_matches('\\').eq_ignore_ascii_case(fq_name) {
+                continue;
+            }
+            ranges.push(self.range_of(text.end() - old_name.len(), text.end()));
+        }
+        for range in ranges {
+            self.add_edit(range, new_name);
+        }
+    }
+
+    ///
+    /// The offset of a position from the analyzer, where the column is in bytes
+    ///
+    fn offset_of(&self, line: usize, column: usize) -> Option<usize> {
+        if line > self.contents.matches('\n').count() {
+            return None;
+        }
+        let position = Position {
+            line: line as u32,
+            character: column as u32,
+        };
+        Some(position_to_byte_offset(&self.contents, &position))
+    }
+
+    fn range_of(&self, start: usize, end: usize) -> Range {
+        Range {
+            start: self.position_of(start),
+            end: self.position_of(end),
+        }
+    }
+
+    fn position_of(&self, offset: usize) -> Position {
+        offset_to_position(&self.contents, offset)
+    }
+}
+
+fn names_equal(a: &str, b: &str, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+fn find_word(haystack: &str, word: &str, case_insensitive: bool) -> Option<usize> {
+    if case_insensitive {
+        haystack.to_ascii_lowercase().find(&word.to_ascii_lowercase())
+    } else {
+        haystack.find(word)
+    }
+}
@@ -0,0 +1,107 @@
+use crate::phpls::code_actions::code_action;
+use crate::phpls::document_symbols::document_symbols;
+use crate::phpls::instance::PHPLanguageServerInstance;
+use crate::phpls::request_tracker::RequestTracker;
+use rust_lsp::jsonrpc::jsonrpc_request::RequestParams;
+use rust_lsp::jsonrpc::method_types::MethodError;
+use rust_lsp::jsonrpc::MethodCompletable;
+use rust_lsp::jsonrpc::RequestHandler;
+use rust_lsp::jsonrpc::ResponseCompletable;
+use rust_lsp::lsp::ServerRequestHandler;
+use rust_lsp::lsp_types::request::CodeActionRequest;
+use rust_lsp::lsp_types::request::DocumentSymbolRequest;
+use rust_lsp::lsp_types::request::Initialize;
+use rust_lsp::lsp_types::request::Request;
+use rust_lsp::lsp_types::{
+    CodeActionParams, DocumentSymbolParams, InitializeError, InitializeParams,
+};
+use serde_json::Value;
+
+///
+/// Dispatches requests to the language server, with the cancellation token of
+/// each request in place while it is handled.
+///
+/// Some requests are answered here rather than through `LanguageServerHandling`,
+/// whose methods have response types narrower than what we answer with:
+///
+/// The `ServerCapabilities` of our lsp-types predates `typeHierarchyProvider`,
+/// so the initialize result is answered as JSON with the capability added
+/// next to the others, where the clients look for it.
+///
+/// The document symbols are answered as a tree, and code actions as such rather
+/// than as commands, when the client supports it.
+///
+pub struct PHPRequestHandler {
+    handler: ServerRequestHandler<PHPLanguageServerInstance>,
+    tracker: RequestTracker,
+}
+
+impl PHPRequestHandler {
+    pub fn new(instance: PHPLanguageServerInstance, tracker: RequestTracker) -> Self {
+        PHPRequestHandler {
+            handler: ServerRequestHandler(instance),
+            tracker,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        params: InitializeParams,
+        completable: MethodCompletable<Value, InitializeError>,
+    ) {
+        let instance = &mut self.handler.0;
+        let result = instance.initialize_result(params);
+        let mut result = match serde_json::to_value(result) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Klarte ikke serialisere initialize-resultatet: {}", e);
+                return completable.complete(Err(MethodError {
+                    code: 1,
+                    message: format!("Unable to serialize initialize result: {}", e),
+                    data: InitializeError { retry: false },
+                }));
+            }
+        };
+        if let Some(capabilities) = result
+            .get_mut("capabilities")
+            .and_then(|c| c.as_object_mut())
+        {
+            capabilities.insert("typeHierarchyProvider".to_string(), Value::Bool(true));
+        }
+        completable.complete(Ok(result));
+        instance.reanalyze(None, None);
+    }
+}
+
+impl RequestHandler for PHPRequestHandler {
+    fn handle_request(
+        &mut self,
+        method_name: &str,
+        params: RequestParams,
+        completable: ResponseCompletable,
+    ) {
+        let cancellation = self.tracker.current_cancellation();
+        self.handler.0.set_request_cancellation(cancellation);
+        match method_name {
+            Initialize::METHOD => completable
+                .handle_request_with(params, |params: InitializeParams, completable| {
+                    self.initialize(params, completable)
+                }),
+            DocumentSymbolRequest::METHOD => completable.handle_request_with(
+                params,
+                |params: DocumentSymbolParams, completable| {
+                    document_symbols(&self.handler.0, params, completable)
+                },
+            ),
+            CodeActionRequest::METHOD => {
+                completable.handle_request_with(params, |params: CodeActionParams, completable| {
+                    code_action(&self.handler.0, params, completable)
+                })
+            }
+            _ => self
+                .handler
+                .handle_request(method_name, params, completable),
+        }
+        self.tracker.finish();
+    }
+}
@@ -0,0 +1,200 @@
+use crate::codetree::cancellation::CancellationToken;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Read};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+///
+/// Keeps track of which request the server is working on, so that a `$/cancelRequest` for it
+/// can be acted on. Requests are handled one at a time on the thread reading the input, so
+/// the input is read ahead of the server by another thread, which sees the cancellation
+/// while the request is still being worked on.
+///
+/// The server reads a message and handles it before reading the next, so the request being
+/// handled is the one whose message the server read last. It is known from the bytes handed
+/// to the server, rather than from counting messages.
+///
+#[derive(Clone)]
+pub struct RequestTracker {
+    state: Arc<Mutex<TrackerState>>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    /// The ids of the requests read ahead, but not yet by the server
+    queued: HashSet<String>,
+    /// The request the server read last, and its token
+    current: Option<(String, CancellationToken)>,
+    /// Requests cancelled before the server read them
+    cancelled: HashSet<String>,
+}
+
+impl RequestTracker {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(TrackerState::default())),
+        }
+    }
+
+    ///
+    /// Start reading messages from `input` in a thread of its own. The server reads them from
+    /// the returned reader, unchanged.
+    ///
+    pub fn read_ahead<R>(&self, mut input: R) -> MessageReader
+    where
+        R: BufRead + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let tracker = self.clone();
+        thread::spawn(move || {
+            while let Some((message, body)) = read_message(&mut input) {
+                let id = match serde_json::from_slice::<Value>(&body) {
+                    Ok(body) => tracker.saw_message(&body),
+                    Err(_) => None,
+                };
+                if sender.send((message, id)).is_err() {
+                    break;
+                }
+            }
+        });
+        MessageReader {
+            receiver,
+            tracker: self.clone(),
+            buffer: vec![],
+            pos: 0,
+        }
+    }
+
+    ///
+    /// The token of the request the server is handling, which is cancelled when the client
+    /// cancels the request. Notifications get one that is never cancelled.
+    ///
+    pub fn current_cancellation(&self) -> CancellationToken {
+        match &self.state.lock().unwrap().current {
+            Some((_, token)) => token.clone(),
+            None => CancellationToken::new(),
+        }
+    }
+
+    ///
+    /// Called when the server is done with the request it read last
+    ///
+    pub fn finish(&self) {
+        self.state.lock().unwrap().current = None;
+    }
+
+    ///
+    /// Called when the server reads the message with `id`, or a notification
+    ///
+    fn server_read(&self, id: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        let current = match id {
+            Some(id) => {
+                let token = CancellationToken::new();
+                state.queued.remove(&id);
+                if state.cancelled.remove(&id) {
+                    token.cancel();
+                }
+                Some((id, token))
+            }
+            None => None,
+        };
+        state.current = current;
+    }
+
+    ///
+    /// Look at a message read ahead. Returns the id of requests.
+    ///
+    fn saw_message(&self, message: &Value) -> Option<String> {
+        // Responses to our requests have no method
+        let method = message.get("method").and_then(|m| m.as_str())?;
+        let mut state = self.state.lock().unwrap();
+        if method == "$/cancelRequest" {
+            if let Some(id) = message.get("params").and_then(|p| p.get("id")) {
+                let id = id.to_string();
+                match &state.current {
+                    Some((current, token)) if current == &id => token.cancel(),
+                    _ if state.queued.contains(&id) => {
+                        state.cancelled.insert(id);
+                    }
+                    // Already answered
+                    _ => (),
+                }
+            }
+        }
+        let id = message.get("id").map(|id| id.to_string())?;
+        state.queued.insert(id.clone());
+        Some(id)
+    }
+}
+
+///
+/// Read one message, returning it as read along with its content
+///
+fn read_message<R: BufRead>(input: &mut R) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut message = vec![];
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        message.extend_from_slice(line.as_bytes());
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; content_length.unwrap_or(0)];
+    input.read_exact(&mut body).ok()?;
+    message.extend_from_slice(&body);
+    Some((message, body))
+}
+
+///
+/// The input of the server, as read by `RequestTracker::read_ahead`
+///
+pub struct MessageReader {
+    receiver: Receiver<(Vec<u8>, Option<String>)>,
+    tracker: RequestTracker,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for MessageReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for MessageReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.buffer.len() {
+            match self.receiver.recv() {
+                Ok((message, id)) => {
+                    self.tracker.server_read(id);
+                    self.buffer = message;
+                    self.pos = 0;
+                }
+                // The input is closed
+                Err(_) => return Ok(&[]),
+            }
+        }
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
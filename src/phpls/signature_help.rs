@@ -0,0 +1,210 @@
+use phpanalyzer::{
+    analysis::state::AnalysisState,
+    autonodes::any::AnyNodeRef,
+    issue::VoidEmitter,
+    symboldata::FunctionArgumentData,
+    symbols::Symbol,
+    types::union::DiscreteType,
+    Point,
+};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{
+        Documentation, MarkupContent, MarkupKind, ParameterInformation, ParameterLabel,
+        SignatureHelp, SignatureInformation, TextDocumentPositionParams,
+    },
+};
+
+use super::{
+    instance::PHPLanguageServerInstance,
+    locations::position_to_byte_position,
+    members::get_method,
+    signatures::{
+        format_argument, format_function_documentation, format_function_signature,
+        format_method_documentation, format_method_signature,
+    },
+};
+
+pub fn signature_help(
+    phpls: &PHPLanguageServerInstance,
+    params: TextDocumentPositionParams,
+    completable: MethodCompletable<SignatureHelp, ()>,
+) {
+    // Compared with ranges in the syntax tree, which have the column in bytes
+    let source = phpls
+        .get_file_for_uri(&params.text_document.uri)
+        .and_then(|f| f.get_contents().ok())
+        .map(|c| String::from_utf8_lossy(&c).to_string())
+        .unwrap_or_default();
+    let position = position_to_byte_position(&source, &params.position);
+    let cursor = Point {
+        row: position.line as usize,
+        column: position.character as usize,
+    };
+    let help = match phpls.at_position(
+        params,
+        Box::new(move |node, state, path| get_signature_help(node, state, path, cursor)),
+    ) {
+        Ok((_, Some(Some(help)))) => help,
+        Ok(_) => empty_signature_help(),
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            empty_signature_help()
+        }
+    };
+    if phpls.is_request_cancelled() {
+        completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+        return;
+    }
+    completable.complete(Ok(help));
+}
+
+fn empty_signature_help() -> SignatureHelp {
+    SignatureHelp {
+        signatures: vec![],
+        active_signature: None,
+        active_parameter: None,
+    }
+}
+
+///
+/// Callback for `at_position`. Finds the closest argument list around the cursor, and
+/// describes the function, method or constructor it belongs to.
+///
+fn get_signature_help(
+    node: AnyNodeRef,
+    state: &mut AnalysisState,
+    path: &Vec<AnyNodeRef>,
+    cursor: Point,
+) -> Option<SignatureHelp> {
+    let mut nodes = path.clone();
+    nodes.push(node);
+
+    let args_idx = nodes
+        .iter()
+        .rposition(|n| matches!(n, AnyNodeRef::Arguments(_)))?;
+    let call = nodes.get(args_idx.checked_sub(1)?)?;
+    let arguments = &nodes[args_idx];
+
+    let (label, parameters, documentation) = get_callable(call, state)?;
+
+    let mut active_parameter = 0;
+    let mut named_argument = None;
+    for argument in arguments.children_any() {
+        let argument = match argument {
+            AnyNodeRef::Argument(a) => a,
+            _ => continue,
+        };
+        let range = argument.range();
+        if range.end_point < cursor {
+            active_parameter += 1;
+        } else if range.start_point <= cursor {
+            named_argument = argument.name.as_ref().map(|n| n.get_name().to_string());
+        }
+    }
+    if let Some(named_argument) = named_argument {
+        if let Some(idx) = parameters
+            .iter()
+            .position(|p| p.name.to_string() == named_argument)
+        {
+            active_parameter = idx;
+        }
+    }
+    // Everything past the end goes to a variadic last parameter
+    if let Some(last) = parameters.last() {
+        if last.variadic && active_parameter >= parameters.len() {
+            active_parameter = parameters.len() - 1;
+        }
+    }
+
+    let parameter_information = parameters
+        .iter()
+        .map(|p| ParameterInformation {
+            label: ParameterLabel::Simple(format_argument(p)),
+            documentation: p
+                .phpdoc_description
+                .as_ref()
+                .map(|d| Documentation::String(d.clone())),
+        })
+        .collect();
+
+    let documentation = if documentation.is_empty() {
+        None
+    } else {
+        Some(Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: documentation,
+        }))
+    };
+
+    Some(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label,
+            documentation,
+            parameters: Some(parameter_information),
+            active_parameter: None,
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(active_parameter as u32),
+    })
+}
+
+///
+/// Get the signature, parameters and documentation of what `call` invokes
+///
+fn get_callable(
+    call: &AnyNodeRef,
+    state: &mut AnalysisState,
+) -> Option<(String, Vec<FunctionArgumentData>, String)> {
+    let symbols = match call {
+        AnyNodeRef::FunctionCallExpression(fc) => fc.get_symbols(state)?,
+        AnyNodeRef::MemberCallExpression(mc) => mc.get_symbols(state)?,
+        AnyNodeRef::NullsafeMemberCallExpression(mc) => mc.get_symbols(state)?,
+        AnyNodeRef::ScopedCallExpression(sc) => vec![Symbol::Method(sc.get_method_symbol(state)?)],
+        AnyNodeRef::ObjectCreationExpression(oc) => {
+            let utype = oc.get_utype(state, &VoidEmitter::new())?;
+            let fq_name = utype.types.into_iter().find_map(|t| match t {
+                DiscreteType::Named(_, fq_name) => Some(fq_name),
+                _ => None,
+            })?;
+            let constructor = get_method(&state.symbol_data, &fq_name, "__construct")?;
+            let constructor = constructor.read().unwrap();
+            return Some((
+                format_method_signature(&constructor),
+                constructor.arguments.clone(),
+                format_method_documentation(&constructor),
+            ));
+        }
+        _ => return None,
+    };
+
+    for symbol in symbols {
+        match symbol {
+            Symbol::Method(method_symbol) => {
+                let class_name = method_symbol.class.get_fq_name();
+                let name = method_symbol.name.to_string();
+                if let Some(method) = get_method(&state.symbol_data, &class_name, &name) {
+                    let method = method.read().unwrap();
+                    return Some((
+                        format_method_signature(&method),
+                        method.arguments.clone(),
+                        format_method_documentation(&method),
+                    ));
+                }
+            }
+            Symbol::Function(function_symbol) => {
+                let functions = state.symbol_data.functions.read().unwrap();
+                if let Some(function) = functions.get(&function_symbol.fq_name) {
+                    let function = function.read().unwrap();
+                    return Some((
+                        format_function_signature(&function),
+                        function.arguments.clone(),
+                        format_function_documentation(&function),
+                    ));
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
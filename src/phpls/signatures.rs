@@ -0,0 +1,218 @@
+use phpanalyzer::{
+    symboldata::{
+        class::{ClassMemberVisibility, ClassType, MethodData},
+        ConstantData, FunctionArgumentData, FunctionData,
+    },
+    symbols::FullyQualifiedName,
+};
+
+use super::members::{ClassMember, MemberKind};
+
+///
+/// Format a single parameter like it would be declared, `?int $foo = null`
+///
+pub fn format_argument(argument: &FunctionArgumentData) -> String {
+    let mut label = String::new();
+    if let Some(arg_type) = argument.arg_type.as_ref().or(argument.phpdoc_type.as_ref()) {
+        label.push_str(&arg_type.to_string());
+        label.push(' ');
+    }
+    if argument.variadic {
+        label.push_str("...");
+    }
+    label.push('$');
+    label.push_str(&argument.name.to_string());
+    if let Some(default_value) = &argument.default_value {
+        label.push_str(" = ");
+        label.push_str(&default_value.to_string());
+    }
+    label
+}
+
+pub fn format_arguments(arguments: &[FunctionArgumentData]) -> Vec<String> {
+    arguments.iter().map(format_argument).collect()
+}
+
+pub fn format_visibility(visibility: &ClassMemberVisibility) -> &'static str {
+    match visibility {
+        ClassMemberVisibility::Public => "public",
+        ClassMemberVisibility::Protected => "protected",
+        ClassMemberVisibility::Private => "private",
+    }
+}
+
+///
+/// Format the signature of a method, `public static function foo(int $a): Bar`
+///
+pub fn format_method_signature(method: &MethodData) -> String {
+    let mut signature = String::new();
+    if method.is_abstract {
+        signature.push_str("abstract ");
+    }
+    signature.push_str(format_visibility(&method.visibility));
+    if method.is_static {
+        signature.push_str(" static");
+    }
+    signature.push_str(" function ");
+    signature.push_str(&method.name.to_string());
+    signature.push('(');
+    signature.push_str(&format_arguments(&method.arguments).join(", "));
+    signature.push(')');
+    if let Some(return_type) = method.get_return_type() {
+        signature.push_str(": ");
+        signature.push_str(&return_type.to_string());
+    }
+    signature
+}
+
+///
+/// Format the signature of a function, `function foo(int $a): Bar`
+///
+pub fn format_function_signature(function: &FunctionData) -> String {
+    let name = function.name.to_string();
+    let mut signature = format!("function {}(", name.trim_start_matches('\\'));
+    signature.push_str(&format_arguments(&function.arguments).join(", "));
+    signature.push(')');
+    if let Some(return_type) = function.get_return_type() {
+        signature.push_str(": ");
+        signature.push_str(&return_type.to_string());
+    }
+    signature
+}
+
+///
+/// Format the declaration of a property or class constant, `protected static ?int $foo` or
+/// `public const int FOO`. Methods have more to them, see `format_method_signature`.
+///
+pub fn format_member_signature(member: &ClassMember) -> String {
+    let mut signature = format_visibility(&member.visibility).to_string();
+    match member.kind {
+        MemberKind::Constant => signature.push_str(" const"),
+        _ if member.is_static => signature.push_str(" static"),
+        _ => (),
+    }
+    if let Some(member_type) = &member.type_description {
+        signature.push(' ');
+        signature.push_str(member_type);
+    }
+    signature.push(' ');
+    if member.kind == MemberKind::Property {
+        signature.push('$');
+    }
+    signature.push_str(&member.name);
+    signature
+}
+
+///
+/// Format the declaration of a class, interface or trait, `interface Foo\Bar`
+///
+pub fn format_class_signature(class: &ClassType, fq_name: &FullyQualifiedName) -> Option<String> {
+    let keyword = match class {
+        ClassType::Class(_) => "class",
+        ClassType::Interface(_) => "interface",
+        ClassType::Trait(_) => "trait",
+        ClassType::None => return None,
+    };
+    Some(format!(
+        "{} {}",
+        keyword,
+        fq_name.to_string().trim_start_matches('\\')
+    ))
+}
+
+///
+/// Format the declaration of a global constant, `const int Foo\BAR`
+///
+pub fn format_constant_signature(constant: &ConstantData, fq_name: &FullyQualifiedName) -> String {
+    let constant_type = constant
+        .get_utype()
+        .map(|t| format!("{} ", t))
+        .unwrap_or_default();
+    format!(
+        "const {}{}",
+        constant_type,
+        fq_name.to_string().trim_start_matches('\\')
+    )
+}
+
+///
+/// Render the PHPDoc of a function or method as markdown: the summary, the parameters
+/// and the return type, with a deprecation notice first if there is one
+///
+pub fn format_documentation(
+    description: &str,
+    arguments: &[FunctionArgumentData],
+    return_description: Option<String>,
+    deprecated: Option<&String>,
+) -> String {
+    let mut parts = vec![];
+    if let Some(deprecated) = deprecated {
+        if deprecated.is_empty() {
+            parts.push("**@deprecated**".to_string());
+        } else {
+            parts.push(format!("**@deprecated** {}", deprecated));
+        }
+    }
+    let description = description.trim();
+    if !description.is_empty() {
+        parts.push(description.to_string());
+    }
+
+    let mut tags = vec![];
+    for argument in arguments {
+        let mut line = format!("*@param* `{}`", format_argument(argument));
+        if let Some(desc) = &argument.phpdoc_description {
+            line.push_str(" — ");
+            line.push_str(desc);
+        }
+        tags.push(line);
+    }
+    if let Some(return_description) = return_description {
+        tags.push(format!("*@return* `{}`", return_description));
+    }
+    if !tags.is_empty() {
+        parts.push(tags.join("  \n"));
+    }
+    parts.join("\n\n")
+}
+
+pub fn format_method_documentation(method: &MethodData) -> String {
+    format_documentation(
+        &method.description,
+        &method.arguments,
+        method.get_return_type().map(|t| t.to_string()),
+        method.deprecated.as_ref(),
+    )
+}
+
+pub fn format_function_documentation(function: &FunctionData) -> String {
+    format_documentation(
+        &function.description,
+        &function.arguments,
+        function.get_return_type().map(|t| t.to_string()),
+        function.deprecated.as_ref(),
+    )
+}
+
+pub fn format_member_documentation(member: &ClassMember) -> String {
+    format_documentation(&member.description, &[], None, member.deprecated.as_ref())
+}
+
+pub fn format_class_documentation(class: &ClassType) -> String {
+    let (description, deprecated) = match class {
+        ClassType::Class(c) => (&c.description, c.deprecated.as_ref()),
+        ClassType::Interface(i) => (&i.description, i.deprecated.as_ref()),
+        ClassType::Trait(t) => (&t.description, t.deprecated.as_ref()),
+        ClassType::None => return String::new(),
+    };
+    format_documentation(description, &[], None, deprecated)
+}
+
+pub fn format_constant_documentation(constant: &ConstantData) -> String {
+    format_documentation(
+        &constant.description,
+        &[],
+        None,
+        constant.deprecated.as_ref(),
+    )
+}
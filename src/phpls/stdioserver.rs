@@ -1,4 +1,6 @@
 use crate::phpls::instance::PHPLanguageServerInstance;
+use crate::phpls::request_handler::PHPRequestHandler;
+use crate::phpls::request_tracker::RequestTracker;
 use rust_lsp::lsp::LSPEndpoint;
 use std::io::stdin;
 use std::io::stdout;
@@ -17,8 +19,13 @@ impl PHPStdIOLanguageServer {
 
         let server_handler = PHPLanguageServerInstance::new(endpoint.clone());
 
-        let mut input = BufReader::new(stdin());
-        LSPEndpoint::run_server_from_input(&mut input, endpoint, server_handler);
+        let tracker = RequestTracker::new();
+        let mut input = tracker.read_ahead(BufReader::new(stdin()));
+        LSPEndpoint::run_endpoint_loop(
+            &mut input,
+            endpoint,
+            Box::new(PHPRequestHandler::new(server_handler, tracker)),
+        );
         Ok(())
     }
 }
@@ -1,4 +1,6 @@
 use crate::phpls::instance::PHPLanguageServerInstance;
+use crate::phpls::request_handler::PHPRequestHandler;
+use crate::phpls::request_tracker::RequestTracker;
 use std::io::BufReader;
 use std::net::TcpStream;
 use std::net::TcpListener;
@@ -21,8 +23,10 @@ impl PHPTCPLanguageServer {
         
         let ls = PHPLanguageServerInstance::new(endpoint.clone());
         
-        let mut input = BufReader::new(stream);
-        LSPEndpoint::run_server_from_input(&mut input, endpoint, ls);
+        let tracker = RequestTracker::new();
+        let mut input = tracker.read_ahead(BufReader::new(stream));
+        let handler = PHPRequestHandler::new(ls, tracker);
+        LSPEndpoint::run_endpoint_loop(&mut input, endpoint, Box::new(handler));
     }
 
     pub fn run_listener(&self, listener: TcpListener) {
@@ -0,0 +1,215 @@
+use phpanalyzer::{
+    symboldata::{class::ClassType, SymbolData},
+    symbols::FullyQualifiedName,
+};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{
+        request::Request, PartialResultParams, Range, SymbolKind, SymbolTag,
+        TextDocumentPositionParams, WorkDoneProgressParams,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::codetree::{
+    class_hierarchy::ClassHierarchy,
+    references::{get_reference_at_cursor, ReferenceKey},
+};
+
+use super::{
+    instance::PHPLanguageServerInstance, locations::LocationConverter, members::get_class_position,
+};
+
+// The type hierarchy requests came with version 3.17 of the protocol, which is newer than
+// the lsp-types we're on, so we bring our own definitions
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<SymbolTag>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: Url,
+    pub range: Range,
+    pub selection_range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyPrepareParams {
+    #[serde(flatten)]
+    pub text_document_position_params: TextDocumentPositionParams,
+    #[serde(flatten)]
+    pub work_done_progress_params: WorkDoneProgressParams,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyParams {
+    pub item: TypeHierarchyItem,
+    #[serde(flatten)]
+    pub work_done_progress_params: WorkDoneProgressParams,
+    #[serde(flatten)]
+    pub partial_result_params: PartialResultParams,
+}
+
+pub enum TypeHierarchyPrepare {}
+
+impl Request for TypeHierarchyPrepare {
+    type Params = TypeHierarchyPrepareParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "textDocument/prepareTypeHierarchy";
+}
+
+pub enum TypeHierarchySupertypes {}
+
+impl Request for TypeHierarchySupertypes {
+    type Params = TypeHierarchyParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/supertypes";
+}
+
+pub enum TypeHierarchySubtypes {}
+
+impl Request for TypeHierarchySubtypes {
+    type Params = TypeHierarchyParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/subtypes";
+}
+
+pub fn prepare_type_hierarchy(
+    phpls: &PHPLanguageServerInstance,
+    params: TypeHierarchyPrepareParams,
+    completable: MethodCompletable<Option<Vec<TypeHierarchyItem>>, ()>,
+) {
+    let position = params.text_document_position_params;
+    let uri = position.text_document.uri.clone();
+    let source = phpls
+        .get_file_for_uri(&uri)
+        .and_then(|f| f.get_contents().ok())
+        .unwrap_or_default();
+
+    let fq_name = match phpls.at_position(
+        position,
+        Box::new(move |node, state, path| {
+            match get_reference_at_cursor(&node, path, state, &source) {
+                Some((ReferenceKey::Class(fq_name), _)) => Some(fq_name),
+                _ => None,
+            }
+        }),
+    ) {
+        Ok((_, Some(Some(fq_name)))) => fq_name,
+        Ok(_) => {
+            completable.complete(Ok(None));
+            return;
+        }
+        Err(_) if phpls.is_request_cancelled() => {
+            completable.complete(Err(PHPLanguageServerInstance::request_cancelled(())));
+            return;
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let symbol_data = match phpls.get_symbol_data_for_uri(&uri) {
+        Some(symbol_data) => symbol_data,
+        None => {
+            completable.complete(Ok(None));
+            return;
+        }
+    };
+    let mut converter = LocationConverter::new(phpls);
+    let item = get_type_hierarchy_item(&mut converter, &symbol_data, &fq_name);
+    completable.complete(Ok(item.map(|i| vec![i])))
+}
+
+pub fn type_hierarchy_supertypes(
+    phpls: &PHPLanguageServerInstance,
+    params: TypeHierarchyParams,
+    completable: MethodCompletable<Option<Vec<TypeHierarchyItem>>, ()>,
+) {
+    completable.complete(Ok(get_related_items(phpls, &params.item, |hierarchy, fq_name| {
+        hierarchy.get_parents(fq_name)
+    })))
+}
+
+pub fn type_hierarchy_subtypes(
+    phpls: &PHPLanguageServerInstance,
+    params: TypeHierarchyParams,
+    completable: MethodCompletable<Option<Vec<TypeHierarchyItem>>, ()>,
+) {
+    completable.complete(Ok(get_related_items(phpls, &params.item, |hierarchy, fq_name| {
+        hierarchy.get_children(fq_name)
+    })))
+}
+
+fn get_related_items<F>(
+    phpls: &PHPLanguageServerInstance,
+    item: &TypeHierarchyItem,
+    related: F,
+) -> Option<Vec<TypeHierarchyItem>>
+where
+    F: Fn(&ClassHierarchy, &FullyQualifiedName) -> Vec<FullyQualifiedName>,
+{
+    let fq_name = item
+        .data
+        .as_ref()
+        .and_then(|d| d.get("fq_name"))
+        .and_then(|n| n.as_str())
+        .map(FullyQualifiedName::from)?;
+    let symbol_data = phpls.get_symbol_data_for_uri(&item.uri)?;
+    let hierarchy = phpls.get_class_hierarchy_for_uri(&item.uri)?;
+
+    let mut converter = LocationConverter::new(phpls);
+    Some(
+        related(&hierarchy, &fq_name)
+            .iter()
+            .filter_map(|c| get_type_hierarchy_item(&mut converter, &symbol_data, c))
+            .collect(),
+    )
+}
+
+///
+/// Describe a class, interface or trait. Classes we don't have the declaration of are left
+/// out, as the protocol requires a location.
+///
+fn get_type_hierarchy_item(
+    converter: &mut LocationConverter,
+    symbol_data: &SymbolData,
+    fq_name: &FullyQualifiedName,
+) -> Option<TypeHierarchyItem> {
+    let kind = match &*symbol_data.get_class(fq_name)?.read().unwrap() {
+        ClassType::Class(_) => SymbolKind::Class,
+        ClassType::Interface(_) => SymbolKind::Interface,
+        // The protocol has no kind for traits
+        ClassType::Trait(_) => SymbolKind::Class,
+        ClassType::None => return None,
+    };
+    let location = converter.location(get_class_position(symbol_data, fq_name)?);
+
+    let full_name = fq_name.to_string();
+    let full_name = full_name.trim_start_matches('\\');
+    let (namespace, name) = match full_name.rfind('\\') {
+        Some(idx) => (Some(full_name[..idx].to_string()), &full_name[idx + 1..]),
+        None => (None, full_name),
+    };
+    Some(TypeHierarchyItem {
+        name: name.to_string(),
+        kind,
+        tags: None,
+        detail: namespace,
+        uri: location.uri,
+        range: location.range,
+        selection_range: location.range,
+        data: Some(serde_json::json!({ "fq_name": fq_name.to_string() })),
+    })
+}
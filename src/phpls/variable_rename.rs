@@ -0,0 +1,162 @@
+use phpanalyzer::autonodes::any::AnyNodeRef;
+use regex::Regex;
+use rust_lsp::lsp_types::Range;
+
+use super::locations::offset_to_position;
+use super::variables::{
+    find_linked_variable_scope, get_compact_occurrences, get_linked_variable_occurrences,
+    get_local_variable_at_cursor,
+};
+
+/// Variables PHP gives a meaning of its own, which can't be renamed
+const RESERVED_VARIABLES: &[&str] = &[
+    "this", "GLOBALS", "_GET", "_POST", "_COOKIE", "_FILES", "_SERVER", "_ENV", "_REQUEST",
+    "_SESSION",
+];
+
+///
+/// Everything that has to change to rename a local variable or parameter
+///
+#[derive(Clone, Debug)]
+pub struct VariableRename {
+    /// The name without `$`
+    pub name: String,
+    /// The name at the cursor, without `$`
+    pub range: Range,
+    /// The ranges to replace with the new name, none of them including `$`
+    pub ranges: Vec<Range>,
+    /// Whether the new name is already used by another variable in the same scope
+    pub conflicts: bool,
+}
+
+///
+/// Callback for `at_position`. If the cursor is on a local variable, find all the places in its
+/// function that refer to it: the variable itself, captures in closures and their bodies,
+/// strings in `compact()`, and `@param`/`@var` tags in docblocks.
+///
+pub fn get_variable_rename(
+    node: &AnyNodeRef,
+    path: &Vec<AnyNodeRef>,
+    source: &[u8],
+    new_name: Option<&str>,
+) -> Option<VariableRename> {
+    let variable = get_local_variable_at_cursor(node, path, source)?;
+    if RESERVED_VARIABLES.contains(&variable.name.as_str()) {
+        return None;
+    }
+    let scope = find_linked_variable_scope(&variable.path, &variable.name, source)?;
+
+    let mut offsets: Vec<(usize, usize)> =
+        get_linked_variable_occurrences(&scope, &variable.name, source)
+            .iter()
+            // Skip the `$`
+            .map(|o| (o.range.start_byte + 1, o.range.end_byte))
+            .collect();
+    offsets.extend(get_compact_occurrences(&scope, &variable.name, source, true));
+    offsets.extend(get_docblock_occurrences(&scope, &variable.name, source));
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    let conflicts = match new_name {
+        Some(new_name) if new_name != variable.name => {
+            !get_linked_variable_occurrences(&scope, new_name, source).is_empty()
+        }
+        _ => false,
+    };
+
+    let text = String::from_utf8_lossy(source);
+    let start = variable.range.start_byte + 1;
+    Some(VariableRename {
+        range: byte_range_to_range(&text, start, start + variable.name.len()),
+        ranges: offsets
+            .into_iter()
+            .map(|(start, end)| byte_range_to_range(&text, start, end))
+            .collect(),
+        name: variable.name,
+        conflicts,
+    })
+}
+
+///
+/// Find `$name` in `@param` and `@var` tags of the docblock in front of the function, and of
+/// the docblocks inside it. Functions, methods and classes declared inside the scope have
+/// variables of their own, so their docblocks are left out.
+///
+fn get_docblock_occurrences(scope: &AnyNodeRef, name: &str, source: &[u8]) -> Vec<(usize, usize)> {
+    let text = String::from_utf8_lossy(source);
+    let range = scope.range();
+    let docblock = Regex::new(r"(?s)/\*\*.*?\*/").unwrap();
+    let tag = Regex::new(&format!(
+        r"@[A-Za-z-]*(?:param|var)\b[^\n]*?\$({})\b",
+        regex::escape(name)
+    ))
+    .unwrap();
+
+    let mut blocks = vec![];
+    // The docblock of a function is right in front of it
+    if !matches!(scope, AnyNodeRef::Program(_)) {
+        blocks.extend(get_docblock_before(&text, range.start_byte));
+    }
+    let nested = get_nested_declarations(scope, &text);
+    let end = range.end_byte.min(text.len());
+    for block in docblock.find_iter(&text[range.start_byte.min(end)..end]) {
+        let start = range.start_byte + block.start();
+        if nested.iter().any(|(s, e)| *s <= start && start < *e) {
+            continue;
+        }
+        blocks.push((start, range.start_byte + block.end()));
+    }
+
+    let mut occurrences = vec![];
+    for (start, end) in blocks {
+        for captures in tag.captures_iter(&text[start..end]) {
+            if let Some(m) = captures.get(1) {
+                occurrences.push((start + m.start(), start + m.end()));
+            }
+        }
+    }
+    occurrences
+}
+
+///
+/// The docblock ending right before `offset`, if any
+///
+fn get_docblock_before(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let before = text[..offset.min(text.len())].trim_end();
+    if !before.ends_with("*/") {
+        return None;
+    }
+    before.rfind("/**").map(|start| (start, before.len()))
+}
+
+///
+/// The byte ranges of the functions, methods and classes declared inside `scope`, including
+/// the docblocks in front of them
+///
+fn get_nested_declarations(scope: &AnyNodeRef, text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut stack = scope.children_any();
+    while let Some(node) = stack.pop() {
+        match node {
+            AnyNodeRef::FunctionDefinition(_)
+            | AnyNodeRef::MethodDeclaration(_)
+            | AnyNodeRef::ClassDeclaration(_)
+            | AnyNodeRef::InterfaceDeclaration(_)
+            | AnyNodeRef::TraitDeclaration(_) => {
+                let range = node.range();
+                let start = get_docblock_before(text, range.start_byte)
+                    .map_or(range.start_byte, |(start, _)| start);
+                ranges.push((start, range.end_byte));
+            }
+            _ => stack.extend(node.children_any()),
+        }
+    }
+    ranges
+}
+
+fn byte_range_to_range(text: &str, start: usize, end: usize) -> Range {
+    Range {
+        start: offset_to_position(text, start),
+        end: offset_to_position(text, end),
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+
+use phpanalyzer::{analysis::state::AnalysisState, autonodes::any::AnyNodeRef, Range};
+
+use crate::codetree::references::node_text;
+
+///
+/// A place where a local variable is used
+///
+#[derive(Clone, Debug)]
+pub struct VariableOccurrence {
+    /// Range of the variable, including the `$`
+    pub range: Range,
+    pub is_write: bool,
+}
+
+///
+/// A local variable found at the cursor
+///
+#[derive(Clone)]
+pub struct LocalVariable {
+    /// The name without `$`
+    pub name: String,
+    pub range: Range,
+    /// The nodes from the root down to, but not including, the variable
+    pub path: Vec<AnyNodeRef>,
+}
+
+///
+/// Check if the cursor of a position callback is on a local variable or parameter
+///
+pub fn get_local_variable_at_cursor(
+    node: &AnyNodeRef,
+    path: &Vec<AnyNodeRef>,
+    source: &[u8],
+) -> Option<LocalVariable> {
+    let mut nodes = path.clone();
+    nodes.push(node.clone());
+    let var_idx = nodes
+        .iter()
+        .rposition(|n| matches!(n, AnyNodeRef::VariableName(_)))?;
+    // The variable in a property declaration or static property access is the property
+    match var_idx.checked_sub(1).and_then(|i| nodes.get(i)) {
+        Some(AnyNodeRef::PropertyElement(_)) | Some(AnyNodeRef::ScopedPropertyAccessExpression(_)) => {
+            return None
+        }
+        _ => (),
+    }
+    let range = nodes[var_idx].range();
+    let name = node_text(&range, source).trim_start_matches('$').to_string();
+    nodes.truncate(var_idx);
+    Some(LocalVariable {
+        name,
+        range,
+        path: nodes,
+    })
+}
+
+///
+/// The local variables the analyzer knows of at the node of a position callback, by name
+/// without `$`, with their type if known
+///
+pub fn get_scope_variables(state: &mut AnalysisState) -> BTreeMap<String, Option<String>> {
+    let scope = state.current_scope();
+    let scope = scope.read().unwrap();
+    scope
+        .vars
+        .iter()
+        .map(|(name, var_data)| {
+            let name = name.to_string().trim_start_matches('$').to_string();
+            let var_type = var_data.read().unwrap().get_utype().map(|t| t.to_string());
+            (name, var_type)
+        })
+        .collect()
+}
+
+///
+/// Find the function, method or closure a local variable at the end of `path` belongs to
+///
+pub fn find_variable_scope(path: &Vec<AnyNodeRef>) -> Option<AnyNodeRef> {
+    path.iter().rev().find(|n| is_scope(n)).cloned()
+}
+
+///
+/// Like `find_variable_scope`, but closures capturing `name` with `use` are skipped, as their
+/// copy of the variable has to keep the same name as the captured one
+///
+pub fn find_linked_variable_scope(
+    path: &Vec<AnyNodeRef>,
+    name: &str,
+    source: &[u8],
+) -> Option<AnyNodeRef> {
+    path.iter()
+        .rev()
+        .filter(|n| is_scope(n))
+        .find(|n| {
+            !matches!(n, AnyNodeRef::AnonymousFunctionCreationExpression(_))
+                || !captures_variable(n, name, source)
+        })
+        .cloned()
+}
+
+fn is_scope(node: &AnyNodeRef) -> bool {
+    matches!(
+        node,
+        AnyNodeRef::MethodDeclaration(_)
+            | AnyNodeRef::FunctionDefinition(_)
+            | AnyNodeRef::AnonymousFunctionCreationExpression(_)
+            | AnyNodeRef::Program(_)
+    )
+}
+
+///
+/// Find all uses of the variable `name` (without `$`) inside `scope`. Arrow functions see the
+/// variables of the enclosing scope, closures only the ones they capture with `use`, and
+/// nested functions and classes have their own scope.
+///
+pub fn get_variable_occurrences(
+    scope: &AnyNodeRef,
+    name: &str,
+    source: &[u8],
+) -> Vec<VariableOccurrence> {
+    collect_occurrences(scope, name, source, false)
+}
+
+///
+/// Like `get_variable_occurrences`, but including the uses inside the closures capturing the
+/// variable. This is what has to change together when renaming it.
+///
+pub fn get_linked_variable_occurrences(
+    scope: &AnyNodeRef,
+    name: &str,
+    source: &[u8],
+) -> Vec<VariableOccurrence> {
+    collect_occurrences(scope, name, source, true)
+}
+
+fn collect_occurrences(
+    scope: &AnyNodeRef,
+    name: &str,
+    source: &[u8],
+    into_closures: bool,
+) -> Vec<VariableOccurrence> {
+    let mut occurrences = vec![];
+    visit_scope(scope, name, source, into_closures, |node, parent| {
+        if let AnyNodeRef::VariableName(_) = node {
+            let range = node.range();
+            if node_text(&range, source).trim_start_matches('$') == name {
+                occurrences.push(VariableOccurrence {
+                    range,
+                    is_write: is_write_position(node, parent),
+                });
+            }
+        }
+    });
+    occurrences.sort_by_key(|o| o.range.start_byte);
+    occurrences
+}
+
+///
+/// Get the ranges of the strings naming `name` in calls to `compact()` inside `scope`. The
+/// ranges do not include the quotes.
+///
+pub fn get_compact_occurrences(
+    scope: &AnyNodeRef,
+    name: &str,
+    source: &[u8],
+    into_closures: bool,
+) -> Vec<(usize, usize)> {
+    let mut occurrences = vec![];
+    visit_scope(scope, name, source, into_closures, |node, _| {
+        let call = match node {
+            AnyNodeRef::FunctionCallExpression(fc) => fc,
+            _ => return,
+        };
+        let function = node_text(&call.function.range(), source);
+        if !function.trim_start_matches('\\').eq_ignore_ascii_case("compact") {
+            return;
+        }
+        let range = node.range();
+        let text = node_text(&range, source);
+        for quote in &["'", "\""] {
+            let needle = format!("{}{}{}", quote, name, quote);
+            for (idx, _) in text.match_indices(&needle) {
+                let start = range.start_byte + idx + 1;
+                occurrences.push((start, start + name.len()));
+            }
+        }
+    });
+    occurrences
+}
+
+///
+/// Call `visit` with every node belonging to the variable scope of `scope`, and its parent
+///
+fn visit_scope<F>(scope: &AnyNodeRef, name: &str, source: &[u8], into_closures: bool, mut visit: F)
+where
+    F: FnMut(&AnyNodeRef, Option<&AnyNodeRef>),
+{
+    let mut stack: Vec<(AnyNodeRef, Option<AnyNodeRef>)> = scope
+        .children_any()
+        .into_iter()
+        .map(|c| (c, Some(scope.clone())))
+        .collect();
+
+    while let Some((node, parent)) = stack.pop() {
+        visit(&node, parent.as_ref());
+        match &node {
+            AnyNodeRef::VariableName(_) => continue,
+            AnyNodeRef::FunctionDefinition(_)
+            | AnyNodeRef::MethodDeclaration(_)
+            | AnyNodeRef::ClassDeclaration(_)
+            | AnyNodeRef::InterfaceDeclaration(_)
+            | AnyNodeRef::TraitDeclaration(_) => continue,
+            AnyNodeRef::AnonymousFunctionCreationExpression(_) => {
+                if !captures_variable(&node, name, source) {
+                    continue;
+                }
+                // The capture in `use (...)` is ours. The body of the closure has its own
+                // scope with a copy of the variable, which is only linked to ours by name.
+                for child in node.children_any() {
+                    if into_closures || matches!(child, AnyNodeRef::AnonymousFunctionUseClause(_)) {
+                        stack.push((child, Some(node.clone())));
+                    }
+                }
+                continue;
+            }
+            _ => (),
+        }
+        for child in node.children_any() {
+            stack.push((child, Some(node.clone())));
+        }
+    }
+}
+
+///
+/// Check if a closure captures `name` in its `use (...)` clause
+///
+pub fn captures_variable(closure: &AnyNodeRef, name: &str, source: &[u8]) -> bool {
+    closure.children_any().iter().any(|child| match child {
+        AnyNodeRef::AnonymousFunctionUseClause(_) => child.children_any().iter().any(|var| {
+            matches!(var, AnyNodeRef::VariableName(_))
+                && node_text(&var.range(), source).trim_start_matches('$') == name
+        }),
+        _ => false,
+    })
+}
+
+///
+/// Check if the variable `node` is assigned to, rather than read from
+///
+fn is_write_position(node: &AnyNodeRef, parent: Option<&AnyNodeRef>) -> bool {
+    let parent = if let Some(parent) = parent {
+        parent
+    } else {
+        return false;
+    };
+    let is_first_child = parent
+        .children_any()
+        .first()
+        .map(|c| c.range() == node.range())
+        .unwrap_or(false);
+
+    match parent {
+        AnyNodeRef::AssignmentExpression(_)
+        | AnyNodeRef::AugmentedAssignmentExpression(_)
+        | AnyNodeRef::ReferenceAssignmentExpression(_) => is_first_child,
+        AnyNodeRef::ForeachStatement(_) => !is_first_child,
+        AnyNodeRef::SimpleParameter(_)
+        | AnyNodeRef::VariadicParameter(_)
+        | AnyNodeRef::PropertyPromotionParameter(_)
+        | AnyNodeRef::StaticVariableDeclaration(_)
+        | AnyNodeRef::GlobalDeclaration(_)
+        | AnyNodeRef::ListLiteral(_)
+        | AnyNodeRef::CatchClause(_) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use phpanalyzer::symboldata::{class::ClassType, FileLocation, SymbolData};
+use rust_lsp::{
+    jsonrpc::MethodCompletable,
+    lsp_types::{SymbolInformation, SymbolKind, WorkspaceSymbolParams},
+};
+
+use crate::codetree::symbol_index::{IndexedSymbolKind, SymbolIndex};
+
+use super::{
+    instance::PHPLanguageServerInstance,
+    locations::LocationConverter,
+    members::{get_declared_members, MemberKind},
+};
+
+/// Editors show the first few hits anyway, and sending every match in a large
+/// workspace makes the response slow to produce and to render
+const MAX_WORKSPACE_SYMBOLS: usize = 100;
+
+struct Candidate {
+    score: i64,
+    name: String,
+    kind: SymbolKind,
+    /// Converted for the few candidates returned only, as it takes reading the file
+    location: FileLocation,
+    container_name: Option<String>,
+}
+
+pub fn workspace_symbols(
+    phpls: &PHPLanguageServerInstance,
+    params: WorkspaceSymbolParams,
+    completable: MethodCompletable<Vec<SymbolInformation>, ()>,
+) {
+    let query = params.query.trim().trim_start_matches('\\').to_string();
+
+    if query.is_empty() {
+        // Everything matches, so there is nothing to rank. The first classes, functions and
+        // constants in the index will do, without going through all the members.
+        let mut candidates: Vec<Candidate> = vec![];
+        for codetree in phpls.get_codetrees() {
+            collect_top_level_symbols(
+                &codetree.get_symbol_index(),
+                &query,
+                MAX_WORKSPACE_SYMBOLS,
+                &mut candidates,
+            );
+        }
+        let mut converter = LocationConverter::new(phpls);
+        let symbols = candidates
+            .into_iter()
+            .map(|c| new_symbol_information(&mut converter, c))
+            .collect();
+        completable.complete(Ok(symbols));
+        return;
+    }
+
+    let mut candidates: Vec<Candidate> = vec![];
+    for codetree in phpls.get_codetrees() {
+        collect_top_level_symbols(
+            &codetree.get_symbol_index(),
+            &query,
+            usize::MAX,
+            &mut candidates,
+        );
+        if let Some(symbol_data) = codetree.get_symbol_data() {
+            collect_member_symbols(&symbol_data, &query, &mut candidates);
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| a.name.len().cmp(&b.name.len()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    let mut converter = LocationConverter::new(phpls);
+    let symbols = candidates
+        .into_iter()
+        .take(MAX_WORKSPACE_SYMBOLS)
+        .map(|c| new_symbol_information(&mut converter, c))
+        .collect();
+    completable.complete(Ok(symbols));
+}
+
+///
+/// Classes, functions and constants. A query containing a backslash is matched against
+/// the fully qualified name, otherwise against the short name. Stops when there are
+/// `limit` candidates.
+///
+fn collect_top_level_symbols(
+    index: &Arc<SymbolIndex>,
+    query: &str,
+    limit: usize,
+    candidates: &mut Vec<Candidate>,
+) {
+    let qualified = query.contains('\\');
+    for symbol in index.iter() {
+        if candidates.len() >= limit {
+            break;
+        }
+        let location = if let Some(location) = &symbol.location {
+            location
+        } else {
+            continue;
+        };
+        let haystack = if qualified { &symbol.fq_name } else { &symbol.name };
+        let score = if let Some(score) = fuzzy_score(query, haystack) {
+            score
+        } else {
+            continue;
+        };
+        let kind = match symbol.kind {
+            IndexedSymbolKind::Class => SymbolKind::Class,
+            IndexedSymbolKind::Interface => SymbolKind::Interface,
+            IndexedSymbolKind::Trait => SymbolKind::Class,
+            IndexedSymbolKind::Function => SymbolKind::Function,
+            IndexedSymbolKind::Constant => SymbolKind::Constant,
+        };
+        let namespace = symbol.namespace();
+        candidates.push(Candidate {
+            score,
+            name: symbol.name.clone(),
+            kind,
+            location: location.clone(),
+            container_name: if namespace.is_empty() {
+                None
+            } else {
+                Some(namespace.to_string())
+            },
+        });
+    }
+}
+
+///
+/// Methods, properties and class constants, with the fully qualified class as container.
+/// A query like `UserService::find` or `UserService->find` also matches the class.
+///
+fn collect_member_symbols(symbol_data: &SymbolData, query: &str, candidates: &mut Vec<Candidate>) {
+    let (class_query, member_query) = match query.find("::").or_else(|| query.find("->")) {
+        Some(idx) => (Some(&query[..idx]), &query[idx + 2..]),
+        None => (None, query),
+    };
+    let member_query = member_query.trim_start_matches('$');
+
+    let classes = symbol_data.classes.read().unwrap();
+    for (fq_name, class) in classes.iter() {
+        let class = class.read().unwrap();
+        if let ClassType::None = &*class {
+            continue;
+        }
+        let class_name = fq_name.to_string().trim_start_matches('\\').to_string();
+        let class_score = match class_query {
+            Some(class_query) => {
+                let short_name = class_name.rsplit('\\').next().unwrap_or("");
+                let haystack = if class_query.contains('\\') {
+                    &class_name
+                } else {
+                    short_name
+                };
+                match fuzzy_score(class_query, haystack) {
+                    Some(score) => score,
+                    None => continue,
+                }
+            }
+            None => 0,
+        };
+
+        for member in get_declared_members(&class, fq_name) {
+            let location = if let Some(location) = member.position {
+                location
+            } else {
+                continue;
+            };
+            let score = if let Some(score) = fuzzy_score(member_query, &member.name) {
+                score
+            } else {
+                continue;
+            };
+            let (name, kind) = match member.kind {
+                MemberKind::Method if member.name.eq_ignore_ascii_case("__construct") => {
+                    (member.name, SymbolKind::Constructor)
+                }
+                MemberKind::Method => (member.name, SymbolKind::Method),
+                MemberKind::Property => (format!("${}", member.name), SymbolKind::Property),
+                MemberKind::Constant => (member.name, SymbolKind::Constant),
+            };
+            candidates.push(Candidate {
+                // Rank members slightly below top level symbols matching as well
+                score: score + class_score - 1,
+                name,
+                kind,
+                location,
+                container_name: Some(class_name.clone()),
+            });
+        }
+    }
+}
+
+#[allow(deprecated)]
+fn new_symbol_information(
+    converter: &mut LocationConverter,
+    candidate: Candidate,
+) -> SymbolInformation {
+    SymbolInformation {
+        name: candidate.name,
+        kind: candidate.kind,
+        tags: None,
+        deprecated: None,
+        location: converter.location(candidate.location),
+        container_name: candidate.container_name,
+    }
+}
+
+///
+/// Score how well `query` matches `candidate`, or `None` if it does not match at all.
+/// All characters of the query have to occur in order in the candidate, ignoring case.
+/// Matches at the start of a word (`UsrSvc` against `UserService`), consecutive matches
+/// and matches with the same case score higher, gaps score lower.
+///
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    let candidate_chars: Vec<char> = candidate.chars().collect();
+    let query_chars: Vec<char> = query.chars().collect();
+    let mut score: i64 = 0;
+    let mut last_match: Option<usize> = None;
+    let mut pos = 0;
+
+    for (qi, &q) in query_chars.iter().enumerate() {
+        let q_lower = q.to_ascii_lowercase();
+        let next_any = (pos..candidate_chars.len())
+            .find(|&i| candidate_chars[i].to_ascii_lowercase() == q_lower)?;
+        // Prefer the next word start matching this character, as long as the rest of the
+        // query can still be matched after it
+        let next_word_start = (next_any..candidate_chars.len()).find(|&i| {
+            candidate_chars[i].to_ascii_lowercase() == q_lower
+                && is_word_start(&candidate_chars, i)
+                && is_subsequence(&query_chars[qi + 1..], &candidate_chars[i + 1..])
+        });
+        let idx = match (last_match, next_word_start) {
+            // Keep going through a consecutive run instead of jumping to a word start
+            (Some(last), _) if next_any == last + 1 => next_any,
+            (_, Some(word_start)) => word_start,
+            _ => next_any,
+        };
+
+        score += 1;
+        if is_word_start(&candidate_chars, idx) {
+            score += 8;
+        }
+        if candidate_chars[idx] == q {
+            score += 1;
+        }
+        match last_match {
+            Some(last) if idx == last + 1 => score += 4,
+            Some(last) => score -= (idx - last - 1).min(8) as i64,
+            None => score -= idx.min(8) as i64,
+        }
+        last_match = Some(idx);
+        pos = idx + 1;
+    }
+
+    if candidate.eq_ignore_ascii_case(query) {
+        score += 100;
+    } else if candidate
+        .get(..query.len())
+        .map_or(false, |start| start.eq_ignore_ascii_case(query))
+    {
+        score += 20;
+    }
+    // Shorter candidates are closer matches
+    score -= (candidate_chars.len() - query_chars.len()).min(20) as i64 / 4;
+    Some(score)
+}
+
+fn is_word_start(chars: &[char], idx: usize) -> bool {
+    if idx == 0 {
+        return true;
+    }
+    let prev = chars[idx - 1];
+    let current = chars[idx];
+    prev == '_'
+        || prev == '\\'
+        || (prev.is_lowercase() && current.is_uppercase())
+        || (!prev.is_alphanumeric() && current.is_alphanumeric())
+}
+
+fn is_subsequence(needle: &[char], haystack: &[char]) -> bool {
+    let mut haystack = haystack.iter();
+    needle.iter().all(|n| {
+        haystack.any(|h| h.to_ascii_lowercase() == n.to_ascii_lowercase())
+    })
+}
@@ -1,3 +1,5 @@
+use crate::codetree::cancellation::CancellationToken;
+use crate::codetree::references::{ReferenceCollector, ReferenceIndex};
 use phpanalyzer::analysis::analyzer::Analyzer;
 use phpanalyzer::analysis::state::{AnalysisState, LookingForNode};
 use phpanalyzer::autonodes::any::AnyNodeRef;
@@ -31,9 +33,13 @@ use crate::php::type_analysis::NodeType;
 use phpanalyzer::description::NodeDescription;
 use phpanalyzer::Point;
 
+pub const REQUEST_CANCELLED: &str = "Request cancelled";
+
 #[derive(Clone)]
 pub struct PHPFile {
     pub fq_file_name: PathBuf,
+    /// Unsaved contents from the editor, used instead of what's on disk
+    overlay: Option<Arc<Vec<u8>>>,
     analyzed: Arc<RwLock<Option<Arc<Analyzer>>>>,
 }
 
@@ -41,6 +47,15 @@ impl PHPFile {
     pub fn new(fq_file_name: PathBuf) -> PHPFile {
         return PHPFile {
             fq_file_name: fq_file_name,
+            overlay: None,
+            analyzed: Arc::new(RwLock::new(None)),
+        };
+    }
+
+    pub fn new_with_overlay(fq_file_name: PathBuf, overlay: Option<Arc<Vec<u8>>>) -> PHPFile {
+        return PHPFile {
+            fq_file_name: fq_file_name,
+            overlay,
             analyzed: Arc::new(RwLock::new(None)),
         };
     }
@@ -50,6 +65,7 @@ impl PHPFile {
         line: usize,
         character: usize,
         symbol_data: Option<Arc<SymbolData>>,
+        cancel: &CancellationToken,
         callback: Box<
             dyn FnOnce(AnyNodeRef, &mut AnalysisState, &Vec<AnyNodeRef>) -> T_Result + Send + Sync,
         >,
@@ -71,11 +87,17 @@ impl PHPFile {
         phpanalyzer::native::register(&mut state);
 
         a.first_pass(&mut state, &void_emitter);
+        if cancel.is_cancelled() {
+            return Err(REQUEST_CANCELLED);
+        }
 
         // Pass 2-1
         let mut state = AnalysisState::new_with_symbols(symbol_data.clone());
         phpanalyzer::native::register(&mut state);
         a.second_pass(&mut state, &void_emitter);
+        if cancel.is_cancelled() {
+            return Err(REQUEST_CANCELLED);
+        }
 
         // Pass 2-2
         let mut state = AnalysisState::new_with_symbols(symbol_data);
@@ -83,12 +105,16 @@ impl PHPFile {
         let result_container = Arc::new(RwLock::new(None));
 
         let result_container_copy = result_container.clone();
+        let callback_cancel = cancel.clone();
         let looking_for = LookingForNode {
             pos: Point {
                 row: line,
                 column: character,
             },
             callback: Arc::new(RwLock::new(Some(Box::new(move |node, state, path| {
+                if callback_cancel.is_cancelled() {
+                    return;
+                }
                 let mut writable = result_container_copy.write().unwrap();
                 let result = callback(node, state, path);
                 *writable = Some(result);
@@ -96,6 +122,9 @@ impl PHPFile {
         };
         state.looking_for_node = Some(looking_for);
         a.third_pass(&mut state, &void_emitter);
+        if cancel.is_cancelled() {
+            return Err(REQUEST_CANCELLED);
+        }
 
         let mut writeable_result = result_container.write().unwrap();
 
@@ -162,10 +191,35 @@ impl PHPFile {
         Ok(None)
     }
 
+    ///
+    /// Get the current contents of the file, from the editor if it has unsaved changes
+    ///
+    pub fn get_contents(&self) -> std::io::Result<Vec<u8>> {
+        match &self.overlay {
+            Some(overlay) => Ok(overlay.to_vec()),
+            None => std::fs::read(&self.fq_file_name),
+        }
+    }
+
+    ///
+    /// Get a single line of the file, without the line ending
+    ///
+    pub fn get_line(&self, line: usize) -> Option<String> {
+        let contents = self.get_contents().ok()?;
+        String::from_utf8_lossy(&contents)
+            .lines()
+            .nth(line)
+            .map(|l| l.to_string())
+    }
+
     pub fn create_analyzer(&self) -> Analyzer {
         let fname = self.fq_file_name.clone();
+        let overlay = self.overlay.clone();
         Analyzer::new(
-            Box::new(move || std::fs::read(&fname)),
+            Box::new(move || match &overlay {
+                Some(overlay) => Ok(overlay.to_vec()),
+                None => std::fs::read(&fname),
+            }),
             self.fq_file_name.as_os_str().to_os_string(),
         )
     }
@@ -176,7 +230,7 @@ impl PHPFile {
         None
     }
 
-    fn get_analyzer(&self) -> Option<Arc<Analyzer>> {
+    pub fn get_analyzer(&self) -> Option<Arc<Analyzer>> {
         // Read lock scope
         {
             let state = self.analyzed.read().unwrap();
@@ -224,12 +278,35 @@ impl PHPFile {
     }
 
 
-    pub fn analyze_third_pass(&self, emitter: &dyn IssueEmitter, symbol_data: Arc<SymbolData>) {
+    pub fn analyze_third_pass(
+        &self,
+        emitter: &dyn IssueEmitter,
+        symbol_data: Arc<SymbolData>,
+        references: Option<&ReferenceIndex>,
+    ) {
         if let Some(analyzer) = self.get_analyzer() {
             let mut state = AnalysisState::new_with_symbols(symbol_data);
             state.pass = 2;
             state.filename = Some(self.fq_file_name.clone());
+
+            // References are recorded as the analyzer gets to them, while the state is
+            // still the one of the node
+            let collector = references.map(|_| {
+                Arc::new(RwLock::new(ReferenceCollector::new(
+                    self.get_contents().unwrap_or_default(),
+                    self.fq_file_name.as_os_str().to_os_string(),
+                )))
+            });
+            if let (Some(collector), Some(tree)) = (&collector, &analyzer.tree) {
+                state.looking_for_node =
+                    ReferenceCollector::look_for_references(collector.clone(), &tree.as_any());
+            }
             analyzer.third_pass(&mut state, emitter);
+            state.looking_for_node = None;
+
+            if let (Some(references), Some(collector)) = (references, collector) {
+                std::mem::take(&mut *collector.write().unwrap()).add_to(references);
+            }
         }
     }
 
@@ -245,7 +322,7 @@ impl PHPFile {
         phpanalyzer::native::register(&mut state);
         self.analyze_first_pass(emitter, symbol_data.clone());
         self.analyze_second_pass(emitter, symbol_data.clone());
-        self.analyze_third_pass(emitter, symbol_data.clone());
+        self.analyze_third_pass(emitter, symbol_data.clone(), None);
         Ok(symbol_data)
     }
 
@@ -318,6 +395,7 @@ impl PHPFile {
             line,
             charpos,
             symbol_data,
+            &CancellationToken::new(),
             Box::new(Self::get_symbols_at_callback),
         );
         // eprintln!("ETTERPA HAR VI: {:?}", result);